@@ -0,0 +1,67 @@
+//! This module contains the error types that are shared with the hardware dependent modules.
+
+/// A universal implementation specific error.
+///
+/// These error kinds can be used to signal implementation specific errors unrelated to the
+/// specific peripheral. This will be used for all sorts of connectivity problems, e.g. if an
+/// adapter to the peripheral is used or the target peripheral is connected to indirectly (like bus
+/// expanders) or an operating system is controlling the access and denying access.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ProgError {
+  /// Unspecified internal driver error
+  Internal,
+  /// Ran out of memory while trying to allocate required buffers
+  OutOfMemory,
+  /// Operation timed out, please retry
+  TimedOut,
+  /// The peripheral cannot work with the specified settings
+  InvalidConfiguration,
+  /// Tried to use peripheral without configuring it properly
+  NotConfigured,
+  /// Tried to setup a peripheral that is already configured
+  AlreadyConfigured,
+  /// Invalid action
+  PermissionDenied
+}
+
+/// A Serial specific error.
+///
+/// This error type contains errors specific to Serial peripherals. Also it has an `Impl` kind to pass
+/// through implementation specific errors occurring while trying to use a Serial peripheral.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SerialError {
+  /// The peripheral receive buffer was overrun.
+  Overrun,
+  /// Received data does not conform to the peripheral configuration.
+  /// Can be caused by a misconfigured device on either end of the serial line.
+  FrameFormat,
+  /// Parity check failed.
+  Parity,
+  /// Serial line is too noisy to read valid data.
+  Noise,
+  /// The transmit buffer is full.
+  BufferFull,
+  /// The baud rate cannot be generated from the peripheral clock within the tolerance.
+  UnreachableBaud,
+  /// Implementation specific error (shared across all peripheral specific error kinds).
+  Prog(ProgError)
+}
+
+/// A packet framing specific error.
+///
+/// This error type contains errors of the COBS and SLIP framing. Errors of the underlying serial
+/// port are passed through with the `Serial` kind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FramingError {
+  /// The packet does not fit into the buffer.
+  BufferTooSmall,
+  /// The frame contains an invalid code or escape sequence.
+  Malformed,
+  /// The CRC trailer does not match the packet.
+  Crc,
+  /// Error of the serial port.
+  Serial(SerialError)
+}
@@ -0,0 +1,451 @@
+//! This module contains the COBS and SLIP packet framing with optional CRC trailers.
+
+use crate::errors::FramingError;
+
+// SLIP special characters
+const END: u8 = 0xC0;
+const ESC: u8 = 0xDB;
+const ESC_END: u8 = 0xDC;
+const ESC_ESC: u8 = 0xDD;
+
+/// Represents the byte stuffing of a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+  /// Consistent overhead byte stuffing, packets are terminated by 0x00.
+  Cobs,
+  /// Serial line IP (RFC 1055), packets are enclosed in 0xC0.
+  Slip
+}
+
+/// Represents the checksum that is appended to the packet before it is encoded. The CRC is sent
+/// little-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crc {
+  None,
+  /// CRC-16/CCITT-FALSE (polynomial 0x1021, initial value 0xFFFF).
+  Crc16,
+  /// CRC-32 as used by Ethernet and zlib.
+  Crc32
+}
+
+impl Crc {
+  const fn len(&self) -> usize {
+    match self {
+      Crc::None => return 0,
+      Crc::Crc16 => return 2,
+      Crc::Crc32 => return 4
+    };
+  }
+}
+
+/// Decodes a stream of bytes into packets without allocating. A packet has to fit into the N
+/// bytes of the decoder, including the CRC trailer.
+///
+/// # Example
+///
+/// ```rust
+/// use rustuino_core::framing::*;
+///
+/// let mut frame = [0; max_encoded_len(Encoding::Cobs, Crc::Crc16, 3)];
+/// let len = encode(Encoding::Cobs, Crc::Crc16, &[0x01, 0x00, 0x02], &mut frame).unwrap();
+///
+/// let mut decoder: Decoder<64> = Decoder::new(Encoding::Cobs, Crc::Crc16);
+/// for byte in &frame[..len] {
+///   if let Some(Ok(_)) = decoder.feed(*byte) {
+///     assert_eq!(decoder.packet(), &[0x01, 0x00, 0x02]);
+///   }
+/// }
+/// ```
+pub struct Decoder<const N: usize> {
+  encoding: Encoding,
+  crc: Crc,
+  buffer: [u8; N],
+  len: usize,
+  packet_len: usize,
+  started: bool,
+  discard: bool,
+  // COBS: data bytes left in the block and whether the block ends with a zero
+  remaining: u8,
+  pending_zero: bool,
+  // SLIP: the last byte was ESC
+  escaped: bool
+}
+
+impl<const N: usize> Decoder<N> {
+  pub const fn new(encoding: Encoding, crc: Crc) -> Self {
+    return Self {
+      encoding,
+      crc,
+      buffer: [0; N],
+      len: 0,
+      packet_len: 0,
+      started: false,
+      discard: false,
+      remaining: 0,
+      pending_zero: false,
+      escaped: false
+    };
+  }
+
+  /// Processes one received byte. Returns the length of the packet when its end was reached and
+  /// an error if it was broken, the rest of a broken packet is skipped. Empty packets are ignored.
+  pub fn feed(&mut self, byte: u8) -> Option<Result<usize, FramingError>> {
+    let delimiter = match self.encoding {
+      Encoding::Cobs => 0x00,
+      Encoding::Slip => END
+    };
+
+    if byte == delimiter {
+      let result = if self.discard == true || self.started == false {None}
+      else if self.remaining > 0 || self.escaped == true {Some(Err(FramingError::Malformed))}
+      else {Some(self.finish())};
+
+      self.reset();
+      return result;
+    }
+
+    if self.discard == true {return None;}
+    self.started = true;
+
+    let result = match self.encoding {
+      Encoding::Cobs => self.feed_cobs(byte),
+      Encoding::Slip => self.feed_slip(byte)
+    };
+
+    if let Err(error) = result {
+      self.discard = true;
+      return Some(Err(error));
+    }
+
+    return None;
+  }
+
+  /// The last complete packet without the CRC trailer. It stays valid until the next byte is fed.
+  pub fn packet(&self) -> &[u8] {
+    return &self.buffer[..self.packet_len];
+  }
+
+  /// Drops a partially received packet.
+  pub fn reset(&mut self) {
+    self.len = 0;
+    self.started = false;
+    self.discard = false;
+    self.remaining = 0;
+    self.pending_zero = false;
+    self.escaped = false;
+  }
+
+  fn feed_cobs(&mut self, byte: u8) -> Result<(), FramingError> {
+    // Code byte of the next block
+    if self.remaining == 0 {
+      if self.pending_zero == true {
+        if let Err(error) = self.push(0) {return Err(error);}
+      }
+      self.remaining = byte - 1;
+      self.pending_zero = byte != 0xFF;
+      return Ok(());
+    }
+
+    self.remaining -= 1;
+    return self.push(byte);
+  }
+
+  fn feed_slip(&mut self, byte: u8) -> Result<(), FramingError> {
+    if self.escaped == true {
+      self.escaped = false;
+      match byte {
+        ESC_END => return self.push(END),
+        ESC_ESC => return self.push(ESC),
+        _ => return Err(FramingError::Malformed)
+      };
+    }
+
+    if byte == ESC {
+      self.escaped = true;
+      return Ok(());
+    }
+
+    return self.push(byte);
+  }
+
+  fn push(&mut self, byte: u8) -> Result<(), FramingError> {
+    if self.len == N {return Err(FramingError::BufferTooSmall);}
+
+    self.buffer[self.len] = byte;
+    self.len += 1;
+
+    return Ok(());
+  }
+
+  fn finish(&mut self) -> Result<usize, FramingError> {
+    if self.len < self.crc.len() {return Err(FramingError::Crc);}
+
+    let payload = self.len - self.crc.len();
+    let (data, trailer) = self.buffer[..self.len].split_at(payload);
+    let valid = match self.crc {
+      Crc::None => true,
+      Crc::Crc16 => crc16(data).to_le_bytes() == trailer,
+      Crc::Crc32 => crc32(data).to_le_bytes() == trailer
+    };
+    if valid == false {return Err(FramingError::Crc);}
+
+    self.packet_len = payload;
+    return Ok(payload);
+  }
+}
+
+
+// Public Functions ===============================================================================
+/// Encodes the packet with the CRC trailer into the buffer and returns the length of the frame,
+/// including the delimiters.
+pub fn encode(encoding: Encoding, crc: Crc, data: &[u8], buffer: &mut [u8]) -> Result<usize, FramingError> {
+  let mut len = 0;
+
+  let result = encode_with(encoding, crc, data, |byte| {
+    if len == buffer.len() {return Err(FramingError::BufferTooSmall);}
+    buffer[len] = byte;
+    len += 1;
+    return Ok(());
+  });
+
+  match result {
+    Ok(_) => return Ok(len),
+    Err(error) => return Err(error)
+  };
+}
+
+/// Worst case length of an encoded frame with a packet of the given length.
+pub const fn max_encoded_len(encoding: Encoding, crc: Crc, len: usize) -> usize {
+  let len = len + crc.len();
+
+  match encoding {
+    Encoding::Cobs => return len + len / 254 + 2,
+    Encoding::Slip => return 2 * len + 2
+  };
+}
+
+pub fn crc16(data: &[u8]) -> u16 {
+  let mut crc: u16 = 0xFFFF;
+
+  for byte in data {
+    crc ^= (*byte as u16) << 8;
+    for _ in 0..8 {
+      crc = if crc & 0x8000 > 0 {(crc << 1) ^ 0x1021} else {crc << 1};
+    }
+  }
+
+  return crc;
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+  let mut crc: u32 = 0xFFFFFFFF;
+
+  for byte in data {
+    crc ^= *byte as u32;
+    for _ in 0..8 {
+      crc = if crc & 1 > 0 {(crc >> 1) ^ 0xEDB88320} else {crc >> 1};
+    }
+  }
+
+  return !crc;
+}
+
+
+/// Passes the frame byte by byte to the sink, COBS only needs to hold back one block. Errors of
+/// the sink abort the encoding.
+pub fn encode_with<F: FnMut(u8) -> Result<(), FramingError>>(encoding: Encoding, crc: Crc, data: &[u8], mut emit: F) -> Result<(), FramingError> {
+  let mut trailer = [0; 4];
+  match crc {
+    Crc::None => (),
+    Crc::Crc16 => trailer[..2].copy_from_slice(&crc16(data).to_le_bytes()),
+    Crc::Crc32 => trailer.copy_from_slice(&crc32(data).to_le_bytes())
+  };
+  let bytes = data.iter().chain(trailer[..crc.len()].iter());
+
+  match encoding {
+    Encoding::Cobs => {
+      let mut block = [0; 254];
+      let mut len = 0;
+
+      for byte in bytes {
+        // A zero ends the block, full blocks end without a zero
+        if *byte == 0 {
+          if let Err(error) = emit_block(&block[..len], &mut emit) {return Err(error);}
+          len = 0;
+        }
+        else {
+          block[len] = *byte;
+          len += 1;
+          if len == block.len() {
+            if let Err(error) = emit_block(&block, &mut emit) {return Err(error);}
+            len = 0;
+          }
+        }
+      }
+
+      if let Err(error) = emit_block(&block[..len], &mut emit) {return Err(error);}
+      return emit(0x00);
+    },
+    Encoding::Slip => {
+      // The leading END flushes noise that was received before the packet
+      if let Err(error) = emit(END) {return Err(error);}
+
+      for byte in bytes {
+        let result = match *byte {
+          END => emit(ESC).and_then(|_| emit(ESC_END)),
+          ESC => emit(ESC).and_then(|_| emit(ESC_ESC)),
+          value => emit(value)
+        };
+        if let Err(error) = result {return Err(error);}
+      }
+
+      return emit(END);
+    }
+  };
+}
+
+// Private Functions ==============================================================================
+fn emit_block<F: FnMut(u8) -> Result<(), FramingError>>(block: &[u8], emit: &mut F) -> Result<(), FramingError> {
+  if let Err(error) = emit(block.len() as u8 + 1) {return Err(error);}
+
+  for byte in block {
+    if let Err(error) = emit(*byte) {return Err(error);}
+  }
+
+  return Ok(());
+}
+
+
+// Tests ==========================================================================================
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Encodes the data and feeds the frame into a decoder, the packet has to end with the last byte
+  fn round_trip(encoding: Encoding, crc: Crc, data: &[u8]) {
+    let mut frame = [0; 1024];
+    let len = encode(encoding, crc, data, &mut frame).unwrap();
+    assert!(len <= max_encoded_len(encoding, crc, data.len()));
+
+    let mut decoder: Decoder<600> = Decoder::new(encoding, crc);
+    for byte in &frame[..len - 1] {
+      assert_eq!(decoder.feed(*byte), None);
+    }
+    assert_eq!(decoder.feed(frame[len - 1]), Some(Ok(data.len())));
+    assert_eq!(decoder.packet(), data);
+  }
+
+  fn decode<const N: usize>(decoder: &mut Decoder<N>, frame: &[u8]) -> Vec<Option<Result<usize, FramingError>>> {
+    return frame.iter().map(|byte| decoder.feed(*byte)).collect();
+  }
+
+  #[test]
+  fn cobs_encodes_zero_bytes() {
+    let mut frame = [0; 16];
+    let len = encode(Encoding::Cobs, Crc::None, &[0x11, 0x00, 0x00, 0x22], &mut frame).unwrap();
+    assert_eq!(&frame[..len], &[0x02, 0x11, 0x01, 0x02, 0x22, 0x00]);
+
+    round_trip(Encoding::Cobs, Crc::None, &[0x11, 0x00, 0x00, 0x22]);
+    round_trip(Encoding::Cobs, Crc::None, &[0x00]);
+    round_trip(Encoding::Cobs, Crc::None, &[0x00, 0x00, 0x00]);
+  }
+
+  #[test]
+  fn cobs_full_blocks() {
+    let data: Vec<u8> = (0..255).map(|i| (i % 255 + 1) as u8).collect();
+
+    // A full block has no implicit zero
+    let mut frame = [0; 300];
+    let len = encode(Encoding::Cobs, Crc::None, &data[..254], &mut frame).unwrap();
+    assert_eq!(len, 257);
+    assert_eq!(frame[0], 0xFF);
+    assert_eq!(&frame[255..257], &[0x01, 0x00]);
+    round_trip(Encoding::Cobs, Crc::None, &data[..254]);
+
+    let len = encode(Encoding::Cobs, Crc::None, &data, &mut frame).unwrap();
+    assert_eq!(len, 258);
+    assert_eq!(&frame[255..258], &[0x02, 0xFF, 0x00]);
+    round_trip(Encoding::Cobs, Crc::None, &data);
+  }
+
+  #[test]
+  fn cobs_empty_packet() {
+    let mut frame = [0; 8];
+    let len = encode(Encoding::Cobs, Crc::None, &[], &mut frame).unwrap();
+    assert_eq!(&frame[..len], &[0x01, 0x00]);
+
+    round_trip(Encoding::Cobs, Crc::None, &[]);
+    round_trip(Encoding::Cobs, Crc::Crc16, &[]);
+  }
+
+  #[test]
+  fn slip_escapes_special_bytes() {
+    let mut frame = [0; 16];
+    let len = encode(Encoding::Slip, Crc::None, &[0xC0, 0xDB, 0x01], &mut frame).unwrap();
+    assert_eq!(&frame[..len], &[0xC0, 0xDB, 0xDC, 0xDB, 0xDD, 0x01, 0xC0]);
+
+    round_trip(Encoding::Slip, Crc::None, &[0xC0, 0xDB, 0x01]);
+    round_trip(Encoding::Slip, Crc::Crc32, &[0xDB, 0xDC, 0xDD, 0xC0]);
+  }
+
+  #[test]
+  fn malformed_frames() {
+    // ESC has to be followed by ESC_END or ESC_ESC, the rest of the packet is skipped
+    let mut decoder: Decoder<16> = Decoder::new(Encoding::Slip, Crc::None);
+    assert_eq!(decode(&mut decoder, &[0xC0, 0xDB, 0x05, 0x06, 0xC0]), vec![None, None, Some(Err(FramingError::Malformed)), None, None]);
+
+    // The code byte announces more data than the block has
+    let mut decoder: Decoder<16> = Decoder::new(Encoding::Cobs, Crc::None);
+    assert_eq!(decode(&mut decoder, &[0x05, 0x11, 0x00]), vec![None, None, Some(Err(FramingError::Malformed))]);
+
+    // The decoder recovers with the next packet
+    assert_eq!(decode(&mut decoder, &[0x02, 0x11, 0x00]), vec![None, None, Some(Ok(1))]);
+    assert_eq!(decoder.packet(), &[0x11]);
+  }
+
+  #[test]
+  fn wrong_crc() {
+    let mut frame = [0; 16];
+    let len = encode(Encoding::Slip, Crc::Crc16, &[0x01, 0x02], &mut frame).unwrap();
+    frame[1] ^= 0xFF;
+
+    let mut decoder: Decoder<16> = Decoder::new(Encoding::Slip, Crc::Crc16);
+    assert_eq!(decode(&mut decoder, &frame[..len]).pop().unwrap(), Some(Err(FramingError::Crc)));
+  }
+
+  #[test]
+  fn decoder_overflow() {
+    let mut frame = [0; 16];
+    let len = encode(Encoding::Cobs, Crc::None, &[1, 2, 3, 4, 5], &mut frame).unwrap();
+
+    let mut decoder: Decoder<4> = Decoder::new(Encoding::Cobs, Crc::None);
+    let results = decode(&mut decoder, &frame[..len]);
+    assert_eq!(results[5], Some(Err(FramingError::BufferTooSmall)));
+    assert_eq!(results[6], None);
+
+    assert_eq!(encode(Encoding::Cobs, Crc::None, &[1, 2, 3, 4, 5], &mut frame[..6]), Err(FramingError::BufferTooSmall));
+  }
+
+  #[test]
+  fn max_encoded_len_bounds() {
+    let mut frame = [0; 2048];
+
+    // Non-zero bytes are the worst case of COBS, special bytes the worst case of SLIP
+    for len in 0..600 {
+      let data: Vec<u8> = (0..len).map(|i| (i % 255 + 1) as u8).collect();
+      assert_eq!(encode(Encoding::Cobs, Crc::None, &data, &mut frame).unwrap(), max_encoded_len(Encoding::Cobs, Crc::None, len));
+
+      let data: Vec<u8> = vec![0xC0; len];
+      assert_eq!(encode(Encoding::Slip, Crc::None, &data, &mut frame).unwrap(), max_encoded_len(Encoding::Slip, Crc::None, len));
+    }
+
+    assert_eq!(max_encoded_len(Encoding::Cobs, Crc::Crc32, 10), 16);
+    assert_eq!(max_encoded_len(Encoding::Slip, Crc::Crc16, 10), 26);
+  }
+
+  #[test]
+  fn crc_check_values() {
+    assert_eq!(crc16(b"123456789"), 0x29B1);
+    assert_eq!(crc32(b"123456789"), 0xCBF43926);
+  }
+}
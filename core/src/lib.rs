@@ -0,0 +1,10 @@
+//! Hardware independent parts of rustuino. This crate has no dependency on the device crates, so
+//! its unit tests run on the host with `cargo test`.
+#![cfg_attr(not(test), no_std)]
+// The code follows the style of the rest of rustuino with explicit returns and comparisons
+#![allow(clippy::needless_return, clippy::bool_comparison, clippy::len_zero, clippy::manual_range_contains, clippy::question_mark)]
+
+// Submodule includes =============================================================================
+pub mod errors;
+pub mod framing;
+pub mod uart;
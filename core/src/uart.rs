@@ -0,0 +1,154 @@
+//! This module contains the hardware independent calculations of the UART driver.
+
+use crate::errors::{SerialError, ProgError};
+
+// Deviation between the requested and the generated baud rate in percent. Both sides together may
+// deviate by about 3.5%, so each side gets a bit more than half of it.
+const BAUD_TOLERANCE: f32 = 2.0;
+
+/// Baud rate that is generated by the baud rate register, see [calc_baud].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BaudRate {
+  /// Value of the baud rate register.
+  pub brr: u16,
+  pub achieved: u32,
+  /// Deviation from the requested baud rate in percent.
+  pub error: f32
+}
+
+
+// Public Functions ===============================================================================
+/// Calculates the baud rate register for the given peripheral clock. USARTDIV is rounded to the
+/// nearest step of the fraction, which has 4 bits with 16 times and 3 bits with 8 times
+/// oversampling. Fails with `SerialError::UnreachableBaud` if the divider is out of range or the
+/// generated baud rate deviates more than 2% from the requested one.
+///
+/// # Example
+///
+/// ```rust
+/// use rustuino_core::uart::*;
+///
+/// let rate = calc_baud(16000000, 115200, false).unwrap();
+/// assert_eq!(rate.brr, 0x8B);
+/// ```
+pub fn calc_baud(pclk: u32, baud: u32, over8: bool) -> Result<BaudRate, SerialError> {
+  if baud == 0 {return Err(SerialError::Prog(ProgError::InvalidConfiguration));}
+
+  let samples: u32 = if over8 == true {8} else {16};
+
+  // pclk / baud = USARTDIV * samples, so the fraction is already in the lower bits
+  let div = (pclk + baud / 2) / baud;
+  if div < samples || div / samples > 0xFFF {return Err(SerialError::UnreachableBaud);}
+
+  // With OVER8 the fraction has only 3 bits and bit 3 has to stay cleared
+  let brr = if over8 == true {((div >> 3) << 4) | (div & 0x7)}
+  else {div};
+
+  let achieved = (pclk + div / 2) / div;
+  let error = (achieved as f32 - baud as f32) * 100.0 / baud as f32;
+  if error > BAUD_TOLERANCE || error < -BAUD_TOLERANCE {return Err(SerialError::UnreachableBaud);}
+
+  return Ok(BaudRate {
+    brr: brr as u16,
+    achieved,
+    error
+  });
+}
+
+/// Returns how many bytes of `pattern` are matched after `byte` was received, when `matched`
+/// bytes were matched before. On a mismatch it falls back to the longest prefix of the pattern
+/// that ends with the received byte, so matches that overlap a partial match are not missed.
+pub fn next_match(pattern: &[u8], matched: usize, byte: u8) -> usize {
+  if matched < pattern.len() && pattern[matched] == byte {return matched + 1;}
+
+  // The received data ends with pattern[..matched] followed by the byte
+  for len in (1..matched.min(pattern.len())).rev() {
+    if pattern[len] == byte && pattern[..len] == pattern[matched - len..matched] {return len + 1;}
+  }
+  if pattern.len() > 0 && pattern[0] == byte {return 1;}
+
+  return 0;
+}
+
+
+// Tests ==========================================================================================
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn brr_rounds_to_the_nearest_fraction() {
+    // 16MHz / 115200 = 138.89 -> 139
+    let rate = calc_baud(16000000, 115200, false).unwrap();
+    assert_eq!(rate.brr, 0x8B);
+  }
+
+  #[test]
+  fn brr_fraction_carries_into_the_mantissa() {
+    // 16MHz / 91117 = 175.6 = 16 * 10 + 15.6, the fraction rounds up to the next mantissa
+    let rate = calc_baud(16000000, 91117, false).unwrap();
+    assert_eq!(rate.brr, 0xB0);
+  }
+
+  #[test]
+  fn brr_over8_keeps_bit_3_cleared() {
+    // 139 = 8 * 17 + 3
+    let rate = calc_baud(16000000, 115200, true).unwrap();
+    assert_eq!(rate.brr, (17 << 4) | 3);
+    assert_eq!(rate.brr & 0x8, 0);
+
+    // 16MHz / 1MBd = 16 = 8 * 2 + 0
+    let rate = calc_baud(16000000, 1000000, true).unwrap();
+    assert_eq!(rate.brr, 0x20);
+  }
+
+  #[test]
+  fn achieved_baud_and_error() {
+    let rate = calc_baud(16000000, 115200, false).unwrap();
+    assert_eq!(rate.achieved, 115108);
+    assert!((rate.error - (-0.0799)).abs() < 0.001);
+
+    let rate = calc_baud(16000000, 1000000, true).unwrap();
+    assert_eq!(rate.achieved, 1000000);
+    assert_eq!(rate.error, 0.0);
+  }
+
+  #[test]
+  fn error_tolerance() {
+    // 16MHz / 1.31MBd = 12.21 -> 12 gives 1333333 Bd (+1.78%)
+    let rate = calc_baud(16000000, 1310000, true).unwrap();
+    assert_eq!(rate.achieved, 1333333);
+    assert!(rate.error > 1.7 && rate.error < 1.8);
+
+    // 16MHz / 1.3MBd = 12.31 -> 12 gives +2.56%
+    assert_eq!(calc_baud(16000000, 1300000, true), Err(SerialError::UnreachableBaud));
+    // 16MHz / 1.4MBd = 11.43 -> 11 gives +3.9%
+    assert_eq!(calc_baud(16000000, 1400000, true), Err(SerialError::UnreachableBaud));
+  }
+
+  #[test]
+  fn overlapping_matches() {
+    let find = |pattern: &[u8], data: &[u8]| {
+      let mut matched = 0;
+      for (i, byte) in data.iter().enumerate() {
+        matched = next_match(pattern, matched, *byte);
+        if matched == pattern.len() {return Some(i + 1);}
+      }
+      return None;
+    };
+
+    assert_eq!(find(b"aab", b"aaab"), Some(4));
+    assert_eq!(find(b"abab", b"abaabab"), Some(7));
+    assert_eq!(find(b"+++exit", b"++++exit"), Some(8));
+    assert_eq!(find(b"abc", b"ababd"), None);
+  }
+
+  #[test]
+  fn divider_out_of_range() {
+    // Less than one bit period per sample
+    assert_eq!(calc_baud(16000000, 1400000, false), Err(SerialError::UnreachableBaud));
+    // The mantissa has only 12 bits
+    assert_eq!(calc_baud(180000000, 300, false), Err(SerialError::UnreachableBaud));
+    assert_eq!(calc_baud(16000000, 0, false), Err(SerialError::Prog(ProgError::InvalidConfiguration)));
+  }
+}
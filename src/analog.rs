@@ -2,6 +2,7 @@
 
 use crate::include::{stm_peripherals, GpioError, ProgError, ADC_MAP};
 use crate::gpio::{GpioMode::Analog, return_pinmode};
+use crate::clocks::{pclk2, timer_clk1};
 use rtt_target::rprintln;
 
 
@@ -46,7 +47,7 @@ pub fn enable_channel(pin: (char, u8)) -> Result<(), ProgError> {
       let adc1 = &peripheral_ptr.ADC1;
       if rcc.apb2enr.read().adc1en().is_disabled() == true {
         rcc.apb2enr.modify(|_, w| w.adc1en().enabled());
-        adcc.ccr.modify(|_, w| w.adcpre().div2());
+        adcc.ccr.modify(|_, w| w.adcpre().bits(calc_adcpre()));
         adc1.smpr2.modify(|_, w| w.smp0().cycles144());
         adc1.cr1.modify(|_, w| w.res().ten_bit());
         adc1.cr2.modify(|_, w| w.adon().enabled());
@@ -56,7 +57,7 @@ pub fn enable_channel(pin: (char, u8)) -> Result<(), ProgError> {
       let adc2 = &peripheral_ptr.ADC2;
       if rcc.apb2enr.read().adc2en().is_disabled() == true {
         rcc.apb2enr.modify(|_, w| w.adc2en().enabled());
-        adcc.ccr.modify(|_, w| w.adcpre().div2());
+        adcc.ccr.modify(|_, w| w.adcpre().bits(calc_adcpre()));
         adc2.smpr2.modify(|_, w| w.smp0().cycles144());
         adc2.cr1.modify(|_, w| w.res().ten_bit());
         adc2.cr2.modify(|_, w| w.adon().enabled());
@@ -66,7 +67,7 @@ pub fn enable_channel(pin: (char, u8)) -> Result<(), ProgError> {
       let adc3 = &peripheral_ptr.ADC3;
       if rcc.apb2enr.read().adc3en().is_disabled() == true {
         rcc.apb2enr.modify(|_, w| w.adc3en().enabled());
-        adcc.ccr.modify(|_, w| w.adcpre().div2());
+        adcc.ccr.modify(|_, w| w.adcpre().bits(calc_adcpre()));
         adc3.smpr2.modify(|_, w| w.smp0().cycles144());
         adc3.cr1.modify(|_, w| w.res().ten_bit());
         adc3.cr2.modify(|_, w| w.adon().enabled());
@@ -313,12 +314,13 @@ pub fn analog_wave_freq(freq: u32) {
   let peripheral_ptr = stm_peripherals();
   let tim5 = &peripheral_ptr.TIM5;
 
-  // Max. 16MHz -> arr = 16000000 / freq
-  let val = if freq > 16000000 {
+  // Max. timer clock -> arr = timer_clk / freq
+  let timer_clk = timer_clk1();
+  let val = if freq > timer_clk || freq == 0 {
     rprintln!("Outside limits of internal clock! | analog_wave_freq()");
     1
   }
-  else {16000000 / freq};
+  else {timer_clk / freq};
 
   tim5.arr.write(|w| w.arr().bits(val.into()));
 }
@@ -347,8 +349,15 @@ fn start_dac_timer() {
   rcc.apb1enr.modify(|_, w| w.tim5en().enabled());
   tim5.cr1.modify(|_, w| w.arpe().enabled());
   tim5.psc.write(|w| w.psc().bits(1));
-  tim5.arr.write(|w| w.arr().bits(16000000 / 1000));
+  tim5.arr.write(|w| w.arr().bits(timer_clk1() / 1000));
   tim5.egr.write(|w| w.ug().update());
   tim5.cr2.modify(|_, w| w.mms().update());
   tim5.cr1.modify(|_, w| w.cen().enabled());
 }
+
+fn calc_adcpre() -> u8 {
+  // ADC clock max. 36MHz -> PCLK2 / 2, 4, 6 or 8
+  let mut adcpre: u8 = 0;
+  while pclk2() / (2 * (adcpre as u32 + 1)) > 36000000 && adcpre < 3 {adcpre += 1;}
+  return adcpre;
+}
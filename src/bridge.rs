@@ -0,0 +1,335 @@
+//! This module contains a transparent bridge between two U(S)ART ports.
+
+use crate::include::{stm_peripherals, SerialError, ProgError};
+use crate::uart::{UART, next_match, queue_word};
+use crate::time::{start_time, millis};
+use cortex_m::interrupt::{Mutex, free};
+use core::cell::RefCell;
+use heapless::Vec;
+use rtt_target::rprintln;
+
+// Longest supported escape sequence
+const MAX_ESCAPE: usize = 16;
+// Bytes that one received byte can release, every one of them can grow into a line ending
+const OUT_SIZE: usize = 2 * (MAX_ESCAPE + 1);
+
+static STATE: Mutex<RefCell<Option<State>>> = Mutex::new(RefCell::new(None));
+
+/// Represents the line ending that is sent on a side of the bridge. Any line ending that is
+/// received (CR, LF or CR LF) is replaced with it, `Unchanged` passes everything through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+  Unchanged,
+  Cr,
+  Lf,
+  CrLf
+}
+
+/// Forwards everything that is received on one port to the other. The data is forwarded from the
+/// receive interrupts straight into the transmit buffer of the other port, bytes that do not fit
+/// into it are dropped. [Bridge::poll] or [Bridge::run] only has to be called to start the bridge,
+/// to flush a partially received escape sequence after the guard time and to notice the escape.
+/// Only one bridge can be active at a time.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustuino::*;
+/// use rustuino::uart::*;
+/// use rustuino::bridge::*;
+///
+/// let pc = UART::new(2, PA2, PA3, 115200, UART_8N1).unwrap();
+/// let modem = UART::new(4, PA0, PA1, 9600, UART_8N1).unwrap();
+///
+/// let mut bridge = Bridge::new(&pc, &modem).line_endings(LineEnding::CrLf, LineEnding::CrLf).escape(b"+++exit").tap(true);
+/// bridge.run().unwrap();
+/// pc.println("Bridge closed").unwrap();
+/// ```
+pub struct Bridge<'a> {
+  host: &'a UART,
+  device: &'a UART,
+  to_device: LineEnding,
+  to_host: LineEnding,
+  escape: &'static [u8],
+  guard_time: usize,
+  tap: bool,
+  active: bool
+}
+
+// Everything the receive hooks need while the bridge is active.
+struct State {
+  host: u8,
+  device: u8,
+  to_device: Channel,
+  to_host: Channel,
+  escape: &'static [u8],
+  matched: usize,
+  held_since: usize,
+  guard_time: usize,
+  escaped: bool,
+  tap: bool
+}
+
+// Line ending state of one direction.
+struct Channel {
+  ending: LineEnding,
+  last_cr: bool
+}
+
+impl<'a> Bridge<'a> {
+  /// Creates a bridge between a host port (e.g. the PC) and a device port (e.g. a modem).
+  pub fn new(host: &'a UART, device: &'a UART) -> Self {
+    return Self {
+      host,
+      device,
+      to_device: LineEnding::Unchanged,
+      to_host: LineEnding::Unchanged,
+      escape: &[],
+      guard_time: 1000,
+      tap: false,
+      active: false
+    };
+  }
+
+  /// Sets the line endings that are sent to the device and to the host.
+  pub fn line_endings(mut self, to_device: LineEnding, to_host: LineEnding) -> Self {
+    self.to_device = to_device;
+    self.to_host = to_host;
+    return self;
+  }
+
+  /// Sets a sequence of up to 16 bytes that closes the bridge when it is received from the host.
+  /// The sequence itself is not forwarded.
+  pub fn escape(mut self, sequence: &'static [u8]) -> Self {
+    if sequence.len() > MAX_ESCAPE {
+      rprintln!("Escape sequence is longer than {} bytes! | Bridge::escape()", MAX_ESCAPE);
+      return self;
+    }
+
+    self.escape = sequence;
+    return self;
+  }
+
+  /// Sets the time in ms after which the start of an escape sequence is forwarded if the rest of it
+  /// does not follow. The default is one second.
+  pub fn guard_time(mut self, ms: usize) -> Self {
+    self.guard_time = ms;
+    return self;
+  }
+
+  /// Logs all forwarded data to RTT.
+  pub fn tap(mut self, enable: bool) -> Self {
+    self.tap = enable;
+    return self;
+  }
+
+  /// Starts the bridge on the first call. Returns false if the escape sequence was received, the
+  /// bridge is closed then and the data after the sequence stays in the receive buffer of the
+  /// host port.
+  pub fn poll(&mut self) -> Result<bool, SerialError> {
+    if self.active == false {
+      if let Err(error) = self.start() {return Err(error);}
+    }
+
+    let escaped = free(|cs| {
+      let mut state = STATE.borrow(cs).borrow_mut();
+      let state = match state.as_mut() {
+        Some(value) => value,
+        None => return true
+      };
+
+      if state.matched > 0 && millis().wrapping_sub(state.held_since) >= state.guard_time {
+        let mut data: Vec<u8, OUT_SIZE> = Vec::new();
+        for index in 0..state.matched {state.to_device.translate(state.escape[index], &mut data);}
+        state.matched = 0;
+        state.send(&data, true);
+      }
+
+      return state.escaped;
+    });
+
+    if escaped == true {
+      self.stop();
+      return Ok(false);
+    }
+
+    return Ok(true);
+  }
+
+  /// Polls the bridge until the escape sequence is received.
+  pub fn run(&mut self) -> Result<(), SerialError> {
+    loop {
+      match self.poll() {
+        Ok(true) => (),
+        Ok(false) => return Ok(()),
+        Err(error) => return Err(error)
+      };
+    }
+  }
+
+  /// Closes the bridge, the received data stays in the receive buffers from now on.
+  pub fn stop(&mut self) {
+    if self.active == false {return;}
+
+    self.host.set_rx_hook(None);
+    self.device.set_rx_hook(None);
+    free(|cs| STATE.borrow(cs).replace(None));
+    self.active = false;
+  }
+
+  fn start(&mut self) -> Result<(), SerialError> {
+    let peripheral_ptr = stm_peripherals();
+    let rcc = &peripheral_ptr.RCC;
+
+    // The guard time is measured with the millis timer
+    if rcc.apb1enr.read().tim7en().is_disabled() == true {start_time();}
+
+    let started = free(|cs| {
+      let mut state = STATE.borrow(cs).borrow_mut();
+      if state.is_some() == true {return false;}
+
+      state.replace(State {
+        host: self.host.core(),
+        device: self.device.core(),
+        to_device: Channel {ending: self.to_device, last_cr: false},
+        to_host: Channel {ending: self.to_host, last_cr: false},
+        escape: self.escape,
+        matched: 0,
+        held_since: 0,
+        guard_time: self.guard_time,
+        escaped: false,
+        tap: self.tap
+      });
+      drop(state);
+
+      // Data that arrived before the bridge is forwarded first, the interrupts are masked until
+      // the hooks are in place
+      loop {
+        let escaped = match STATE.borrow(cs).borrow().as_ref() {
+          Some(state) => state.escaped,
+          None => true
+        };
+        if escaped == true {break;}
+
+        match self.host.try_read_word() {
+          Some(byte) => host_hook(byte),
+          None => break
+        };
+      }
+      while let Some(byte) = self.device.try_read_word() {device_hook(byte);}
+
+      self.host.set_rx_hook(Some(host_hook));
+      self.device.set_rx_hook(Some(device_hook));
+      return true;
+    });
+
+    if started == false {
+      rprintln!("Another bridge is already active! | Bridge::poll()");
+      return Err(SerialError::Prog(ProgError::AlreadyConfigured));
+    }
+
+    self.active = true;
+    return Ok(());
+  }
+}
+
+impl<'a> Drop for Bridge<'a> {
+  fn drop(&mut self) {
+    self.stop();
+  }
+}
+
+impl State {
+  // Returns false once the escape sequence was received, so the data after it stays in the buffer
+  fn from_host(&mut self, byte: u8) -> bool {
+    if self.escaped == true {return false;}
+
+    // Bytes that drop out of the held back part of the sequence were no escape after all, the
+    // received byte is one of them if it does not continue a match
+    let mut data: Vec<u8, OUT_SIZE> = Vec::new();
+    let matched = next_match(self.escape, self.matched, byte);
+    for index in 0..(self.matched + 1 - matched) {
+      let held = if index < self.matched {self.escape[index]} else {byte};
+      self.to_device.translate(held, &mut data);
+    }
+    if matched > self.matched {self.held_since = millis();}
+    self.matched = matched;
+
+    if self.escape.len() > 0 && self.matched == self.escape.len() {
+      self.matched = 0;
+      self.escaped = true;
+    }
+
+    self.send(&data, true);
+    return true;
+  }
+
+  fn from_device(&mut self, byte: u8) {
+    let mut data: Vec<u8, OUT_SIZE> = Vec::new();
+    self.to_host.translate(byte, &mut data);
+    self.send(&data, false);
+  }
+
+  fn send(&self, data: &[u8], to_device: bool) {
+    if data.len() == 0 {return;}
+
+    if self.tap == true {
+      if to_device == true {rprintln!("host -> device: {:02X?}", data);}
+      else {rprintln!("device -> host: {:02X?}", data);}
+    }
+
+    let target = if to_device == true {self.device} else {self.host};
+    for byte in data {
+      if queue_word(target, *byte as u16) == false {break;}
+    }
+  }
+}
+
+impl Channel {
+  // Adds the byte to the data, a line ending can grow by one byte
+  fn translate(&mut self, byte: u8, data: &mut Vec<u8, OUT_SIZE>) {
+    if self.ending == LineEnding::Unchanged {
+      let _ = data.push(byte);
+      return;
+    }
+
+    let last_cr = self.last_cr;
+    self.last_cr = byte == b'\r';
+
+    match byte {
+      b'\r' => (),
+      b'\n' if last_cr == true => return,
+      b'\n' => (),
+      _ => {
+        let _ = data.push(byte);
+        return;
+      }
+    };
+
+    match self.ending {
+      LineEnding::Cr => {let _ = data.push(b'\r');},
+      LineEnding::Lf => {let _ = data.push(b'\n');},
+      LineEnding::CrLf => {let _ = data.extend_from_slice(b"\r\n");},
+      LineEnding::Unchanged => unreachable!()
+    };
+  }
+}
+
+
+// Receive hooks ==================================================================================
+fn host_hook(data: u16) -> bool {
+  return free(|cs| match STATE.borrow(cs).borrow_mut().as_mut() {
+    Some(state) => state.from_host(data as u8),
+    None => false
+  });
+}
+
+fn device_hook(data: u16) -> bool {
+  return free(|cs| match STATE.borrow(cs).borrow_mut().as_mut() {
+    Some(state) => {
+      state.from_device(data as u8);
+      return true;
+    },
+    None => false
+  });
+}
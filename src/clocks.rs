@@ -0,0 +1,279 @@
+//! This module contains everything that is related to the system clock tree.
+
+use crate::include::{stm_peripherals, ProgError};
+use core::sync::atomic::{AtomicU32, Ordering};
+use rtt_target::rprintln;
+
+const HSI_FREQ: u32 = 16000000;
+// Above this system clock the regulator has to run in over-drive mode.
+const OVERDRIVE_FREQ: u32 = 168000000;
+
+// Current frequencies of the clock tree, the MC starts up with the HSI and all prescalers set to 1.
+static SYSCLK: AtomicU32 = AtomicU32::new(HSI_FREQ);
+static HCLK: AtomicU32 = AtomicU32::new(HSI_FREQ);
+static PCLK1: AtomicU32 = AtomicU32::new(HSI_FREQ);
+static PCLK2: AtomicU32 = AtomicU32::new(HSI_FREQ);
+
+/// Represents the source of the system clock. The frequency of an external crystal has to be given
+/// in Hz and must lie between 4MHz and 26MHz.
+pub enum ClockSource {
+  HSI,
+  HSE(u32)
+}
+
+/// Represents the configuration of the clock tree.
+///
+/// | Bus  | Max. Frequency | Peripherals                                 |
+/// | ---- | -------------- | ------------------------------------------- |
+/// | AHB  | 180MHz         | Core, GPIO, DMA                             |
+/// | APB1 | 45MHz          | TIM2-7, USART2/3, UART4/5, I2C, SPI2/3, DAC |
+/// | APB2 | 90MHz          | TIM1, USART1/6, SPI1, ADC                   |
+///
+/// The prescalers only accept powers of two (AHB: 1-512, APB: 1-16). Timers on a bus with a
+/// prescaler other than 1 run with twice the bus frequency.
+pub struct ClockConfig {
+  pub source: ClockSource,
+  pub sysclk: u32,
+  pub ahb_div: u16,
+  pub apb1_div: u8,
+  pub apb2_div: u8
+}
+
+impl ClockConfig {
+  /// Returns a configuration for the given system clock, with the fastest APB prescalers that are
+  /// still within the limits of the buses.
+  pub fn new(source: ClockSource, sysclk: u32) -> Self {
+    let mut apb1_div: u8 = 1;
+    while sysclk / apb1_div as u32 > 45000000 {apb1_div *= 2;}
+    let mut apb2_div: u8 = 1;
+    while sysclk / apb2_div as u32 > 90000000 {apb2_div *= 2;}
+
+    return Self {
+      source,
+      sysclk,
+      ahb_div: 1,
+      apb1_div,
+      apb2_div
+    };
+  }
+}
+
+
+// Public Functions ===============================================================================
+/// Configures the clock tree and stores the resulting frequencies for the other modules. This
+/// should be called at the start of the program, before any peripheral is set up.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustuino::*;
+/// use rustuino::clocks::*;
+///
+/// setup_clocks(ClockConfig::new(ClockSource::HSE(8000000), 180000000)).unwrap();
+/// rprintln!("Running at {}Hz", sysclk());
+/// ```
+pub fn setup_clocks(config: ClockConfig) -> Result<(), ProgError> {
+  let peripheral_ptr = stm_peripherals();
+  let rcc = &peripheral_ptr.RCC;
+  let pwr = &peripheral_ptr.PWR;
+  let flash = &peripheral_ptr.FLASH;
+
+  let src_freq = match config.source {
+    ClockSource::HSI => HSI_FREQ,
+    ClockSource::HSE(freq) => {
+      if freq < 4000000 || freq > 26000000 {
+        rprintln!("HSE frequency has to be between 4MHz and 26MHz! | setup_clocks()");
+        return Err(ProgError::InvalidConfiguration);
+      }
+      freq
+    }
+  };
+
+  if config.sysclk > 180000000 {
+    rprintln!("System clock cannot be faster than 180MHz! | setup_clocks()");
+    return Err(ProgError::InvalidConfiguration);
+  }
+
+  let hpre: u32 = match config.ahb_div {
+    1 => 0b0000,
+    2 => 0b1000,
+    4 => 0b1001,
+    8 => 0b1010,
+    16 => 0b1011,
+    64 => 0b1100,
+    128 => 0b1101,
+    256 => 0b1110,
+    512 => 0b1111,
+    _ => {
+      rprintln!("{} is not a valid AHB prescaler! | setup_clocks()", config.ahb_div);
+      return Err(ProgError::InvalidConfiguration);
+    }
+  };
+  let ppre1 = match calc_ppre(config.apb1_div) {
+    Some(value) => value,
+    None => {
+      rprintln!("{} is not a valid APB1 prescaler! | setup_clocks()", config.apb1_div);
+      return Err(ProgError::InvalidConfiguration);
+    }
+  };
+  let ppre2 = match calc_ppre(config.apb2_div) {
+    Some(value) => value,
+    None => {
+      rprintln!("{} is not a valid APB2 prescaler! | setup_clocks()", config.apb2_div);
+      return Err(ProgError::InvalidConfiguration);
+    }
+  };
+
+  let hclk = config.sysclk / config.ahb_div as u32;
+  let pclk1 = hclk / config.apb1_div as u32;
+  let pclk2 = hclk / config.apb2_div as u32;
+
+  if pclk1 > 45000000 || pclk2 > 90000000 {
+    rprintln!("APB clocks are outside of their limits! | setup_clocks()");
+    return Err(ProgError::InvalidConfiguration);
+  }
+
+  let use_pll = config.sysclk != src_freq;
+  let pllcfgr = if use_pll == true {
+    match calc_pll(src_freq, config.sysclk) {
+      Some(value) => value,
+      None => {
+        rprintln!("{}Hz cannot be generated by the PLL! | setup_clocks()", config.sysclk);
+        return Err(ProgError::InvalidConfiguration);
+      }
+    }
+  }
+  else {0};
+
+  // Run from the HSI while the clock tree is changed
+  rcc.cr.modify(|_, w| w.hsion().on());
+  while rcc.cr.read().hsirdy().is_not_ready() == true {}
+  rcc.cfgr.modify(|_, w| w.sw().hsi());
+  while rcc.cfgr.read().sws().is_hsi() == false {}
+  rcc.cr.modify(|_, w| w.pllon().off());
+
+  if let ClockSource::HSE(_) = config.source {
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() == true {}
+  }
+
+  // Voltage scale 1 -> max. 180MHz, scale 2 -> 144MHz, scale 3 -> 120MHz
+  rcc.apb1enr.modify(|_, w| w.pwren().enabled());
+  let vos: u32 = if config.sysclk > 144000000 {0b11} else if config.sysclk > 120000000 {0b10} else {0b01};
+  pwr.cr.modify(|r, w| unsafe {w.bits((r.bits() & !(0b11 << 14)) | (vos << 14))});
+
+  // One wait state per 30MHz at 2.7V - 3.6V, must be set before the clock is raised
+  let latency = (hclk - 1) / 30000000;
+  flash.acr.modify(|_, w| unsafe {
+    w.latency().bits(latency as u8);
+    w.prften().set_bit();
+    w.icen().set_bit();
+    w.dcen().set_bit()
+  });
+  while flash.acr.read().latency().bits() != latency as u8 {}
+
+  rcc.cfgr.modify(|r, w| unsafe {w.bits((r.bits() & !0xFCF0) | (hpre << 4) | (ppre1 << 10) | (ppre2 << 13))});
+
+  if use_pll == true {
+    let pllsrc: u32 = if let ClockSource::HSE(_) = config.source {1} else {0};
+    rcc.pllcfgr.write(|w| unsafe {w.bits(pllcfgr | (pllsrc << 22))});
+    rcc.cr.modify(|_, w| w.pllon().on());
+    while rcc.cr.read().pllrdy().is_not_ready() == true {}
+
+    if config.sysclk > OVERDRIVE_FREQ {enable_overdrive();}
+
+    rcc.cfgr.modify(|_, w| w.sw().pll());
+    while rcc.cfgr.read().sws().is_pll() == false {}
+  }
+  else if let ClockSource::HSE(_) = config.source {
+    rcc.cfgr.modify(|_, w| w.sw().hse());
+    while rcc.cfgr.read().sws().is_hse() == false {}
+  }
+
+  SYSCLK.store(config.sysclk, Ordering::SeqCst);
+  HCLK.store(hclk, Ordering::SeqCst);
+  PCLK1.store(pclk1, Ordering::SeqCst);
+  PCLK2.store(pclk2, Ordering::SeqCst);
+
+  return Ok(());
+}
+
+pub fn sysclk() -> u32 {
+  return SYSCLK.load(Ordering::SeqCst);
+}
+
+pub fn hclk() -> u32 {
+  return HCLK.load(Ordering::SeqCst);
+}
+
+pub fn pclk1() -> u32 {
+  return PCLK1.load(Ordering::SeqCst);
+}
+
+pub fn pclk2() -> u32 {
+  return PCLK2.load(Ordering::SeqCst);
+}
+
+/// Clock of the timers on APB1 (TIM2-7, TIM12-14).
+pub fn timer_clk1() -> u32 {
+  if pclk1() == hclk() {return pclk1();}
+  else {return pclk1() * 2;}
+}
+
+/// Clock of the timers on APB2 (TIM1, TIM8-11).
+pub fn timer_clk2() -> u32 {
+  if pclk2() == hclk() {return pclk2();}
+  else {return pclk2() * 2;}
+}
+
+/// Switches the regulator to over-drive mode, which is needed for system clocks above 168MHz. The
+/// PLL has to be running, but must not be the system clock yet. The hardware leaves over-drive
+/// mode in stop mode, so it has to be enabled again before the PLL is switched back on.
+pub fn enable_overdrive() {
+  let peripheral_ptr = stm_peripherals();
+  let pwr = &peripheral_ptr.PWR;
+
+  pwr.cr.modify(|_, w| w.oden().set_bit());
+  while pwr.csr.read().odrdy().bit_is_clear() == true {}
+  pwr.cr.modify(|_, w| w.odswen().set_bit());
+  while pwr.csr.read().odswrdy().bit_is_clear() == true {}
+}
+
+/// True if the stored system clock needs the regulator in over-drive mode.
+pub fn needs_overdrive() -> bool {
+  return sysclk() > OVERDRIVE_FREQ;
+}
+
+
+// Private Functions ==============================================================================
+fn calc_ppre(div: u8) -> Option<u32> {
+  match div {
+    1 => return Some(0b000),
+    2 => return Some(0b100),
+    4 => return Some(0b101),
+    8 => return Some(0b110),
+    16 => return Some(0b111),
+    _ => return None
+  };
+}
+
+fn calc_pll(src_freq: u32, sysclk: u32) -> Option<u32> {
+  // VCO input between 1MHz and 2MHz, 2MHz gives the lowest jitter
+  let pllm = if src_freq % 2000000 == 0 {src_freq / 2000000} else {src_freq / 1000000};
+  let vco_in = src_freq / pllm;
+
+  for pllp in [2, 4, 6, 8] {
+    let vco_out = sysclk * pllp;
+    if vco_out < 100000000 || vco_out > 432000000 || vco_out % vco_in != 0 {continue;}
+
+    let plln = vco_out / vco_in;
+    if plln < 50 || plln > 432 {continue;}
+
+    // 48MHz domain (USB, SDIO) as close as possible
+    let pllq = ((vco_out + 24000000) / 48000000).max(2).min(15);
+
+    return Some(pllm | (plln << 6) | ((pllp / 2 - 1) << 16) | (pllq << 24));
+  }
+
+  return None;
+}
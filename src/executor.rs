@@ -0,0 +1,187 @@
+//! This module contains a small cooperative executor for running multiple async tasks without an RTOS.
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use core::cell::RefCell;
+use cortex_m::interrupt::{Mutex, free};
+use heapless::Vec;
+use crate::include::ProgError;
+use rtt_target::rprintln;
+
+// Every bit represents a task slot that has to be polled again.
+static READY: AtomicU32 = AtomicU32::new(0);
+// The wakers only carry the task slot, so all of them belong to the one existing executor.
+static EXECUTOR_TAKEN: AtomicBool = AtomicBool::new(false);
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(waker_clone, waker_wake, waker_wake, waker_drop);
+
+
+// Public Structs =================================================================================
+/// Single-threaded executor that polls up to `N` tasks (max. 31) until all of them are finished.
+///
+/// Tasks are only polled again after their waker was triggered, e.g. by an interrupt. While no task
+/// is ready the core is put to sleep with `WFI`. Only one executor can exist at a time, the slots of
+/// finished tasks are reused by the next spawned tasks.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustuino::*;
+/// use rustuino::executor::Executor;
+/// use rustuino::time::delay_ms;
+/// use core::pin::pin;
+///
+/// let blink = pin!(async {
+///   pin_mode(A5, GpioMode::Output).unwrap();
+///   loop {
+///     digital_write(A5, true).unwrap();
+///     delay_ms(500).await;
+///     digital_write(A5, false).unwrap();
+///     delay_ms(500).await;
+///   }
+/// });
+///
+/// let mut executor: Executor<4> = Executor::new().unwrap();
+/// executor.spawn(blink).unwrap();
+/// executor.run();
+/// ```
+pub struct Executor<'a, const N: usize> {
+  tasks: Vec<Option<Pin<&'a mut dyn Future<Output = ()>>>, N>
+}
+
+impl<'a, const N: usize> Executor<'a, N> {
+  // Fails to evaluate for executors with more tasks than the READY bitmask can hold
+  const TASK_LIMIT: () = assert!(N <= 31, "The executor can only handle 31 tasks! | Executor::new()");
+
+  pub fn new() -> Result<Self, ProgError> {
+    let _ = Self::TASK_LIMIT;
+
+    if EXECUTOR_TAKEN.swap(true, Ordering::SeqCst) == true {
+      rprintln!("Only one executor can exist at a time! | Executor::new()");
+      return Err(ProgError::AlreadyConfigured);
+    }
+
+    return Ok(Self {
+      tasks: Vec::new()
+    });
+  }
+
+  pub fn spawn(&mut self, task: Pin<&'a mut dyn Future<Output = ()>>) -> Result<(), ProgError> {
+    let id = match self.tasks.iter().position(|i| i.is_none()) {
+      Some(id) => {
+        self.tasks[id] = Some(task);
+        id
+      },
+      None => {
+        if self.tasks.push(Some(task)).is_err() {
+          rprintln!("Cannot spawn more than {} tasks! | .spawn()", N);
+          return Err(ProgError::OutOfMemory);
+        }
+        self.tasks.len() - 1
+      }
+    };
+
+    READY.fetch_or(1 << id, Ordering::SeqCst);
+
+    return Ok(());
+  }
+
+  pub fn run(&mut self) {
+    loop {
+      // Slot 31 is left to block_on
+      let ready = READY.fetch_and(1 << 31, Ordering::SeqCst);
+
+      for (id, slot) in self.tasks.iter_mut().enumerate() {
+        if ready & (1 << id) == 0 {continue;}
+
+        let finished = match slot {
+          Some(task) => {
+            let waker = unsafe {Waker::from_raw(RawWaker::new(id as *const (), &VTABLE))};
+            let mut cx = Context::from_waker(&waker);
+            task.as_mut().poll(&mut cx).is_ready()
+          },
+          None => false
+        };
+
+        if finished == true {*slot = None;}
+      }
+
+      if self.tasks.iter().all(|i| i.is_none()) == true {return;}
+
+      // The interrupt that sets a ready bit between the check and WFI still wakes the core.
+      free(|_| {
+        if READY.load(Ordering::SeqCst) & !(1 << 31) == 0 {cortex_m::asm::wfi();}
+      });
+    }
+  }
+}
+
+impl<'a, const N: usize> Drop for Executor<'a, N> {
+  fn drop(&mut self) {
+    // Wakeups of the dropped tasks must not reach the next executor, slot 31 belongs to block_on
+    READY.fetch_and(1 << 31, Ordering::SeqCst);
+    EXECUTOR_TAKEN.store(false, Ordering::SeqCst);
+  }
+}
+
+/// Stores the waker of a task that waits for an interrupt.
+pub struct WakerSlot {
+  waker: Mutex<RefCell<Option<Waker>>>
+}
+
+impl WakerSlot {
+  pub const fn new() -> Self {
+    return Self {
+      waker: Mutex::new(RefCell::new(None))
+    };
+  }
+
+  pub fn register(&self, waker: &Waker) {
+    free(|cs| {
+      let mut slot = self.waker.borrow(cs).borrow_mut();
+      match slot.as_ref() {
+        Some(stored) if stored.will_wake(waker) == true => (),
+        _ => *slot = Some(waker.clone())
+      };
+    });
+  }
+
+  pub fn wake(&self) {
+    if let Some(waker) = free(|cs| self.waker.borrow(cs).borrow_mut().take()) {waker.wake();}
+  }
+}
+
+
+// Public Functions ===============================================================================
+/// Runs a single future to completion and returns its output.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+  let mut future = future;
+  let mut future = unsafe {Pin::new_unchecked(&mut future)};
+
+  // Slot 31 is reserved for block_on so it does not collide with spawned tasks.
+  let waker = unsafe {Waker::from_raw(RawWaker::new(31 as *const (), &VTABLE))};
+  let mut cx = Context::from_waker(&waker);
+
+  loop {
+    if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {return output;}
+
+    free(|_| {
+      if READY.load(Ordering::SeqCst) & (1 << 31) == 0 {cortex_m::asm::wfi();}
+    });
+    READY.fetch_and(!(1 << 31), Ordering::SeqCst);
+  }
+}
+
+
+// Private Functions ==============================================================================
+fn waker_clone(data: *const ()) -> RawWaker {
+  return RawWaker::new(data, &VTABLE);
+}
+
+fn waker_wake(data: *const ()) {
+  READY.fetch_or(1 << (data as usize), Ordering::SeqCst);
+}
+
+fn waker_drop(_data: *const ()) {}
@@ -0,0 +1,731 @@
+//! This module contains a Firmata server that is compatible with StandardFirmata, so that boards
+//! can be controlled from a PC with any Firmata client library.
+
+use crate::include::{stm_peripherals, SerialError, I2cError, ProgError, ADC_MAP, PWM_MAP, I2C_MAP};
+use crate::uart::UART;
+use crate::i2c::I2C;
+use crate::gpio::{GpioMode, GpioBias, pin_mode, set_bias, digital_read, digital_write};
+use crate::analog::analog_read;
+use crate::time::{setup_pwm, pwm_write, setup_servo, servo_write, start_time, millis, micros};
+use core::fmt::{Arguments, Write};
+use heapless::{Vec, String};
+use rtt_target::rprintln;
+
+// Messages
+const DIGITAL_MESSAGE: u8 = 0x90;
+const ANALOG_MESSAGE: u8 = 0xE0;
+const REPORT_ANALOG: u8 = 0xC0;
+const REPORT_DIGITAL: u8 = 0xD0;
+const SET_PIN_MODE: u8 = 0xF4;
+const SET_DIGITAL_PIN_VALUE: u8 = 0xF5;
+const REPORT_VERSION: u8 = 0xF9;
+const SYSTEM_RESET: u8 = 0xFF;
+const START_SYSEX: u8 = 0xF0;
+const END_SYSEX: u8 = 0xF7;
+
+// Sysex commands
+const ANALOG_MAPPING_QUERY: u8 = 0x69;
+const ANALOG_MAPPING_RESPONSE: u8 = 0x6A;
+const CAPABILITY_QUERY: u8 = 0x6B;
+const CAPABILITY_RESPONSE: u8 = 0x6C;
+const PIN_STATE_QUERY: u8 = 0x6D;
+const PIN_STATE_RESPONSE: u8 = 0x6E;
+const EXTENDED_ANALOG: u8 = 0x6F;
+const SERVO_CONFIG: u8 = 0x70;
+const STRING_DATA: u8 = 0x71;
+const I2C_REQUEST: u8 = 0x76;
+const I2C_REPLY: u8 = 0x77;
+const I2C_CONFIG: u8 = 0x78;
+const REPORT_FIRMWARE: u8 = 0x79;
+const SAMPLING_INTERVAL: u8 = 0x7A;
+
+// Pin modes
+const MODE_INPUT: u8 = 0x00;
+const MODE_OUTPUT: u8 = 0x01;
+const MODE_ANALOG: u8 = 0x02;
+const MODE_PWM: u8 = 0x03;
+const MODE_SERVO: u8 = 0x04;
+const MODE_I2C: u8 = 0x06;
+const MODE_PULLUP: u8 = 0x0B;
+const UNCONFIGURED: u8 = 0x7F;
+
+const PROTOCOL_VERSION: (u8, u8) = (2, 5);
+const FIRMWARE_NAME: &str = "rustuino";
+const DEFAULT_SAMPLING_INTERVAL: usize = 19;
+// Standard pulse widths of the Arduino servo library
+const DEFAULT_SERVO_RANGE: (u16, u16) = (544, 2400);
+const SYSEX_SIZE: usize = 64;
+const MAX_I2C_QUERIES: usize = 8;
+const MAX_I2C_BYTES: usize = 32;
+
+// Pins are numbered block by block with 16 pins each, PA0 is 0, PB0 is 16 and PH0 is 64
+const BLOCKS: [char; 5] = ['a', 'b', 'c', 'd', 'h'];
+/// Number of pins that are reported to the client.
+pub const PIN_COUNT: usize = 16 * BLOCKS.len();
+const PORT_COUNT: usize = PIN_COUNT / 8;
+
+/// StandardFirmata compatible server on a U(S)ART port. Digital, analog, PWM and servo pins are
+/// configured on request of the client. As pins can only be configured once, the mode of a pin
+/// can not be changed afterwards, except between input and pull-up. The pins of a timer run either
+/// PWM or servos, as servos need their own timer period.
+///
+/// The pins are numbered block by block with 16 numbers each: PA0 is pin 0, PB0 is pin 16, PC0 is
+/// pin 32, PD2 is pin 50 and PH0 is pin 64. Analog channels are numbered in the order of the
+/// analog pins. Analog messages only reach pins 0 to 15, the other pins are written with extended
+/// analog messages.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustuino::*;
+/// use rustuino::uart::*;
+/// use rustuino::i2c::*;
+/// use rustuino::firmata::*;
+///
+/// let serial = UART::new(2, PA2, PA3, 57600, UART_8N1).unwrap();
+/// let mut i2c: I2C<32> = I2C::new(1, PB8, PB9, false, 0).unwrap();
+///
+/// let mut firmata = Firmata::new(&serial).i2c(&mut i2c);
+/// firmata.run().unwrap();
+/// ```
+pub struct Firmata<'a> {
+  uart: &'a UART,
+  i2c: Option<&'a mut dyn Bus>,
+  modes: [u8; PIN_COUNT],
+  states: [u16; PIN_COUNT],
+  servo_ranges: [(u16, u16); PIN_COUNT],
+  timer_modes: [u8; 4],
+  report_digital: u16,
+  ports: [u8; PORT_COUNT],
+  report_analog: u16,
+  sampling_interval: usize,
+  last_sample: usize,
+  i2c_delay: usize,
+  i2c_queries: Vec<I2cQuery, MAX_I2C_QUERIES>,
+  command: u8,
+  data: Vec<u8, SYSEX_SIZE>,
+  sysex: bool
+}
+
+// Read request of the client that is repeated every sampling interval
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct I2cQuery {
+  address: u8,
+  register: Option<u8>,
+  len: u8,
+  restart: bool
+}
+
+// Gives the server access to an I2C port independent of its buffer size
+trait Bus {
+  fn write_bytes(&mut self, address: u8, data: &[u8], stop: bool) -> Result<(), I2cError>;
+  fn read_bytes(&mut self, address: u8, buffer: &mut [u8]) -> Result<usize, I2cError>;
+}
+
+impl<'a> Firmata<'a> {
+  /// Creates a server on the port. The millisecond timer is started if it is not running yet.
+  pub fn new(uart: &'a UART) -> Self {
+    let peripheral_ptr = stm_peripherals();
+    let rcc = &peripheral_ptr.RCC;
+
+    if rcc.apb1enr.read().tim7en().is_disabled() == true {start_time();}
+
+    return Self {
+      uart,
+      i2c: None,
+      modes: [UNCONFIGURED; PIN_COUNT],
+      states: [0; PIN_COUNT],
+      servo_ranges: [DEFAULT_SERVO_RANGE; PIN_COUNT],
+      timer_modes: [UNCONFIGURED; 4],
+      report_digital: 0,
+      ports: [0; PORT_COUNT],
+      report_analog: 0,
+      sampling_interval: DEFAULT_SAMPLING_INTERVAL,
+      last_sample: 0,
+      i2c_delay: 0,
+      i2c_queries: Vec::new(),
+      command: 0,
+      data: Vec::new(),
+      sysex: false
+    };
+  }
+
+  /// Passes I2C requests of the client to the port. The pins of the port are configured by
+  /// [I2C::new] and only marked as I2C pins by the client.
+  pub fn i2c<const N: usize>(mut self, i2c: &'a mut I2C<N>) -> Self {
+    self.i2c = Some(i2c);
+    return self;
+  }
+
+  /// Sends the protocol version and the firmware name, like StandardFirmata does after a reset.
+  pub fn start(&self) -> Result<(), SerialError> {
+    if let Err(error) = self.report_version() {return Err(error);}
+    return self.report_firmware();
+  }
+
+  /// Handles the received messages, reports changed digital ports and samples the analog pins
+  /// and I2C devices when the sampling interval has passed.
+  pub fn poll(&mut self) -> Result<(), SerialError> {
+    while let Some(byte) = self.uart.try_read() {
+      if let Err(error) = self.process(byte) {return Err(error);}
+    }
+
+    for port in 0..PORT_COUNT {
+      if self.report_digital & (1 << port) > 0 {
+        if let Err(error) = self.report_port(port, false) {return Err(error);}
+      }
+    }
+
+    let now = millis();
+    if now.wrapping_sub(self.last_sample) >= self.sampling_interval {
+      self.last_sample = now;
+      if let Err(error) = self.sample() {return Err(error);}
+    }
+
+    return Ok(());
+  }
+
+  /// Sends the version and polls the server until an error occurs on the port.
+  pub fn run(&mut self) -> Result<(), SerialError> {
+    if let Err(error) = self.start() {return Err(error);}
+
+    loop {
+      if let Err(error) = self.poll() {return Err(error);}
+    }
+  }
+
+  fn process(&mut self, byte: u8) -> Result<(), SerialError> {
+    // A command byte in a sysex message aborts it
+    if self.sysex == true && (byte & 0x80 == 0 || byte == END_SYSEX) {
+      if byte == END_SYSEX {
+        self.sysex = false;
+        let data = self.data.clone();
+        return self.handle_sysex(&data);
+      }
+
+      // Messages that do not fit are dropped, the rest is ignored as data without a command
+      if let Err(_) = self.data.push(byte) {self.sysex = false;}
+      return Ok(());
+    }
+    self.sysex = false;
+
+    if byte & 0x80 > 0 {
+      self.data.clear();
+      self.command = byte;
+
+      match byte {
+        START_SYSEX => self.sysex = true,
+        REPORT_VERSION => {
+          self.command = 0;
+          return self.report_version();
+        },
+        SYSTEM_RESET => {
+          self.command = 0;
+          self.reset();
+        },
+        _ => ()
+      };
+
+      return Ok(());
+    }
+
+    if self.command == 0 {return Ok(());}
+    let _ = self.data.push(byte);
+
+    let expected = match self.command {
+      SET_PIN_MODE | SET_DIGITAL_PIN_VALUE => 2,
+      command if command & 0xF0 == DIGITAL_MESSAGE || command & 0xF0 == ANALOG_MESSAGE => 2,
+      command if command & 0xF0 == REPORT_ANALOG || command & 0xF0 == REPORT_DIGITAL => 1,
+      _ => {
+        self.command = 0;
+        return Ok(());
+      }
+    };
+    if self.data.len() < expected {return Ok(());}
+
+    let command = self.command;
+    let first = self.data[0];
+    let second = if expected > 1 {self.data[1]} else {0};
+    self.command = 0;
+    self.data.clear();
+
+    match command {
+      SET_PIN_MODE => return self.set_pin_mode(first as usize, second),
+      SET_DIGITAL_PIN_VALUE => return self.write_pin(first as usize, second > 0),
+      _ => ()
+    };
+
+    let channel = (command & 0x0F) as usize;
+    match command & 0xF0 {
+      DIGITAL_MESSAGE => return self.write_port(channel, first | (second << 7)),
+      ANALOG_MESSAGE => return self.write_analog(channel, decode(first, second)),
+      REPORT_ANALOG => return self.enable_analog(channel, first > 0),
+      REPORT_DIGITAL => {
+        if channel >= PORT_COUNT {return Ok(());}
+
+        if first > 0 {
+          self.report_digital |= 1 << channel;
+          return self.report_port(channel, true);
+        }
+        self.report_digital &= !(1 << channel);
+        return Ok(());
+      },
+      _ => unreachable!()
+    };
+  }
+
+  fn handle_sysex(&mut self, data: &[u8]) -> Result<(), SerialError> {
+    if data.len() == 0 {return Ok(());}
+    let payload = &data[1..];
+
+    match data[0] {
+      REPORT_FIRMWARE => return self.report_firmware(),
+      CAPABILITY_QUERY => return self.report_capabilities(),
+      ANALOG_MAPPING_QUERY => return self.report_analog_mapping(),
+      PIN_STATE_QUERY if payload.len() > 0 => return self.report_pin_state(payload[0] as usize),
+      EXTENDED_ANALOG if payload.len() > 1 => {
+        let value = payload[1..].iter().take(3).enumerate().fold(0, |sum, (i, byte)| sum | ((*byte as u32) << (7 * i)));
+        return self.write_analog(payload[0] as usize, value.min(u16::MAX as u32) as u16);
+      },
+      SERVO_CONFIG if payload.len() > 4 => {
+        let pin = payload[0] as usize;
+        if pin >= PIN_COUNT {return Ok(());}
+
+        self.servo_ranges[pin] = (decode(payload[1], payload[2]), decode(payload[3], payload[4]));
+        return self.set_pin_mode(pin, MODE_SERVO);
+      },
+      SAMPLING_INTERVAL if payload.len() > 1 => {
+        self.sampling_interval = (decode(payload[0], payload[1]) as usize).max(1);
+        return Ok(());
+      },
+      I2C_CONFIG => {
+        if payload.len() > 1 {self.i2c_delay = decode(payload[0], payload[1]) as usize;}
+        return Ok(());
+      },
+      I2C_REQUEST if payload.len() > 1 => return self.i2c_request(payload),
+      _ => return Ok(())
+    };
+  }
+
+  // Pins that were configured before can only switch between input and pull-up
+  fn set_pin_mode(&mut self, pin: usize, mode: u8) -> Result<(), SerialError> {
+    let target = match pin_from_number(pin as u8) {
+      Some(value) => value,
+      None => return self.error(format_args!("Pin {} does not exist", pin))
+    };
+
+    let current = self.modes[pin];
+    if current == mode {return Ok(());}
+
+    // PWM and servo pins set the period of the whole timer
+    let timer = pwm_timer(target);
+    if let Some(index) = timer {
+      let timer_mode = self.timer_modes[index];
+      if (mode == MODE_PWM || mode == MODE_SERVO) && timer_mode != UNCONFIGURED && timer_mode != mode {
+        let running = if timer_mode == MODE_SERVO {"servos"} else {"PWM"};
+        rprintln!("P{}{} can not be set to mode {}, its timer already runs {}! | Firmata::poll()", target.0.to_uppercase(), target.1, mode, running);
+        return self.error(format_args!("P{}{} can not be set to mode {}, its timer already runs {}", target.0.to_uppercase(), target.1, mode, running));
+      }
+    }
+
+    let configured = match (current, mode) {
+      (MODE_INPUT, MODE_PULLUP) => set_bias(target, GpioBias::Pullup).is_ok(),
+      (MODE_PULLUP, MODE_INPUT) => set_bias(target, GpioBias::None).is_ok(),
+      (UNCONFIGURED, MODE_INPUT) => pin_mode(target, GpioMode::Input).is_ok(),
+      (UNCONFIGURED, MODE_PULLUP) => pin_mode(target, GpioMode::Input).is_ok() && set_bias(target, GpioBias::Pullup).is_ok(),
+      (UNCONFIGURED, MODE_OUTPUT) => pin_mode(target, GpioMode::Output).is_ok(),
+      (UNCONFIGURED, MODE_ANALOG) => analog_channel(target).is_some() && pin_mode(target, GpioMode::Analog).is_ok(),
+      (UNCONFIGURED, MODE_PWM) => setup_pwm(target).is_ok(),
+      (UNCONFIGURED, MODE_SERVO) => setup_servo(target).is_ok(),
+      (UNCONFIGURED, MODE_I2C) => is_i2c_pin(target),
+      _ => false
+    };
+
+    if configured == false {
+      rprintln!("P{}{} can not be set to mode {}! | Firmata::poll()", target.0.to_uppercase(), target.1, mode);
+      return self.error(format_args!("P{}{} can not be set to mode {}", target.0.to_uppercase(), target.1, mode));
+    }
+
+    self.modes[pin] = mode;
+    self.states[pin] = 0;
+    if let Some(index) = timer {
+      if mode == MODE_PWM || mode == MODE_SERVO {self.timer_modes[index] = mode;}
+    }
+
+    return Ok(());
+  }
+
+  fn write_pin(&mut self, pin: usize, value: bool) -> Result<(), SerialError> {
+    if pin >= PIN_COUNT || self.modes[pin] != MODE_OUTPUT {return Ok(());}
+
+    let target = pin_from_number(pin as u8).unwrap();
+    if let Err(error) = digital_write(target, value) {
+      return self.error(format_args!("P{}{} could not be written: {:?}", target.0.to_uppercase(), target.1, error));
+    }
+    self.states[pin] = value as u16;
+
+    return Ok(());
+  }
+
+  // Only the output pins of the port are written
+  fn write_port(&mut self, port: usize, value: u8) -> Result<(), SerialError> {
+    if port >= PORT_COUNT {return Ok(());}
+
+    for bit in 0..8 {
+      if let Err(error) = self.write_pin(port * 8 + bit, value & (1 << bit) > 0) {return Err(error);}
+    }
+
+    return Ok(());
+  }
+
+  // Servos take an angle in degrees or a pulse width in microseconds like the Arduino servo library
+  fn write_analog(&mut self, pin: usize, value: u16) -> Result<(), SerialError> {
+    let target = match pin_from_number(pin as u8) {
+      Some(value) => value,
+      None => return Ok(())
+    };
+    let result = match self.modes[pin] {
+      MODE_PWM => pwm_write(target, value.min(255) as u8),
+      MODE_SERVO => {
+        let (min, max) = self.servo_ranges[pin];
+        let pulse = if value < DEFAULT_SERVO_RANGE.0 {
+          min + ((max.saturating_sub(min) as u32 * value.min(180) as u32) / 180) as u16
+        }
+        else {value};
+        servo_write(target, pulse)
+      },
+      _ => return Ok(())
+    };
+
+    if let Err(error) = result {
+      return self.error(format_args!("P{}{} could not be written: {:?}", target.0.to_uppercase(), target.1, error));
+    }
+    self.states[pin] = value;
+
+    return Ok(());
+  }
+
+  // Pins that are still unconfigured are switched to analog input
+  fn enable_analog(&mut self, channel: usize, enable: bool) -> Result<(), SerialError> {
+    let target = match analog_pin(channel) {
+      Some(value) => value,
+      None => return Ok(())
+    };
+
+    if enable == false {
+      self.report_analog &= !(1 << channel);
+      return Ok(());
+    }
+
+    let pin = pin_number(target) as usize;
+    if self.modes[pin] == UNCONFIGURED {
+      if let Err(error) = self.set_pin_mode(pin, MODE_ANALOG) {return Err(error);}
+    }
+    self.report_analog |= 1 << channel;
+
+    return Ok(());
+  }
+
+  fn i2c_request(&mut self, payload: &[u8]) -> Result<(), SerialError> {
+    let address = payload[0];
+    let flags = payload[1];
+    let restart = flags & 0x40 > 0;
+
+    if flags & 0x20 > 0 {return self.error(format_args!("10-bit I2C addresses are not supported"));}
+    if self.i2c.is_none() == true {return self.error(format_args!("No I2C port available"));}
+
+    let mut bytes: Vec<u8, MAX_I2C_BYTES> = Vec::new();
+    for pair in payload[2..].chunks_exact(2) {
+      if let Err(_) = bytes.push(decode(pair[0], pair[1]) as u8) {return self.error(format_args!("I2C request is too long"));}
+    }
+
+    match (flags >> 3) & 0x03 {
+      // Write
+      0 => {
+        let result = match self.i2c.as_mut() {
+          Some(i2c) => i2c.write_bytes(address, &bytes, true),
+          None => unreachable!()
+        };
+        if let Err(error) = result {return self.error(format_args!("I2C write failed: {:?}", error));}
+      },
+      // Read once or continuously
+      mode @ 1..=2 => {
+        let query = match bytes.len() {
+          1 => I2cQuery {address, register: None, len: bytes[0], restart},
+          2 => I2cQuery {address, register: Some(bytes[0]), len: bytes[1], restart},
+          _ => return self.error(format_args!("Invalid I2C read request"))
+        };
+
+        if mode == 1 {return self.i2c_read(query);}
+
+        if self.i2c_queries.contains(&query) == false {
+          if let Err(_) = self.i2c_queries.push(query) {return self.error(format_args!("Too many I2C queries"));}
+        }
+      },
+      // Stop reading
+      _ => {
+        while let Some(index) = self.i2c_queries.iter().position(|query| query.address == address) {
+          self.i2c_queries.swap_remove(index);
+        }
+      }
+    };
+
+    return Ok(());
+  }
+
+  fn i2c_read(&mut self, query: I2cQuery) -> Result<(), SerialError> {
+    let mut buffer = [0; MAX_I2C_BYTES];
+    let len = (query.len as usize).min(MAX_I2C_BYTES);
+    let delay = self.i2c_delay;
+
+    let i2c = match self.i2c.as_mut() {
+      Some(value) => value,
+      None => return Ok(())
+    };
+
+    let mut result = Ok(0);
+    if let Some(register) = query.register {
+      result = i2c.write_bytes(query.address, &[register], query.restart == false).map(|_| 0);
+      let start = micros();
+      while micros().wrapping_sub(start) < delay {}
+    }
+    if result.is_ok() == true {result = i2c.read_bytes(query.address, &mut buffer[..len]);}
+
+    let count = match result {
+      Ok(value) => value,
+      Err(error) => return self.error(format_args!("I2C read failed: {:?}", error))
+    };
+
+    // A missing register is reported as 0xFF like StandardFirmata does
+    let register = query.register.unwrap_or(0xFF);
+    if let Err(error) = self.send(&[START_SYSEX, I2C_REPLY, query.address & 0x7F, query.address >> 7]) {return Err(error);}
+    if let Err(error) = self.send_value(register as u16) {return Err(error);}
+    for byte in &buffer[..count] {
+      if let Err(error) = self.send_value(*byte as u16) {return Err(error);}
+    }
+
+    return self.send(&[END_SYSEX]);
+  }
+
+  fn sample(&mut self) -> Result<(), SerialError> {
+    for channel in 0..16 {
+      if self.report_analog & (1 << channel) == 0 {continue;}
+
+      let target = match analog_pin(channel) {
+        Some(value) => value,
+        None => continue
+      };
+      if self.modes[pin_number(target) as usize] != MODE_ANALOG {continue;}
+
+      if let Ok(value) = analog_read(target) {
+        let message = [ANALOG_MESSAGE | channel as u8, (value & 0x7F) as u8, ((value >> 7) & 0x7F) as u8];
+        if let Err(error) = self.send(&message) {return Err(error);}
+      }
+    }
+
+    for index in 0..self.i2c_queries.len() {
+      let query = self.i2c_queries[index];
+      if let Err(error) = self.i2c_read(query) {return Err(error);}
+    }
+
+    return Ok(());
+  }
+
+  // The input pins of the port are sent if they changed or if the report is forced
+  fn report_port(&mut self, port: usize, force: bool) -> Result<(), SerialError> {
+    let mut value: u8 = 0;
+
+    for bit in 0..8 {
+      let pin = port * 8 + bit;
+      if self.modes[pin] != MODE_INPUT && self.modes[pin] != MODE_PULLUP {continue;}
+
+      if let Ok(true) = digital_read(pin_from_number(pin as u8).unwrap()) {value |= 1 << bit;}
+    }
+
+    if value == self.ports[port] && force == false {return Ok(());}
+    self.ports[port] = value;
+
+    return self.send(&[DIGITAL_MESSAGE | port as u8, value & 0x7F, value >> 7]);
+  }
+
+  fn report_version(&self) -> Result<(), SerialError> {
+    return self.send(&[REPORT_VERSION, PROTOCOL_VERSION.0, PROTOCOL_VERSION.1]);
+  }
+
+  fn report_firmware(&self) -> Result<(), SerialError> {
+    if let Err(error) = self.send(&[START_SYSEX, REPORT_FIRMWARE, PROTOCOL_VERSION.0, PROTOCOL_VERSION.1]) {return Err(error);}
+    for byte in FIRMWARE_NAME.bytes() {
+      if let Err(error) = self.send_value(byte as u16) {return Err(error);}
+    }
+
+    return self.send(&[END_SYSEX]);
+  }
+
+  // Every pin lists pairs of mode and resolution, terminated by 0x7F
+  fn report_capabilities(&self) -> Result<(), SerialError> {
+    if let Err(error) = self.send(&[START_SYSEX, CAPABILITY_RESPONSE]) {return Err(error);}
+
+    for number in 0..PIN_COUNT {
+      if let Some(pin) = pin_from_number(number as u8) {
+        if let Err(error) = self.send(&[MODE_INPUT, 1, MODE_OUTPUT, 1, MODE_PULLUP, 1]) {return Err(error);}
+        if analog_channel(pin).is_some() == true {
+          if let Err(error) = self.send(&[MODE_ANALOG, 10]) {return Err(error);}
+        }
+        // Only the mode its timer already runs in is left for a PWM pin
+        if let Some(index) = pwm_timer(pin) {
+          if self.timer_modes[index] != MODE_SERVO {
+            if let Err(error) = self.send(&[MODE_PWM, 8]) {return Err(error);}
+          }
+          if self.timer_modes[index] != MODE_PWM {
+            if let Err(error) = self.send(&[MODE_SERVO, 14]) {return Err(error);}
+          }
+        }
+        if is_i2c_pin(pin) == true {
+          if let Err(error) = self.send(&[MODE_I2C, 1]) {return Err(error);}
+        }
+      }
+      if let Err(error) = self.send(&[UNCONFIGURED]) {return Err(error);}
+    }
+
+    return self.send(&[END_SYSEX]);
+  }
+
+  fn report_analog_mapping(&self) -> Result<(), SerialError> {
+    if let Err(error) = self.send(&[START_SYSEX, ANALOG_MAPPING_RESPONSE]) {return Err(error);}
+
+    for number in 0..PIN_COUNT {
+      let channel = match pin_from_number(number as u8).and_then(analog_channel) {
+        Some(value) => value,
+        None => 0x7F
+      };
+      if let Err(error) = self.send(&[channel]) {return Err(error);}
+    }
+
+    return self.send(&[END_SYSEX]);
+  }
+
+  // Digital pins report their level, all other pins the last written value
+  fn report_pin_state(&self, pin: usize) -> Result<(), SerialError> {
+    let target = match pin_from_number(pin as u8) {
+      Some(value) => value,
+      None => return Ok(())
+    };
+
+    let (mode, state) = match self.modes[pin] {
+      UNCONFIGURED => (MODE_INPUT, 0),
+      mode @ (MODE_INPUT | MODE_PULLUP | MODE_OUTPUT) => (mode, digital_read(target).unwrap_or(false) as u16),
+      mode => (mode, self.states[pin])
+    };
+
+    if let Err(error) = self.send(&[START_SYSEX, PIN_STATE_RESPONSE, pin as u8, mode]) {return Err(error);}
+    if let Err(error) = self.send_value(state) {return Err(error);}
+
+    return self.send(&[END_SYSEX]);
+  }
+
+  // The configured pins keep their mode, as they can not be released
+  fn reset(&mut self) {
+    self.report_digital = 0;
+    self.ports = [0; PORT_COUNT];
+    self.report_analog = 0;
+    self.sampling_interval = DEFAULT_SAMPLING_INTERVAL;
+    self.i2c_delay = 0;
+    self.i2c_queries.clear();
+    self.data.clear();
+    self.sysex = false;
+  }
+
+  fn error(&self, args: Arguments) -> Result<(), SerialError> {
+    let mut message: String<SYSEX_SIZE> = String::new();
+    let _ = message.write_fmt(args);
+
+    if let Err(error) = self.send(&[START_SYSEX, STRING_DATA]) {return Err(error);}
+    for byte in message.bytes() {
+      if let Err(error) = self.send_value(byte as u16) {return Err(error);}
+    }
+
+    return self.send(&[END_SYSEX]);
+  }
+
+  fn send(&self, data: &[u8]) -> Result<(), SerialError> {
+    for byte in data {
+      if let Err(error) = self.uart.write(*byte) {return Err(error);}
+    }
+
+    return Ok(());
+  }
+
+  // Values are sent as two 7-bit bytes, LSB first
+  fn send_value(&self, value: u16) -> Result<(), SerialError> {
+    return self.send(&[(value & 0x7F) as u8, ((value >> 7) & 0x7F) as u8]);
+  }
+}
+
+impl<const N: usize> Bus for I2C<N> {
+  fn write_bytes(&mut self, address: u8, data: &[u8], stop: bool) -> Result<(), I2cError> {
+    self.begin_transmission(address);
+    for byte in data {
+      if let Err(_) = self.write(*byte) {return Err(I2cError::Prog(ProgError::InvalidConfiguration));}
+    }
+
+    return self.end_transmission(stop);
+  }
+
+  fn read_bytes(&mut self, address: u8, buffer: &mut [u8]) -> Result<usize, I2cError> {
+    let len = match self.request_bytes(address, buffer.len() as u8, true) {
+      Ok(value) => value,
+      Err(error) => return Err(error)
+    };
+
+    // The receive buffer is read from the back
+    for index in (0..len).rev() {
+      if let Some(byte) = self.read() {buffer[index] = byte;}
+    }
+
+    return Ok(len);
+  }
+}
+
+
+// Public Functions ===============================================================================
+/// Firmata pin number of a pin.
+pub fn pin_number(pin: (char, u8)) -> u8 {
+  let block = BLOCKS.iter().position(|&block| block == pin.0).unwrap_or(0);
+  return (block * 16) as u8 + pin.1;
+}
+
+/// Pin of a Firmata pin number, if the pin exists.
+pub fn pin_from_number(number: u8) -> Option<(char, u8)> {
+  let block = match BLOCKS.get(number as usize / 16) {
+    Some(value) => *value,
+    None => return None
+  };
+  let pin = number % 16;
+
+  if (block == 'd' && pin != 2) || (block == 'h' && pin > 1) {return None;}
+  return Some((block, pin));
+}
+
+
+// Private Functions ==============================================================================
+// The DAC pins are listed in the ADC map with ADC 0
+fn analog_pin(channel: usize) -> Option<(char, u8)> {
+  return ADC_MAP.pins.iter().zip(ADC_MAP.adcs.iter()).filter(|&(_, &adc)| adc != 0).map(|(&pin, _)| pin).nth(channel);
+}
+
+fn analog_channel(pin: (char, u8)) -> Option<u8> {
+  return ADC_MAP.pins.iter().zip(ADC_MAP.adcs.iter()).filter(|&(_, &adc)| adc != 0).position(|(&i, _)| i == pin).map(|channel| channel as u8);
+}
+
+// Index of the PWM timer of the pin, timer 1 is index 0
+fn pwm_timer(pin: (char, u8)) -> Option<usize> {
+  return PWM_MAP.pins.iter().position(|&i| i == pin).map(|index| (PWM_MAP.timers[index] - 1) as usize);
+}
+
+fn is_i2c_pin(pin: (char, u8)) -> bool {
+  return I2C_MAP.scl_pins.contains(&pin) || I2C_MAP.sda_pins.contains(&pin);
+}
+
+fn decode(lsb: u8, msb: u8) -> u16 {
+  return (lsb & 0x7F) as u16 | ((msb & 0x7F) as u16) << 7;
+}
@@ -0,0 +1,68 @@
+//! This module contains the COBS and SLIP packet framing with optional CRC trailers.
+
+use crate::include::FramingError;
+use crate::uart::UART;
+pub use rustuino_core::framing::*;
+
+
+// UART Functions =================================================================================
+impl UART {
+  /// Sends the packet encoded and with the CRC trailer. The packet is encoded while it is sent, so
+  /// no buffer is needed.
+  ///
+  /// # Example
+  ///
+  /// ```rust,no_run
+  /// use rustuino::*;
+  /// use rustuino::uart::*;
+  /// use rustuino::framing::*;
+  ///
+  /// let serial = UART::new(2, PA2, PA3, 115200, UART_8N1).unwrap();
+  /// serial.send_packet(&[0x01, 0x00, 0x02], Encoding::Cobs, Crc::Crc16).unwrap();
+  /// ```
+  pub fn send_packet(&self, data: &[u8], encoding: Encoding, crc: Crc) -> Result<(), FramingError> {
+    return encode_with(encoding, crc, data, |byte| {
+      match self.write(byte) {
+        Ok(_) => return Ok(()),
+        Err(error) => return Err(FramingError::Serial(error))
+      };
+    });
+  }
+
+  /// Reads bytes into the decoder until a packet is complete and returns it. The timeout of the
+  /// port applies to every single byte.
+  ///
+  /// # Example
+  ///
+  /// ```rust,no_run
+  /// use rustuino::*;
+  /// use rustuino::uart::*;
+  /// use rustuino::framing::*;
+  ///
+  /// let serial = UART::new(2, PA2, PA3, 115200, UART_8N1).unwrap();
+  /// let mut decoder: Decoder<64> = Decoder::new(Encoding::Cobs, Crc::Crc16);
+  ///
+  /// loop {
+  ///   match serial.receive_packet(&mut decoder) {
+  ///     Ok(packet) => rprintln!("Packet: {:?}", packet),
+  ///     Err(error) => rprintln!("Broken packet: {:?}", error)
+  ///   };
+  /// }
+  /// ```
+  pub fn receive_packet<'a, const N: usize>(&self, decoder: &'a mut Decoder<N>) -> Result<&'a [u8], FramingError> {
+    loop {
+      let byte = match self.timed_read() {
+        Ok(value) => value,
+        Err(error) => return Err(FramingError::Serial(error))
+      };
+
+      match decoder.feed(byte) {
+        Some(Ok(_)) => break,
+        Some(Err(error)) => return Err(error),
+        None => ()
+      };
+    }
+
+    return Ok(decoder.packet());
+  }
+}
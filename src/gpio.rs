@@ -482,7 +482,7 @@ pub fn return_pinmode(pin: (char, u8)) -> Result<GpioMode, GpioError> {
     _   => unreachable!()
   };
 
-  bits = (bits & (3 << (2 * pin.1))) >> pin.1;
+  bits = (bits & (3 << (2 * pin.1))) >> (2 * pin.1);
   
   if pin.1 > 7 {af = (af & (15 << (4 * (pin.1 - 8)))) >> (4 * (pin.1 - 8))}
   else {af = (af & (15 << (4 * pin.1))) >> (4 * pin.1)}
@@ -499,7 +499,7 @@ pub fn return_pinmode(pin: (char, u8)) -> Result<GpioMode, GpioError> {
 
 // Private Functions ==============================================================================
 fn check_pin(pin: (char, u8)) -> Result<(), ProgError> {
-  if pin.1 > 15 || (pin.1 != 2 && pin.0 == 'd') || ((pin.1 != 0 && pin.0 == 'h') && (pin.1 != 1 && pin.0 == 'h')) {
+  if ['a', 'b', 'c', 'd', 'h'].contains(&pin.0) == false || pin.1 > 15 || (pin.1 != 2 && pin.0 == 'd') || ((pin.1 != 0 && pin.0 == 'h') && (pin.1 != 1 && pin.0 == 'h')) {
     rprintln!("P{}{} is not an available GPIO Pin!", pin.0.to_uppercase(), pin.1);
     return Err(ProgError::InvalidConfiguration);
   }
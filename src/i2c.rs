@@ -1,11 +1,42 @@
 use crate::include::{stm_peripherals, I2cError, ProgError, I2C_MAP};
 use crate::gpio::{pin_mode, set_bias, GpioMode::AlternateFunction, GpioBias::Pullup};
+use crate::clocks::pclk1;
 use heapless::Vec;
 use rtt_target::rprintln;
+#[cfg(feature = "async")]
+use {crate::executor::WakerSlot, stm32f4::stm32f446::{NVIC, Interrupt, interrupt}, core::task::Poll, core::future::poll_fn};
 
-const BUS_FREQ: u32 = 16000000;
 const I2C_FREQ: u32 = 100000;
 
+// Status and control register bits used by the interrupt driven functions.
+#[cfg(feature = "async")]
+const SR1_SB: u32 = 1 << 0;
+#[cfg(feature = "async")]
+const SR1_ADDR: u32 = 1 << 1;
+#[cfg(feature = "async")]
+const SR1_BTF: u32 = 1 << 2;
+#[cfg(feature = "async")]
+const SR1_RXNE: u32 = 1 << 6;
+#[cfg(feature = "async")]
+const SR1_TXE: u32 = 1 << 7;
+#[cfg(feature = "async")]
+const CR1_START: u32 = 1 << 8;
+#[cfg(feature = "async")]
+const CR1_STOP: u32 = 1 << 9;
+#[cfg(feature = "async")]
+const CR1_ACK: u32 = 1 << 10;
+#[cfg(feature = "async")]
+const CR1_POS: u32 = 1 << 11;
+#[cfg(feature = "async")]
+const CR2_ITERREN: u32 = 1 << 8;
+#[cfg(feature = "async")]
+const CR2_ITEVTEN: u32 = 1 << 9;
+#[cfg(feature = "async")]
+const CR2_ITBUFEN: u32 = 1 << 10;
+
+#[cfg(feature = "async")]
+static WAKERS: [WakerSlot; 3] = [WakerSlot::new(), WakerSlot::new(), WakerSlot::new()];
+
 pub struct I2C<const N: usize> {
   core: u8,
   tx_buffer: Vec<u8, N>,
@@ -20,6 +51,7 @@ impl<const N: usize> I2C<N> {
     let rcc = &peripheral_ptr.RCC;
   
     let (ccr_t, rise_t) = calc_i2c_freq(I2C_FREQ);
+    let bus_mhz = pclk1() / 1000000;
 
     if I2C_MAP.scl_pins.iter().zip(I2C_MAP.sda_pins.iter()).zip(I2C_MAP.cores.iter()).any(|i| i == ((&scl_pin, &sda_pin), &core)) == false {
       return Err(ProgError::InvalidConfiguration);
@@ -41,7 +73,7 @@ impl<const N: usize> I2C<N> {
           return Err(ProgError::AlreadyConfigured);
         }
         rcc.apb1enr.modify(|_, w| w.i2c1en().enabled());
-        i2c1.cr2.modify(|_, w| unsafe {w.freq().bits(BUS_FREQ as u8)});
+        i2c1.cr2.modify(|_, w| unsafe {w.freq().bits(bus_mhz as u8)});
         i2c1.ccr.modify(|_, w| unsafe {w.ccr().bits(ccr_t as u16)});
         i2c1.trise.write(|w| w.trise().bits(rise_t as u8));
         if addr > 0 {if addr > 0 {i2c1.oar1.modify(|_, w| w.add().bits((addr << 1).into()));}}
@@ -57,7 +89,7 @@ impl<const N: usize> I2C<N> {
           return Err(ProgError::AlreadyConfigured);
         }
         rcc.apb1enr.modify(|_, w| w.i2c2en().enabled());
-        i2c2.cr2.modify(|_, w| unsafe {w.freq().bits(BUS_FREQ as u8)});
+        i2c2.cr2.modify(|_, w| unsafe {w.freq().bits(bus_mhz as u8)});
         i2c2.ccr.modify(|_, w| unsafe {w.ccr().bits(ccr_t as u16)});
         i2c2.trise.write(|w| w.trise().bits(rise_t as u8));
         if addr > 0 {i2c2.oar1.modify(|_, w| w.add().bits((addr << 1).into()));}
@@ -73,7 +105,7 @@ impl<const N: usize> I2C<N> {
           return Err(ProgError::AlreadyConfigured);
         }
         rcc.apb1enr.modify(|_, w| w.i2c3en().enabled());
-        i2c3.cr2.modify(|_, w| unsafe {w.freq().bits(BUS_FREQ as u8)});
+        i2c3.cr2.modify(|_, w| unsafe {w.freq().bits(bus_mhz as u8)});
         i2c3.ccr.modify(|_, w| unsafe {w.ccr().bits(ccr_t as u16)});
         i2c3.trise.write(|w| w.trise().bits(rise_t as u8));
         if addr > 0 {i2c3.oar1.modify(|_, w| w.add().bits((addr << 1).into()));}
@@ -463,13 +495,97 @@ impl<const N: usize> I2C<N> {
 }
 
 
+// Async Functions ================================================================================
+#[cfg(feature = "async")]
+impl<const N: usize> I2C<N> {
+  /// Async version of [I2C::end_transmission] that waits for the event interrupts instead of polling the flags.
+  pub async fn end_transmission_async(&mut self, stop: bool) -> Result<(), I2cError> {
+    let core = self.core;
+
+    modify_cr1(core, CR1_START, true);
+    if let Err(error) = wait_event(core, SR1_SB).await {return Err(error);}
+    write_dr(core, self.tx_addr);
+    if let Err(error) = wait_event(core, SR1_ADDR).await {return Err(error);}
+    let _sr = read_sr2(core);
+
+    for byte in self.tx_buffer.iter() {
+      write_dr(core, byte.clone());
+      if let Err(error) = wait_event(core, SR1_TXE).await {return Err(error);}
+    }
+
+    if stop == true {modify_cr1(core, CR1_STOP, true);}
+    else {modify_cr1(core, CR1_START, true);}
+
+    return Ok(());
+  }
+
+  /// Async version of [I2C::request_bytes] that waits for the event interrupts instead of polling the flags.
+  pub async fn request_bytes_async(&mut self, addr: u8, nbytes: u8, stop: bool) -> Result<usize, I2cError> {
+    let core = self.core;
+    let end = if stop == true {CR1_STOP} else {CR1_START};
+
+    if nbytes == 0 || nbytes as usize > N {
+      rprintln!("Cannot store number of bytes! ({}) | .request_bytes_async()", nbytes);
+      return Err(I2cError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    self.rx_buffer.clear();
+
+    modify_cr1(core, CR1_START, true);
+    if let Err(error) = wait_event(core, SR1_SB).await {return Err(error);}
+    write_dr(core, (addr << 1) + 1);
+    if nbytes == 1 {modify_cr1(core, CR1_ACK, false);}
+    if let Err(error) = wait_event(core, SR1_ADDR).await {return Err(error);}
+
+    if nbytes == 1 {
+      let _sr = read_sr2(core);
+      modify_cr1(core, end, true);
+      if let Err(error) = wait_event(core, SR1_RXNE).await {return Err(error);}
+      self.rx_buffer.push(read_dr(core)).unwrap();
+    }
+    else if nbytes == 2 {
+      modify_cr1(core, CR1_ACK, false);
+      modify_cr1(core, CR1_POS, true);
+      let _sr = read_sr2(core);
+      if let Err(error) = wait_event(core, SR1_BTF).await {return Err(error);}
+      modify_cr1(core, end, true);
+      self.rx_buffer.push(read_dr(core)).unwrap();
+      if let Err(error) = wait_event(core, SR1_RXNE).await {return Err(error);}
+      self.rx_buffer.push(read_dr(core)).unwrap();
+      modify_cr1(core, CR1_POS, false);
+    }
+    else {
+      let _sr = read_sr2(core);
+      for _ in 0..(nbytes - 3) {
+        if let Err(error) = wait_event(core, SR1_RXNE).await {return Err(error);}
+        self.rx_buffer.push(read_dr(core)).unwrap();
+      }
+      if let Err(error) = wait_event(core, SR1_BTF).await {return Err(error);}
+      modify_cr1(core, CR1_ACK, false);
+      self.rx_buffer.push(read_dr(core)).unwrap();
+      if let Err(error) = wait_event(core, SR1_BTF).await {return Err(error);}
+      modify_cr1(core, end, true);
+      self.rx_buffer.push(read_dr(core)).unwrap();
+      if let Err(error) = wait_event(core, SR1_RXNE).await {return Err(error);}
+      self.rx_buffer.push(read_dr(core)).unwrap();
+    }
+
+    modify_cr1(core, CR1_ACK, true);
+
+    return Ok(self.rx_buffer.len());
+  }
+}
+
+
 // Private Functions ==============================================================================
 fn calc_i2c_freq(freq: u32) -> (u32, u32) {
+  let bus_freq = pclk1();
+
   // (I2C_T / 2) / BUS_T ->  BUS_FREQ / (I2C_FREQ * 2)
-  let ccr_t = BUS_FREQ / (2 * freq);
+  let ccr_t = bus_freq / (2 * freq);
 
   // (1000ns / BUS_T) + 1 -> (BUS_FREQ / 1000000) + 1
-  let rise_t = (BUS_FREQ / 1000000) + 1;
+  let rise_t = (bus_freq / 1000000) + 1;
 
   return (ccr_t, rise_t);
 }
@@ -483,3 +599,163 @@ fn scan_i2c_error(sr: u16) -> Result<(), I2cError> {
   else if status & 0b0000000100000000 > 0 {return Err(I2cError::Bus);}
   else {return Ok(());}
 }
+
+
+// Private Async Functions ========================================================================
+#[cfg(feature = "async")]
+async fn wait_event(core: u8, flag: u32) -> Result<(), I2cError> {
+  return poll_fn(|cx| {
+    let sr1 = read_sr1(core);
+    if let Err(error) = scan_i2c_error(sr1 as u16) {return Poll::Ready(Err(error));}
+    if sr1 & flag > 0 {return Poll::Ready(Ok(()));}
+
+    WAKERS[(core - 1) as usize].register(cx.waker());
+    unsafe {
+      NVIC::unmask(i2c_interrupts(core).0);
+      NVIC::unmask(i2c_interrupts(core).1);
+    }
+    // RXNE and TXE only generate an event interrupt if the buffer interrupt is enabled as well.
+    if flag == SR1_RXNE || flag == SR1_TXE {modify_cr2(core, CR2_ITEVTEN | CR2_ITBUFEN | CR2_ITERREN, true);}
+    else {modify_cr2(core, CR2_ITEVTEN | CR2_ITERREN, true);}
+    return Poll::Pending;
+  }).await;
+}
+
+#[cfg(feature = "async")]
+fn read_sr1(core: u8) -> u32 {
+  let peripheral_ptr = stm_peripherals();
+
+  let bits = match core {
+    1 => peripheral_ptr.I2C1.sr1.read().bits(),
+    2 => peripheral_ptr.I2C2.sr1.read().bits(),
+    3 => peripheral_ptr.I2C3.sr1.read().bits(),
+    _ => unreachable!()
+  };
+
+  return bits;
+}
+
+#[cfg(feature = "async")]
+fn read_sr2(core: u8) -> u32 {
+  let peripheral_ptr = stm_peripherals();
+
+  let bits = match core {
+    1 => peripheral_ptr.I2C1.sr2.read().bits(),
+    2 => peripheral_ptr.I2C2.sr2.read().bits(),
+    3 => peripheral_ptr.I2C3.sr2.read().bits(),
+    _ => unreachable!()
+  };
+
+  return bits;
+}
+
+#[cfg(feature = "async")]
+fn read_dr(core: u8) -> u8 {
+  let peripheral_ptr = stm_peripherals();
+
+  let data = match core {
+    1 => peripheral_ptr.I2C1.dr.read().dr().bits(),
+    2 => peripheral_ptr.I2C2.dr.read().dr().bits(),
+    3 => peripheral_ptr.I2C3.dr.read().dr().bits(),
+    _ => unreachable!()
+  };
+
+  return data;
+}
+
+#[cfg(feature = "async")]
+fn write_dr(core: u8, data: u8) {
+  let peripheral_ptr = stm_peripherals();
+
+  match core {
+    1 => peripheral_ptr.I2C1.dr.write(|w| w.dr().bits(data)),
+    2 => peripheral_ptr.I2C2.dr.write(|w| w.dr().bits(data)),
+    3 => peripheral_ptr.I2C3.dr.write(|w| w.dr().bits(data)),
+    _ => unreachable!()
+  };
+}
+
+#[cfg(feature = "async")]
+fn modify_cr1(core: u8, mask: u32, set: bool) {
+  let peripheral_ptr = stm_peripherals();
+
+  match core {
+    1 => peripheral_ptr.I2C1.cr1.modify(|r, w| unsafe {w.bits(if set == true {r.bits() | mask} else {r.bits() & !mask})}),
+    2 => peripheral_ptr.I2C2.cr1.modify(|r, w| unsafe {w.bits(if set == true {r.bits() | mask} else {r.bits() & !mask})}),
+    3 => peripheral_ptr.I2C3.cr1.modify(|r, w| unsafe {w.bits(if set == true {r.bits() | mask} else {r.bits() & !mask})}),
+    _ => unreachable!()
+  };
+}
+
+#[cfg(feature = "async")]
+fn modify_cr2(core: u8, mask: u32, set: bool) {
+  let peripheral_ptr = stm_peripherals();
+
+  match core {
+    1 => peripheral_ptr.I2C1.cr2.modify(|r, w| unsafe {w.bits(if set == true {r.bits() | mask} else {r.bits() & !mask})}),
+    2 => peripheral_ptr.I2C2.cr2.modify(|r, w| unsafe {w.bits(if set == true {r.bits() | mask} else {r.bits() & !mask})}),
+    3 => peripheral_ptr.I2C3.cr2.modify(|r, w| unsafe {w.bits(if set == true {r.bits() | mask} else {r.bits() & !mask})}),
+    _ => unreachable!()
+  };
+}
+
+#[cfg(feature = "async")]
+fn i2c_interrupts(core: u8) -> (Interrupt, Interrupt) {
+  match core {
+    1 => return (Interrupt::I2C1_EV, Interrupt::I2C1_ER),
+    2 => return (Interrupt::I2C2_EV, Interrupt::I2C2_ER),
+    3 => return (Interrupt::I2C3_EV, Interrupt::I2C3_ER),
+    _ => unreachable!()
+  };
+}
+
+
+// Interrupts =====================================================================================
+#[cfg(feature = "async")]
+fn i2c_handler(core: u8) {
+  // The interrupts get masked again so they do not fire until the waiting future polls again.
+  modify_cr2(core, CR2_ITEVTEN | CR2_ITBUFEN | CR2_ITERREN, false);
+  WAKERS[(core - 1) as usize].wake();
+}
+
+#[cfg(feature = "async")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn I2C1_EV() {
+  i2c_handler(1);
+}
+
+#[cfg(feature = "async")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn I2C1_ER() {
+  i2c_handler(1);
+}
+
+#[cfg(feature = "async")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn I2C2_EV() {
+  i2c_handler(2);
+}
+
+#[cfg(feature = "async")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn I2C2_ER() {
+  i2c_handler(2);
+}
+
+#[cfg(feature = "async")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn I2C3_EV() {
+  i2c_handler(3);
+}
+
+#[cfg(feature = "async")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn I2C3_ER() {
+  i2c_handler(3);
+}
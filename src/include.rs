@@ -121,6 +121,45 @@ pub const UART_MAP: UARTMap = UARTMap {
   cores:   [1,   1,  1,   1,  3,   3,   3,   3,   3,   3,   4,  4,   4,   4,   5,   6]
 };
 
+// The RTS and CTS pins of USART6 are on port G, which is not available.
+pub struct UARTFlowMap {
+  pub rts_pins: [(char, u8); 3],
+  pub cts_pins: [(char, u8); 3],
+  pub cores: [u8; 3]
+}
+
+pub const UART_FLOW_MAP: UARTFlowMap = UARTFlowMap {
+  rts_pins: [A12, A1, B14],
+  cts_pins: [A11, A0, B13],
+  cores:    [1,   2,  3]
+};
+
+// Only the USARTs can generate a clock, UART4 and UART5 have no CK pin.
+pub struct UARTClockMap {
+  pub ck_pins: [(char, u8); 5],
+  pub cores: [u8; 5]
+}
+
+pub const UART_CLOCK_MAP: UARTClockMap = UARTClockMap {
+  ck_pins: [A8, A4, B12, C12, C8],
+  cores:   [1,  2,  3,   3,   6]
+};
+
+// Indexed by U(S)ART number - 1, RX and TX of a port are always on the same DMA controller.
+pub struct UARTDMAMap {
+  pub dmas: [u8; 6],
+  pub rx_streams: [usize; 6],
+  pub tx_streams: [usize; 6],
+  pub channels: [u32; 6]
+}
+
+pub const UART_DMA_MAP: UARTDMAMap = UARTDMAMap {
+  dmas:       [2, 1, 1, 1, 1, 2],
+  rx_streams: [2, 5, 1, 2, 0, 1],
+  tx_streams: [7, 6, 3, 4, 7, 6],
+  channels:   [4, 4, 4, 4, 4, 5]
+};
+
 pub struct I2CMap {
   pub scl_pins: [(char, u8); 9],
   pub sda_pins: [(char, u8); 9],
@@ -159,30 +198,7 @@ pub const SPI_DATA: SPIData = SPIData {
 
 
 // Embedded Errors ================================================================================
-/// A universal implementation specific error.
-///
-/// These error kinds can be used to signal implementation specific errors unrelated to the
-/// specific peripheral. This will be used for all sorts of connectivity problems, e.g. if an
-/// adapter to the peripheral is used or the target peripheral is connected to indirectly (like bus
-/// expanders) or an operating system is controlling the access and denying access.
-#[derive(Debug, Clone, PartialEq, Eq)]
-#[non_exhaustive]
-pub enum ProgError {
-  /// Unspecified internal driver error
-  Internal,
-  /// Ran out of memory while trying to allocate required buffers
-  OutOfMemory,
-  /// Operation timed out, please retry
-  TimedOut,
-  /// The peripheral cannot work with the specified settings
-  InvalidConfiguration,
-  /// Tried to use peripheral without configuring it properly
-  NotConfigured,
-  /// Tried to setup a peripheral that is already configured
-  AlreadyConfigured,
-  /// Invalid action
-  PermissionDenied
-}
+pub use rustuino_core::errors::{ProgError, SerialError, FramingError};
 
 /// This crate contains a variety of universal error types which can be used to universally model
 /// conditions which can typically arise for certain peripherals.
@@ -207,26 +223,82 @@ pub enum GpioError {
   Prog(ProgError)
 }
 
-/// A Serial specific error.
+/// A LIN specific error.
 ///
-/// This error type contains errors specific to Serial peripherals. Also it has an `Impl` kind to pass
-/// through implementation specific errors occurring while trying to use a Serial peripheral.
+/// This error type contains errors of the LIN frame layer. Errors of the underlying serial port are
+/// passed through with the `Serial` kind.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[non_exhaustive]
-pub enum SerialError {
-  /// The peripheral receive buffer was overrun.
-  Overrun,
-  /// Received data does not conform to the peripheral configuration.
-  /// Can be caused by a misconfigured device on either end of the serial line.
-  FrameFormat,
-  /// Parity check failed.
-  Parity,
-  /// Serial line is too noisy to read valid data.
-  Noise,
+pub enum LinError {
+  /// The checksum of a received frame is wrong.
+  Checksum,
+  /// The sync byte or the parity of the protected identifier is wrong.
+  InvalidHeader,
+  /// The data that was read back differs from the sent data, so another node sent at the same time.
+  BitError,
+  /// No node answered in time.
+  NoResponse,
+  /// Error of the serial port.
+  Serial(SerialError),
   /// Implementation specific error (shared across all peripheral specific error kinds).
   Prog(ProgError)
 }
 
+/// A smartcard specific error.
+///
+/// This error type contains errors of the ISO 7816 layer. Errors of the underlying serial port are
+/// passed through with the `Serial` kind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SmartcardError {
+  /// The answer to reset is malformed, uses the inverse convention or has a wrong checksum.
+  InvalidAtr,
+  /// The card sent an unexpected procedure byte or more data than fits in the response.
+  Protocol,
+  /// The card did not answer in time.
+  NoResponse,
+  /// Error of the serial port.
+  Serial(SerialError),
+  /// Implementation specific error (shared across all peripheral specific error kinds).
+  Prog(ProgError)
+}
+
+/// A Modbus specific error.
+///
+/// This error type contains errors of the Modbus RTU layer. Errors of the underlying serial port
+/// are passed through with the `Serial` kind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ModbusError {
+  /// The CRC of a received frame is wrong.
+  Crc,
+  /// The slave answered with the contained exception code.
+  Exception(u8),
+  /// The response does not belong to the request or has a wrong length.
+  InvalidResponse,
+  /// The slave did not answer in time.
+  NoResponse,
+  /// Error of the serial port.
+  Serial(SerialError),
+  /// Implementation specific error (shared across all peripheral specific error kinds).
+  Prog(ProgError)
+}
+
+/// A shell specific error.
+///
+/// This error type is returned by the commands of the shell. Errors of the underlying peripherals
+/// are passed through with the `Gpio` and `Serial` kinds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ShellError {
+  /// An argument is missing, superfluous or could not be parsed.
+  InvalidArgument,
+  /// Error of a GPIO operation.
+  Gpio(GpioError),
+  /// Error of the serial port.
+  Serial(SerialError)
+}
+
 /// An I2C specific error.
 ///
 /// This error type contains errors specific to I2C peripherals. Also it has an `Impl` kind to pass
@@ -10,7 +10,7 @@ pub use rtt_target::{rtt_init_print, rprint, rprintln};
 pub use include::pins::*;
 pub use gpio::*;
 pub use analog::{adc_resolution, analog_read, analog_write, analog_write_noise, analog_write_triangle, analog_wave_freq};
-pub use time::{pwm_write, delay, start_time, millis};
+pub use time::{pwm_write, delay, delay_sleep, start_time, millis, micros};
 
 
 // Submodule includes =============================================================================
@@ -21,6 +21,19 @@ pub mod time;
 pub mod uart;
 pub mod i2c;
 pub mod spi;
+pub mod rtc;
+pub mod watchdog;
+pub mod power;
+pub mod clocks;
+pub mod lin;
+pub mod smartcard;
+pub mod bridge;
+pub mod framing;
+pub mod modbus;
+pub mod shell;
+pub mod firmata;
+#[cfg(feature = "async")]
+pub mod executor;
 
 
 // Panic handler ==================================================================================
@@ -0,0 +1,328 @@
+//! This module contains the LIN frame layer, which runs on a U(S)ART in LIN mode.
+
+use crate::include::{stm_peripherals, LinError, SerialError, ProgError};
+use crate::uart::{UART, BreakLength};
+use crate::time::{start_time, millis};
+use heapless::Vec;
+use rtt_target::rprintln;
+
+const SYNC: u8 = 0x55;
+
+/// Represents the checksum model. LIN 1.x uses the classic checksum over the data bytes, LIN 2.x
+/// the enhanced checksum that also covers the protected identifier. The diagnostic frames 0x3C and
+/// 0x3D always use the classic checksum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumType {
+  Classic,
+  Enhanced
+}
+
+/// Represents a frame from the view of the own node.
+#[derive(Clone, Copy)]
+pub enum LinFrame {
+  /// The own node sends the response, the callback fills in up to 8 data bytes.
+  Publish(u8, fn(u8, &mut Vec<u8, 8>)),
+  /// Another node sends a response with the given length, which is passed to the callback.
+  Subscribe(u8, usize, fn(u8, &[u8]))
+}
+
+/// One entry of a master schedule table. The slot time includes the frame itself.
+#[derive(Clone, Copy)]
+pub struct ScheduleSlot {
+  pub frame: LinFrame,
+  pub slot_ms: usize
+}
+
+/// LIN master that sends the headers and runs the schedule table.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustuino::*;
+/// use rustuino::uart::*;
+/// use rustuino::lin::*;
+///
+/// fn lights(_id: u8, data: &mut Vec<u8, 8>) {
+///   data.push(0x01).unwrap();
+/// }
+///
+/// fn temperature(_id: u8, data: &[u8]) {
+///   rprintln!("Temperature: {}", data[0]);
+/// }
+///
+/// let schedule = [
+///   ScheduleSlot {frame: LinFrame::Publish(0x10, lights), slot_ms: 10},
+///   ScheduleSlot {frame: LinFrame::Subscribe(0x20, 2, temperature), slot_ms: 10}
+/// ];
+///
+/// let mut serial = UART::new(2, PA2, PA3, 19200, UART_8N1).unwrap();
+/// serial.set_timeout(5);
+/// let master = LinMaster::new(&serial, ChecksumType::Enhanced);
+///
+/// loop {
+///   if let Err(error) = master.run_schedule(&schedule) {rprintln!("LIN error: {:?}", error);}
+/// }
+/// ```
+pub struct LinMaster<'a> {
+  uart: &'a UART,
+  checksum: ChecksumType
+}
+
+/// LIN slave that answers the headers of the master from its response table.
+pub struct LinSlave<'a> {
+  uart: &'a UART,
+  checksum: ChecksumType,
+  table: &'a [LinFrame]
+}
+
+impl<'a> LinMaster<'a> {
+  /// Switches the port to LIN mode. The response timeout is the timeout of the port.
+  pub fn new(uart: &'a UART, checksum: ChecksumType) -> Self {
+    uart.enable_lin(BreakLength::Bits11);
+
+    return Self {
+      uart,
+      checksum
+    };
+  }
+
+  /// Sends a complete frame with the data of the master.
+  pub fn send_frame(&self, id: u8, data: &[u8]) -> Result<(), LinError> {
+    if data.len() == 0 || data.len() > 8 {
+      rprintln!("LIN frames carry between 1 and 8 data bytes! | LinMaster::send_frame()");
+      return Err(LinError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    if let Err(error) = self.send_header(id) {return Err(error);}
+    return send_response(self.uart, protected_id(id), data, self.checksum);
+  }
+
+  /// Sends a header and returns the response of the slave.
+  pub fn request_frame(&self, id: u8, len: usize) -> Result<Vec<u8, 8>, LinError> {
+    if len == 0 || len > 8 {
+      rprintln!("LIN frames carry between 1 and 8 data bytes! | LinMaster::request_frame()");
+      return Err(LinError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    if let Err(error) = self.send_header(id) {return Err(error);}
+    return receive_response(self.uart, protected_id(id), len, self.checksum);
+  }
+
+  /// Runs through the schedule table once. A failing frame does not stop the schedule, the first
+  /// error is returned at the end.
+  pub fn run_schedule(&self, table: &[ScheduleSlot]) -> Result<(), LinError> {
+    let mut result = Ok(());
+
+    for slot in table {
+      let start = now();
+
+      let frame_result = match slot.frame {
+        LinFrame::Publish(id, callback) => {
+          let mut data: Vec<u8, 8> = Vec::new();
+          callback(id, &mut data);
+          self.send_frame(id, &data)
+        },
+        LinFrame::Subscribe(id, len, callback) => {
+          match self.request_frame(id, len) {
+            Ok(data) => {
+              callback(id, &data);
+              Ok(())
+            },
+            Err(error) => Err(error)
+          }
+        }
+      };
+      if result.is_ok() == true {result = frame_result;}
+
+      while now().wrapping_sub(start) < slot.slot_ms {}
+    }
+
+    return result;
+  }
+
+  fn send_header(&self, id: u8) -> Result<(), LinError> {
+    if id > 0x3F {
+      rprintln!("LIN identifiers are between 0 and 63! | LinMaster::send_header()");
+      return Err(LinError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    self.uart.send_break();
+    // The own break was received as well
+    self.uart.break_detected();
+
+    let header = [SYNC, protected_id(id)];
+    for byte in header {
+      if let Err(error) = self.uart.write(byte) {return Err(LinError::Serial(error));}
+    }
+
+    return read_back(self.uart, &header);
+  }
+}
+
+impl<'a> LinSlave<'a> {
+  /// Switches the port to LIN mode. Frames that are not in the table are ignored.
+  pub fn new(uart: &'a UART, checksum: ChecksumType, table: &'a [LinFrame]) -> Self {
+    uart.enable_lin(BreakLength::Bits11);
+
+    return Self {
+      uart,
+      checksum,
+      table
+    };
+  }
+
+  /// Handles a header of the master if a break was received and returns immediately otherwise. Has
+  /// to be called often enough to answer within the response time of the bus.
+  ///
+  /// # Example
+  ///
+  /// ```rust,no_run
+  /// use rustuino::*;
+  /// use rustuino::uart::*;
+  /// use rustuino::lin::*;
+  ///
+  /// fn temperature(_id: u8, data: &mut Vec<u8, 8>) {
+  ///   data.extend_from_slice(&[21, 0]).unwrap();
+  /// }
+  ///
+  /// let table = [LinFrame::Publish(0x20, temperature)];
+  ///
+  /// let mut serial = UART::new(2, PA2, PA3, 19200, UART_8N1).unwrap();
+  /// serial.set_timeout(5);
+  /// let slave = LinSlave::new(&serial, ChecksumType::Enhanced, &table);
+  ///
+  /// loop {
+  ///   slave.poll().unwrap();
+  /// }
+  /// ```
+  pub fn poll(&self) -> Result<(), LinError> {
+    if self.uart.break_detected() == false {return Ok(());}
+
+    let sync = match self.uart.timed_read() {
+      Ok(byte) => byte,
+      Err(error) => return Err(map_error(error))
+    };
+    let pid = match self.uart.timed_read() {
+      Ok(byte) => byte,
+      Err(error) => return Err(map_error(error))
+    };
+
+    if sync != SYNC || protected_id(pid & 0x3F) != pid {return Err(LinError::InvalidHeader);}
+
+    let id = pid & 0x3F;
+    for frame in self.table {
+      match *frame {
+        LinFrame::Publish(frame_id, callback) if frame_id == id => {
+          let mut data: Vec<u8, 8> = Vec::new();
+          callback(id, &mut data);
+          return send_response(self.uart, pid, &data, self.checksum);
+        },
+        LinFrame::Subscribe(frame_id, len, callback) if frame_id == id => {
+          match receive_response(self.uart, pid, len, self.checksum) {
+            Ok(data) => {
+              callback(id, &data);
+              return Ok(());
+            },
+            Err(error) => return Err(error)
+          };
+        },
+        _ => ()
+      };
+    }
+
+    return Ok(());
+  }
+}
+
+
+// Public Functions ===============================================================================
+/// Adds the two parity bits to a 6-bit identifier.
+pub fn protected_id(id: u8) -> u8 {
+  let bit = |n: u8| (id >> n) & 1;
+
+  let p0 = bit(0) ^ bit(1) ^ bit(2) ^ bit(4);
+  let p1 = (bit(1) ^ bit(3) ^ bit(4) ^ bit(5)) ^ 1;
+
+  return (id & 0x3F) | (p0 << 6) | (p1 << 7);
+}
+
+/// Calculates the inverted sum with carry over the data and, for the enhanced checksum, the
+/// protected identifier.
+pub fn checksum(pid: u8, data: &[u8], checksum: ChecksumType) -> u8 {
+  let mut sum: u16 = 0;
+
+  if checksum == ChecksumType::Enhanced && pid & 0x3F < 0x3C {sum = pid as u16;}
+
+  for byte in data {
+    sum += *byte as u16;
+    if sum > 0xFF {sum -= 0xFF;}
+  }
+
+  return !(sum as u8);
+}
+
+
+// Private Functions ==============================================================================
+fn send_response(uart: &UART, pid: u8, data: &[u8], checksum_type: ChecksumType) -> Result<(), LinError> {
+  for byte in data {
+    if let Err(error) = uart.write(*byte) {return Err(LinError::Serial(error));}
+  }
+
+  let sum = checksum(pid, data, checksum_type);
+  if let Err(error) = uart.write(sum) {return Err(LinError::Serial(error));}
+
+  if let Err(error) = read_back(uart, data) {return Err(error);}
+  return read_back(uart, &[sum]);
+}
+
+fn receive_response(uart: &UART, pid: u8, len: usize, checksum_type: ChecksumType) -> Result<Vec<u8, 8>, LinError> {
+  let mut data: Vec<u8, 8> = Vec::new();
+
+  if len == 0 || len > 8 {
+    rprintln!("LIN frames carry between 1 and 8 data bytes! | receive_response()");
+    return Err(LinError::Prog(ProgError::InvalidConfiguration));
+  }
+
+  for _ in 0..len {
+    match uart.timed_read() {
+      Ok(byte) => data.push(byte).unwrap(),
+      Err(error) => return Err(map_error(error))
+    };
+  }
+
+  let sum = match uart.timed_read() {
+    Ok(byte) => byte,
+    Err(error) => return Err(map_error(error))
+  };
+  if sum != checksum(pid, &data, checksum_type) {return Err(LinError::Checksum);}
+
+  return Ok(data);
+}
+
+// The transceiver receives everything that is on the bus, including the own data
+fn read_back(uart: &UART, expected: &[u8]) -> Result<(), LinError> {
+  for byte in expected {
+    match uart.timed_read() {
+      Ok(value) if value == *byte => (),
+      Ok(_) => return Err(LinError::BitError),
+      Err(error) => return Err(map_error(error))
+    };
+  }
+
+  return Ok(());
+}
+
+fn map_error(error: SerialError) -> LinError {
+  match error {
+    SerialError::Prog(ProgError::TimedOut) => return LinError::NoResponse,
+    _ => return LinError::Serial(error)
+  };
+}
+
+fn now() -> usize {
+  let peripheral_ptr = stm_peripherals();
+  let rcc = &peripheral_ptr.RCC;
+
+  if rcc.apb1enr.read().tim7en().is_disabled() == true {start_time();}
+  return millis();
+}
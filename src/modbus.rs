@@ -0,0 +1,520 @@
+//! This module contains the Modbus RTU master and slave, which run on a U(S)ART.
+
+use crate::include::{stm_peripherals, ModbusError, SerialError, ProgError};
+use crate::uart::UART;
+use crate::time::{start_time, micros};
+use heapless::Vec;
+use rtt_target::rprintln;
+
+// Maximum length of an RTU frame including address and CRC
+const MAX_ADU: usize = 256;
+
+const ILLEGAL_FUNCTION: u8 = 0x01;
+const ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+const ILLEGAL_DATA_VALUE: u8 = 0x03;
+
+/// Modbus master that sends requests to the slaves on the bus. The response timeout is the timeout
+/// of the port, slave address 0 sends a broadcast without response.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustuino::*;
+/// use rustuino::uart::*;
+/// use rustuino::modbus::*;
+///
+/// let mut serial = UART::new(2, PA2, PA3, 19200, UART_8E1).unwrap();
+/// serial.set_timeout(100);
+/// let master = ModbusMaster::new(&serial);
+/// master.enable_rs485(PA1).unwrap();
+///
+/// let mut registers = [0; 4];
+/// master.read_holding_registers(1, 0x0000, &mut registers).unwrap();
+/// master.write_single_coil(1, 0x0010, true).unwrap();
+/// ```
+pub struct ModbusMaster<'a> {
+  uart: &'a UART,
+  gap: usize
+}
+
+/// Data of a slave. The addresses of every table start at 0.
+pub struct ModbusMap<'a> {
+  pub coils: &'a mut [bool],
+  pub discrete_inputs: &'a [bool],
+  pub holding_registers: &'a mut [u16],
+  pub input_registers: &'a [u16]
+}
+
+/// Modbus slave that answers the requests of the master from its map.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustuino::*;
+/// use rustuino::uart::*;
+/// use rustuino::modbus::*;
+///
+/// let serial = UART::new(2, PA2, PA3, 19200, UART_8E1).unwrap();
+///
+/// let mut coils = [false; 16];
+/// let inputs = [false; 8];
+/// let mut holding = [0; 10];
+/// let measurements = [0; 4];
+/// let map = ModbusMap {
+///   coils: &mut coils,
+///   discrete_inputs: &inputs,
+///   holding_registers: &mut holding,
+///   input_registers: &measurements
+/// };
+///
+/// let mut slave = ModbusSlave::new(&serial, 17, map);
+///
+/// loop {
+///   if slave.poll().unwrap_or(false) == true {
+///     digital_write(PA5, slave.map().coils[0]).unwrap();
+///   }
+/// }
+/// ```
+pub struct ModbusSlave<'a> {
+  uart: &'a UART,
+  address: u8,
+  map: ModbusMap<'a>,
+  gap: usize,
+  frame: Vec<u8, MAX_ADU>,
+  overflow: bool,
+  next: Option<u8>
+}
+
+impl<'a> ModbusMaster<'a> {
+  pub fn new(uart: &'a UART) -> Self {
+    return Self {
+      uart,
+      gap: frame_gap(uart)
+    };
+  }
+
+  /// Switches the port to RS-485 mode with the given driver enable pin.
+  pub fn enable_rs485(&self, de_pin: (char, u8)) -> Result<(), ModbusError> {
+    if let Err(error) = self.uart.enable_rs485(de_pin, 0, 0) {return Err(ModbusError::Serial(error));}
+    return Ok(());
+  }
+
+  /// Function code 1.
+  pub fn read_coils(&self, slave: u8, address: u16, coils: &mut [bool]) -> Result<(), ModbusError> {
+    return self.read_bits(0x01, slave, address, coils);
+  }
+
+  /// Function code 2.
+  pub fn read_discrete_inputs(&self, slave: u8, address: u16, inputs: &mut [bool]) -> Result<(), ModbusError> {
+    return self.read_bits(0x02, slave, address, inputs);
+  }
+
+  /// Function code 3.
+  pub fn read_holding_registers(&self, slave: u8, address: u16, registers: &mut [u16]) -> Result<(), ModbusError> {
+    return self.read_registers(0x03, slave, address, registers);
+  }
+
+  /// Function code 4.
+  pub fn read_input_registers(&self, slave: u8, address: u16, registers: &mut [u16]) -> Result<(), ModbusError> {
+    return self.read_registers(0x04, slave, address, registers);
+  }
+
+  /// Function code 5.
+  pub fn write_single_coil(&self, slave: u8, address: u16, value: bool) -> Result<(), ModbusError> {
+    let value: u16 = if value == true {0xFF00} else {0x0000};
+    let request = [slave, 0x05, (address >> 8) as u8, address as u8, (value >> 8) as u8, value as u8];
+
+    return self.write_echo(&request);
+  }
+
+  /// Function code 6.
+  pub fn write_single_register(&self, slave: u8, address: u16, value: u16) -> Result<(), ModbusError> {
+    let request = [slave, 0x06, (address >> 8) as u8, address as u8, (value >> 8) as u8, value as u8];
+
+    return self.write_echo(&request);
+  }
+
+  /// Function code 15.
+  pub fn write_multiple_coils(&self, slave: u8, address: u16, values: &[bool]) -> Result<(), ModbusError> {
+    if values.len() == 0 || values.len() > 1968 {
+      rprintln!("Between 1 and 1968 coils can be written at once! | ModbusMaster::write_multiple_coils()");
+      return Err(ModbusError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    let mut request: Vec<u8, MAX_ADU> = Vec::new();
+    let count = values.len() as u16;
+    let _ = request.extend_from_slice(&[slave, 0x0F, (address >> 8) as u8, address as u8, (count >> 8) as u8, count as u8]);
+    let _ = request.push(((values.len() + 7) / 8) as u8);
+    for chunk in values.chunks(8) {
+      let _ = request.push(pack_bits(chunk));
+    }
+
+    return self.write_multiple(&request);
+  }
+
+  /// Function code 16.
+  pub fn write_multiple_registers(&self, slave: u8, address: u16, values: &[u16]) -> Result<(), ModbusError> {
+    if values.len() == 0 || values.len() > 123 {
+      rprintln!("Between 1 and 123 registers can be written at once! | ModbusMaster::write_multiple_registers()");
+      return Err(ModbusError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    let mut request: Vec<u8, MAX_ADU> = Vec::new();
+    let count = values.len() as u16;
+    let _ = request.extend_from_slice(&[slave, 0x10, (address >> 8) as u8, address as u8, (count >> 8) as u8, count as u8]);
+    let _ = request.push((2 * values.len()) as u8);
+    for value in values {
+      let _ = request.extend_from_slice(&value.to_be_bytes());
+    }
+
+    return self.write_multiple(&request);
+  }
+
+  fn read_bits(&self, function: u8, slave: u8, address: u16, values: &mut [bool]) -> Result<(), ModbusError> {
+    if values.len() == 0 || values.len() > 2000 {
+      rprintln!("Between 1 and 2000 bits can be read at once! | ModbusMaster::read_bits()");
+      return Err(ModbusError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    let count = values.len() as u16;
+    let request = [slave, function, (address >> 8) as u8, address as u8, (count >> 8) as u8, count as u8];
+    let response = match self.transaction(&request) {
+      Ok(frame) => frame,
+      Err(error) => return Err(error)
+    };
+
+    // Address, function, byte count, data
+    let byte_count = (values.len() + 7) / 8;
+    if response.len() != 3 + byte_count || response[2] as usize != byte_count {return Err(ModbusError::InvalidResponse);}
+
+    for (index, value) in values.iter_mut().enumerate() {
+      *value = response[3 + index / 8] & (1 << (index % 8)) > 0;
+    }
+
+    return Ok(());
+  }
+
+  fn read_registers(&self, function: u8, slave: u8, address: u16, values: &mut [u16]) -> Result<(), ModbusError> {
+    if values.len() == 0 || values.len() > 125 {
+      rprintln!("Between 1 and 125 registers can be read at once! | ModbusMaster::read_registers()");
+      return Err(ModbusError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    let count = values.len() as u16;
+    let request = [slave, function, (address >> 8) as u8, address as u8, (count >> 8) as u8, count as u8];
+    let response = match self.transaction(&request) {
+      Ok(frame) => frame,
+      Err(error) => return Err(error)
+    };
+
+    let byte_count = 2 * values.len();
+    if response.len() != 3 + byte_count || response[2] as usize != byte_count {return Err(ModbusError::InvalidResponse);}
+
+    for (index, value) in values.iter_mut().enumerate() {
+      *value = read_u16(&response, 3 + 2 * index);
+    }
+
+    return Ok(());
+  }
+
+  // The slave answers with a copy of the request
+  fn write_echo(&self, request: &[u8]) -> Result<(), ModbusError> {
+    match self.transaction(request) {
+      Ok(response) if request[0] == 0 || &response[..] == request => return Ok(()),
+      Ok(_) => return Err(ModbusError::InvalidResponse),
+      Err(error) => return Err(error)
+    };
+  }
+
+  // The slave answers with the address and the count of the request
+  fn write_multiple(&self, request: &[u8]) -> Result<(), ModbusError> {
+    match self.transaction(request) {
+      Ok(response) if request[0] == 0 || &response[..] == &request[..6] => return Ok(()),
+      Ok(_) => return Err(ModbusError::InvalidResponse),
+      Err(error) => return Err(error)
+    };
+  }
+
+  fn transaction(&self, request: &[u8]) -> Result<Vec<u8, MAX_ADU>, ModbusError> {
+    if request[0] > 247 {
+      rprintln!("Slave addresses are between 1 and 247! | ModbusMaster::transaction()");
+      return Err(ModbusError::Prog(ProgError::InvalidConfiguration));
+    }
+    if request[0] == 0 && request[1] < 0x05 {
+      rprintln!("Only write requests can be broadcast! | ModbusMaster::transaction()");
+      return Err(ModbusError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    // Late answers to an earlier request
+    while self.uart.try_read().is_some() == true {}
+
+    let start = now();
+    while now().wrapping_sub(start) < self.gap {}
+    if let Err(error) = send_frame(self.uart, request) {return Err(error);}
+
+    if request[0] == 0 {return Ok(Vec::new());}
+
+    let response = match receive_frame(self.uart, self.gap) {
+      Ok(frame) => frame,
+      Err(error) => return Err(error)
+    };
+
+    if response.len() < 2 || response[0] != request[0] {return Err(ModbusError::InvalidResponse);}
+    if response[1] == request[1] | 0x80 {
+      if response.len() != 3 {return Err(ModbusError::InvalidResponse);}
+      return Err(ModbusError::Exception(response[2]));
+    }
+    if response[1] != request[1] {return Err(ModbusError::InvalidResponse);}
+
+    return Ok(response);
+  }
+}
+
+impl<'a> ModbusSlave<'a> {
+  pub fn new(uart: &'a UART, address: u8, map: ModbusMap<'a>) -> Self {
+    let gap = frame_gap(uart);
+
+    // The silence between frames is measured when the bytes arrive, not when they are polled
+    uart.set_rx_gap(gap);
+
+    return Self {
+      uart,
+      address,
+      map,
+      gap,
+      frame: Vec::new(),
+      overflow: false,
+      next: None
+    };
+  }
+
+  /// Switches the port to RS-485 mode with the given driver enable pin.
+  pub fn enable_rs485(&self, de_pin: (char, u8)) -> Result<(), ModbusError> {
+    if let Err(error) = self.uart.enable_rs485(de_pin, 0, 0) {return Err(ModbusError::Serial(error));}
+    return Ok(());
+  }
+
+  pub fn map(&mut self) -> &mut ModbusMap<'a> {
+    return &mut self.map;
+  }
+
+  /// Collects the received bytes and answers a request once the frame is complete. Returns true if
+  /// a request for this slave was served. Frames with a wrong CRC are ignored and reported as
+  /// error. Has to be called often enough to keep the receive buffer from overflowing.
+  pub fn poll(&mut self) -> Result<bool, ModbusError> {
+    if self.frame.len() == 0 {
+      if let Some(byte) = self.next.take() {let _ = self.frame.push(byte);}
+    }
+
+    // A byte after 3.5 characters of silence starts the next frame, it waits until this one is done
+    while self.next.is_none() == true {
+      match self.uart.try_read_gap() {
+        Some((byte, true)) if self.frame.len() > 0 => self.next = Some(byte),
+        Some((byte, _)) => if let Err(_) = self.frame.push(byte) {self.overflow = true;},
+        None => break
+      };
+    }
+
+    // The frame is complete once the next one started or after 3.5 characters of silence
+    if self.frame.len() == 0 {return Ok(false);}
+    if self.next.is_none() == true && self.uart.rx_silence() < self.gap {return Ok(false);}
+
+    let frame = self.frame.clone();
+    let overflow = self.overflow;
+    self.frame.clear();
+    self.overflow = false;
+
+    if overflow == true || frame.len() < 4 {return Ok(false);}
+    if check_crc(&frame) == false {return Err(ModbusError::Crc);}
+    if frame[0] != self.address && frame[0] != 0 {return Ok(false);}
+
+    let request = &frame[..frame.len() - 2];
+    let result = self.handle(request);
+
+    // Broadcasts are not answered
+    if request[0] == 0 {return Ok(true);}
+
+    let response = match result {
+      Ok(value) => value,
+      Err(code) => {
+        let mut exception: Vec<u8, MAX_ADU> = Vec::new();
+        let _ = exception.extend_from_slice(&[self.address, request[1] | 0x80, code]);
+        exception
+      }
+    };
+    if let Err(error) = send_frame(self.uart, &response) {return Err(error);}
+
+    return Ok(true);
+  }
+
+  // Returns the response or the exception code
+  fn handle(&mut self, request: &[u8]) -> Result<Vec<u8, MAX_ADU>, u8> {
+    let function = request[1];
+    let mut response: Vec<u8, MAX_ADU> = Vec::new();
+    let _ = response.extend_from_slice(&[self.address, function]);
+
+    if request.len() < 6 {
+      if function == 0 || (function > 6 && function != 0x0F && function != 0x10) {return Err(ILLEGAL_FUNCTION);}
+      return Err(ILLEGAL_DATA_VALUE);
+    }
+
+    let address = read_u16(request, 2) as usize;
+    let count = read_u16(request, 4) as usize;
+
+    match function {
+      0x01 | 0x02 => {
+        if request.len() != 6 || count == 0 || count > 2000 {return Err(ILLEGAL_DATA_VALUE);}
+
+        let table: &[bool] = if function == 0x01 {&*self.map.coils} else {self.map.discrete_inputs};
+        if address + count > table.len() {return Err(ILLEGAL_DATA_ADDRESS);}
+
+        let _ = response.push(((count + 7) / 8) as u8);
+        for chunk in table[address..address + count].chunks(8) {
+          let _ = response.push(pack_bits(chunk));
+        }
+      },
+      0x03 | 0x04 => {
+        if request.len() != 6 || count == 0 || count > 125 {return Err(ILLEGAL_DATA_VALUE);}
+
+        let table: &[u16] = if function == 0x03 {&*self.map.holding_registers} else {self.map.input_registers};
+        if address + count > table.len() {return Err(ILLEGAL_DATA_ADDRESS);}
+
+        let _ = response.push((2 * count) as u8);
+        for value in &table[address..address + count] {
+          let _ = response.extend_from_slice(&value.to_be_bytes());
+        }
+      },
+      0x05 => {
+        // The count field holds the value
+        if request.len() != 6 || (count != 0xFF00 && count != 0x0000) {return Err(ILLEGAL_DATA_VALUE);}
+        if address >= self.map.coils.len() {return Err(ILLEGAL_DATA_ADDRESS);}
+
+        self.map.coils[address] = count == 0xFF00;
+        response.clear();
+        let _ = response.extend_from_slice(request);
+      },
+      0x06 => {
+        if request.len() != 6 {return Err(ILLEGAL_DATA_VALUE);}
+        if address >= self.map.holding_registers.len() {return Err(ILLEGAL_DATA_ADDRESS);}
+
+        self.map.holding_registers[address] = count as u16;
+        response.clear();
+        let _ = response.extend_from_slice(request);
+      },
+      0x0F => {
+        let byte_count = (count + 7) / 8;
+        if count == 0 || count > 1968 || request.len() != 7 + byte_count || request[6] as usize != byte_count {return Err(ILLEGAL_DATA_VALUE);}
+        if address + count > self.map.coils.len() {return Err(ILLEGAL_DATA_ADDRESS);}
+
+        for index in 0..count {
+          self.map.coils[address + index] = request[7 + index / 8] & (1 << (index % 8)) > 0;
+        }
+        let _ = response.extend_from_slice(&request[2..6]);
+      },
+      0x10 => {
+        if count == 0 || count > 123 || request.len() != 7 + 2 * count || request[6] as usize != 2 * count {return Err(ILLEGAL_DATA_VALUE);}
+        if address + count > self.map.holding_registers.len() {return Err(ILLEGAL_DATA_ADDRESS);}
+
+        for index in 0..count {
+          self.map.holding_registers[address + index] = read_u16(request, 7 + 2 * index);
+        }
+        let _ = response.extend_from_slice(&request[2..6]);
+      },
+      _ => return Err(ILLEGAL_FUNCTION)
+    };
+
+    return Ok(response);
+  }
+}
+
+
+// Public Functions ===============================================================================
+/// Calculates the CRC-16/MODBUS of the data, which is sent low byte first.
+pub fn crc(data: &[u8]) -> u16 {
+  let mut crc: u16 = 0xFFFF;
+
+  for byte in data {
+    crc ^= *byte as u16;
+    for _ in 0..8 {
+      crc = if crc & 1 > 0 {(crc >> 1) ^ 0xA001} else {crc >> 1};
+    }
+  }
+
+  return crc;
+}
+
+
+// Private Functions ==============================================================================
+// 3.5 characters of 11 bits in µs, fixed to 1.75ms above 19200 baud
+fn frame_gap(uart: &UART) -> usize {
+  let baud = uart.baud();
+
+  if baud == 0 || baud > 19200 {return 1750;}
+  else {return (38500000 / baud) as usize;}
+}
+
+fn send_frame(uart: &UART, frame: &[u8]) -> Result<(), ModbusError> {
+  for byte in frame.iter().chain(crc(frame).to_le_bytes().iter()) {
+    if let Err(error) = uart.write(*byte) {return Err(ModbusError::Serial(error));}
+  }
+  uart.flush();
+
+  return Ok(());
+}
+
+// Waits for the first byte as long as the timeout of the port, the frame ends with 3.5 characters
+// of silence. The CRC is removed.
+fn receive_frame(uart: &UART, gap: usize) -> Result<Vec<u8, MAX_ADU>, ModbusError> {
+  let mut frame: Vec<u8, MAX_ADU> = Vec::new();
+
+  match uart.timed_read() {
+    Ok(byte) => {let _ = frame.push(byte);},
+    Err(SerialError::Prog(ProgError::TimedOut)) => return Err(ModbusError::NoResponse),
+    Err(error) => return Err(ModbusError::Serial(error))
+  };
+
+  let mut last_byte = now();
+  loop {
+    match uart.try_read() {
+      Some(byte) => {
+        if let Err(_) = frame.push(byte) {return Err(ModbusError::InvalidResponse);}
+        last_byte = now();
+      },
+      None => if now().wrapping_sub(last_byte) >= gap {break;}
+    };
+  }
+
+  if frame.len() < 4 {return Err(ModbusError::InvalidResponse);}
+  if check_crc(&frame) == false {return Err(ModbusError::Crc);}
+
+  frame.truncate(frame.len() - 2);
+  return Ok(frame);
+}
+
+fn check_crc(frame: &[u8]) -> bool {
+  let (data, trailer) = frame.split_at(frame.len() - 2);
+  return crc(data).to_le_bytes() == trailer;
+}
+
+fn read_u16(data: &[u8], index: usize) -> u16 {
+  return ((data[index] as u16) << 8) | data[index + 1] as u16;
+}
+
+// Coils are packed LSB first
+fn pack_bits(bits: &[bool]) -> u8 {
+  let mut byte: u8 = 0;
+
+  for (index, bit) in bits.iter().enumerate() {
+    if *bit == true {byte |= 1 << index;}
+  }
+
+  return byte;
+}
+
+fn now() -> usize {
+  let peripheral_ptr = stm_peripherals();
+  let rcc = &peripheral_ptr.RCC;
+
+  if rcc.apb1enr.read().tim7en().is_disabled() == true {start_time();}
+  return micros();
+}
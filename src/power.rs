@@ -0,0 +1,262 @@
+//! This module contains everything that is related to the low-power modes of the microcontroller.
+
+use crate::include::{stm_peripherals, core_peripherals, ProgError};
+use crate::clocks::{enable_overdrive, needs_overdrive};
+use core::sync::atomic::{AtomicU32, Ordering};
+use rtt_target::rprintln;
+
+// EXTI lines that were configured as wakeup pins.
+static WAKEUP_LINES: AtomicU32 = AtomicU32::new(0);
+
+/// Represents the signal edge that triggers a wakeup on an EXTI pin.
+pub enum Edge {
+  Rising,
+  Falling,
+  Both
+}
+
+/// Represents the sources that can wake the microcontroller from a low-power mode.
+///
+/// | Source       | Sleep | Stop | Standby |
+/// | ------------ | ----- | ---- | ------- |
+/// | Pin          | Yes   | Yes  | No      |
+/// | WakeupPin    | No    | No   | Yes     |
+/// | RtcAlarm     | Yes   | Yes  | Yes     |
+/// | RtcWakeup    | Yes   | Yes  | Yes     |
+///
+/// `WakeupPin(1)` is PA0 and `WakeupPin(2)` is PC13. The RTC sources have to be set up with the
+/// [rtc](crate::rtc) module first.
+pub enum WakeupSource {
+  Pin((char, u8), Edge),
+  WakeupPin(u8),
+  RtcAlarm,
+  RtcWakeup
+}
+
+
+// Public Functions ===============================================================================
+/// Enables a wakeup source for the low-power modes.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustuino::*;
+/// use rustuino::power::*;
+///
+/// pin_mode(C13, GpioMode::Input).unwrap();
+/// enable_wakeup(WakeupSource::Pin(C13, Edge::Falling)).unwrap();
+///
+/// loop {
+///   stop_mode(true);
+///   rprintln!("Button pressed!");
+/// }
+/// ```
+pub fn enable_wakeup(source: WakeupSource) -> Result<(), ProgError> {
+  let peripheral_ptr = stm_peripherals();
+  let rcc = &peripheral_ptr.RCC;
+  let pwr = &peripheral_ptr.PWR;
+  let syscfg = &peripheral_ptr.SYSCFG;
+  let exti = &peripheral_ptr.EXTI;
+
+  rcc.apb1enr.modify(|_, w| w.pwren().enabled());
+
+  match source {
+    WakeupSource::Pin(pin, edge) => {
+      let port: u32 = match pin.0 {
+        'a' => 0,
+        'b' => 1,
+        'c' => 2,
+        'd' => 3,
+        'h' => 7,
+        _ => {
+          rprintln!("P{}{} is not an available GPIO Pin! | enable_wakeup()", pin.0.to_uppercase(), pin.1);
+          return Err(ProgError::InvalidConfiguration);
+        }
+      };
+      if pin.1 > 15 {
+        rprintln!("P{}{} is not an available GPIO Pin! | enable_wakeup()", pin.0.to_uppercase(), pin.1);
+        return Err(ProgError::InvalidConfiguration);
+      }
+
+      let line = pin.1 as u32;
+      let shift = 4 * (line % 4);
+
+      rcc.apb2enr.modify(|_, w| w.syscfgen().enabled());
+      match line / 4 {
+        0 => syscfg.exticr1.modify(|r, w| unsafe {w.bits(r.bits() & !(0xF << shift) | (port << shift))}),
+        1 => syscfg.exticr2.modify(|r, w| unsafe {w.bits(r.bits() & !(0xF << shift) | (port << shift))}),
+        2 => syscfg.exticr3.modify(|r, w| unsafe {w.bits(r.bits() & !(0xF << shift) | (port << shift))}),
+        3 => syscfg.exticr4.modify(|r, w| unsafe {w.bits(r.bits() & !(0xF << shift) | (port << shift))}),
+        _ => unreachable!()
+      };
+
+      match edge {
+        Edge::Rising => exti.rtsr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << line))}),
+        Edge::Falling => exti.ftsr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << line))}),
+        Edge::Both => {
+          exti.rtsr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << line))});
+          exti.ftsr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << line))});
+        }
+      };
+      // The line only raises an event, so the EXTI interrupts stay free for the application
+      exti.emr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << line))});
+      WAKEUP_LINES.fetch_or(1 << line, Ordering::SeqCst);
+    },
+    WakeupSource::WakeupPin(nr) => {
+      // EWUP1 -> bit 8, EWUP2 -> bit 7
+      match nr {
+        1 => pwr.csr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 8))}),
+        2 => pwr.csr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 7))}),
+        _ => {
+          rprintln!("WKUP{} is not an available wakeup pin! | enable_wakeup()", nr);
+          return Err(ProgError::InvalidConfiguration);
+        }
+      };
+    },
+    WakeupSource::RtcAlarm | WakeupSource::RtcWakeup => {
+      if rcc.bdcr.read().rtcen().is_disabled() == true {
+        rprintln!("RTC is not running! | enable_wakeup()");
+        return Err(ProgError::NotConfigured);
+      }
+    }
+  };
+
+  return Ok(());
+}
+
+pub fn disable_wakeup(source: WakeupSource) {
+  let peripheral_ptr = stm_peripherals();
+  let pwr = &peripheral_ptr.PWR;
+  let exti = &peripheral_ptr.EXTI;
+
+  match source {
+    WakeupSource::Pin(pin, _) => {
+      let line = pin.1 as u32;
+      exti.emr.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << line))});
+      exti.rtsr.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << line))});
+      exti.ftsr.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << line))});
+      WAKEUP_LINES.fetch_and(!(1 << line), Ordering::SeqCst);
+    },
+    WakeupSource::WakeupPin(nr) => {
+      if nr == 1 {pwr.csr.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << 8))});}
+      else if nr == 2 {pwr.csr.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << 7))});}
+    },
+    WakeupSource::RtcAlarm | WakeupSource::RtcWakeup => ()
+  };
+}
+
+/// Stops the CPU clock until the next interrupt or wakeup pin event. All peripherals keep running.
+pub fn sleep() {
+  let mut core_ptr = core_peripherals();
+
+  core_ptr.SCB.clear_sleepdeep();
+  wait_for_wakeup();
+}
+
+/// Stops all clocks in the 1.2V domain until an EXTI line (pin or RTC) triggers. SRAM and registers
+/// are kept. With `regulator_lp` the voltage regulator runs in low-power mode, which lowers the
+/// consumption but increases the wakeup time.
+///
+/// After wakeup the HSI is the system clock, so the previous clock configuration (HSE and PLL) gets restored.
+pub fn stop_mode(regulator_lp: bool) {
+  let peripheral_ptr = stm_peripherals();
+  let rcc = &peripheral_ptr.RCC;
+  let pwr = &peripheral_ptr.PWR;
+  let mut core_ptr = core_peripherals();
+
+  let cr = rcc.cr.read().bits();
+  let cfgr = rcc.cfgr.read().bits();
+
+  rcc.apb1enr.modify(|_, w| w.pwren().enabled());
+  pwr.cr.modify(|_, w| {
+    w.pdds().clear_bit();
+    w.lpds().bit(regulator_lp)
+  });
+
+  core_ptr.SCB.set_sleepdeep();
+  wait_for_wakeup();
+  core_ptr.SCB.clear_sleepdeep();
+
+  restore_clocks(cr, cfgr);
+}
+
+/// Powers down the whole 1.2V domain. Only the backup domain and the RTC keep running and the
+/// microcontroller restarts through a reset on wakeup, so this function never returns.
+pub fn standby() -> ! {
+  let peripheral_ptr = stm_peripherals();
+  let rcc = &peripheral_ptr.RCC;
+  let pwr = &peripheral_ptr.PWR;
+  let rtc = &peripheral_ptr.RTC;
+  let mut core_ptr = core_peripherals();
+
+  rcc.apb1enr.modify(|_, w| w.pwren().enabled());
+
+  // Pending RTC flags would wake the MC immediately
+  if rcc.bdcr.read().rtcen().is_enabled() == true {
+    rtc.isr.modify(|_, w| {
+      w.alraf().clear_bit();
+      w.alrbf().clear_bit();
+      w.wutf().clear_bit()
+    });
+  }
+
+  pwr.cr.modify(|_, w| {
+    w.pdds().set_bit();
+    w.cwuf().set_bit()
+  });
+
+  core_ptr.SCB.set_sleepdeep();
+  loop {cortex_m::asm::wfi();}
+}
+
+/// Returns true if the microcontroller was woken up from standby mode and clears the flag.
+pub fn woke_from_standby() -> bool {
+  let peripheral_ptr = stm_peripherals();
+  let rcc = &peripheral_ptr.RCC;
+  let pwr = &peripheral_ptr.PWR;
+
+  rcc.apb1enr.modify(|_, w| w.pwren().enabled());
+  let standby = pwr.csr.read().sbf().bit_is_set();
+  pwr.cr.modify(|_, w| w.csbf().set_bit());
+
+  return standby;
+}
+
+
+// Private Functions ==============================================================================
+fn restore_clocks(cr: u32, cfgr: u32) {
+  let peripheral_ptr = stm_peripherals();
+  let rcc = &peripheral_ptr.RCC;
+
+  // HSEON -> bit 16, PLLON -> bit 24
+  if cr & (1 << 16) > 0 {
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() == true {}
+  }
+  if cr & (1 << 24) > 0 {
+    rcc.cr.modify(|_, w| w.pllon().on());
+    while rcc.cr.read().pllrdy().is_not_ready() == true {}
+  }
+
+  // Over-drive was switched off in stop mode, the PLL must not run above 168MHz without it
+  let sw = cfgr & 0x3;
+  if sw == 0b10 && needs_overdrive() == true {enable_overdrive();}
+
+  rcc.cfgr.modify(|r, w| unsafe {w.bits((r.bits() & !0x3) | sw)});
+  while (rcc.cfgr.read().bits() >> 2) & 0x3 != sw {}
+}
+
+// Wakeup pins only raise events, so the core waits with WFE, which also wakes on every interrupt.
+// SEV sets the event register, so the first WFE clears a stale event without sleeping.
+fn wait_for_wakeup() {
+  let peripheral_ptr = stm_peripherals();
+  let exti = &peripheral_ptr.EXTI;
+
+  cortex_m::asm::sev();
+  cortex_m::asm::wfe();
+  cortex_m::asm::wfe();
+
+  // Lines that are also used as interrupts by the application are left to its handlers
+  let lines = WAKEUP_LINES.load(Ordering::SeqCst) & !exti.imr.read().bits();
+  exti.pr.write(|w| unsafe {w.bits(lines)});
+}
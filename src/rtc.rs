@@ -0,0 +1,496 @@
+//! This module contains everything that is related to the real-time clock.
+
+use crate::include::{stm_peripherals, ProgError};
+use stm32f4::stm32f446::{NVIC, Interrupt, interrupt, RTC};
+use cortex_m::interrupt::{Mutex, free};
+use core::cell::RefCell;
+use core::ptr::{read_volatile, write_volatile};
+use rtt_target::rprintln;
+
+static ALARM_A_CALLBACK: Mutex<RefCell<Option<fn()>>> = Mutex::new(RefCell::new(None));
+static ALARM_B_CALLBACK: Mutex<RefCell<Option<fn()>>> = Mutex::new(RefCell::new(None));
+static WAKEUP_CALLBACK: Mutex<RefCell<Option<fn()>>> = Mutex::new(RefCell::new(None));
+
+// Seconds between 1970-01-01 and 2000-01-01, the RTC only counts years from 2000 to 2099.
+const UNIX_2000: u32 = 946684800;
+
+/// Represents the clock source of the RTC.
+///
+/// | Source | Frequency | Accuracy                      |
+/// | ------ | --------- | ----------------------------- |
+/// | LSE    | 32.768kHz | External crystal, very stable |
+/// | LSI    | ~32kHz    | Internal RC, drifts strongly  |
+pub enum RtcClock {
+  LSE,
+  LSI
+}
+
+/// Represents the two alarm units of the RTC.
+pub enum Alarm {
+  A,
+  B
+}
+
+/// Date and time as stored in the calendar of the RTC.
+///
+/// `year` ranges from 2000 to 2099 and `weekday` goes from 1 (Monday) to 7 (Sunday).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+  pub year: u16,
+  pub month: u8,
+  pub day: u8,
+  pub weekday: u8,
+  pub hour: u8,
+  pub minute: u8,
+  pub second: u8
+}
+
+/// Time at which an alarm fires. Fields set to `None` are ignored when comparing, so an alarm with
+/// only `second: Some(0)` fires once every minute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlarmTime {
+  pub day: Option<u8>,
+  pub hour: Option<u8>,
+  pub minute: Option<u8>,
+  pub second: Option<u8>
+}
+
+impl DateTime {
+  /// Converts a Unix timestamp to a calendar date, the timestamp has to lie between the years 2000 and 2099.
+  pub fn from_unix(timestamp: u32) -> Result<Self, ProgError> {
+    if timestamp < UNIX_2000 || timestamp >= 4102444800 {
+      rprintln!("Timestamp is outside of the RTC range! | DateTime::from_unix()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+
+    let days = timestamp / 86400;
+    let secs = timestamp % 86400;
+
+    // Civil from days algorithm, shifted so the year starts in March.
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 {mp + 3} else {mp - 9};
+    let year = yoe + era * 400 + if month <= 2 {1} else {0};
+
+    return Ok(Self {
+      year: year as u16,
+      month: month as u8,
+      day: day as u8,
+      // 1970-01-01 was a thursday
+      weekday: (((days + 3) % 7) + 1) as u8,
+      hour: (secs / 3600) as u8,
+      minute: ((secs % 3600) / 60) as u8,
+      second: (secs % 60) as u8
+    });
+  }
+
+  /// Converts the calendar date to a Unix timestamp.
+  pub fn to_unix(&self) -> u32 {
+    let year = if self.month <= 2 {self.year as u32 - 1} else {self.year as u32};
+    let month = self.month as u32;
+
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 {month - 3} else {month + 9}) + 2) / 5 + self.day as u32 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    return days * 86400 + self.hour as u32 * 3600 + self.minute as u32 * 60 + self.second as u32;
+  }
+}
+
+
+// Public Functions ===============================================================================
+/// Starts the RTC with the given clock source. If the RTC is already running (e.g. after a reset
+/// with a backup battery) the calendar is left untouched.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustuino::*;
+/// use rustuino::rtc::*;
+///
+/// rtc_init(RtcClock::LSE).unwrap();
+/// rtc_set_datetime(&DateTime::from_unix(1700000000).unwrap()).unwrap();
+///
+/// loop {
+///   let now = rtc_get_datetime();
+///   rprintln!("{}:{}:{}", now.hour, now.minute, now.second);
+///   delay(1000);
+/// }
+/// ```
+pub fn rtc_init(clock: RtcClock) -> Result<(), ProgError> {
+  let peripheral_ptr = stm_peripherals();
+  let rcc = &peripheral_ptr.RCC;
+  let pwr = &peripheral_ptr.PWR;
+  let rtc = &peripheral_ptr.RTC;
+
+  rcc.apb1enr.modify(|_, w| w.pwren().enabled());
+  pwr.cr.modify(|_, w| w.dbp().set_bit());
+
+  if rcc.bdcr.read().rtcen().is_enabled() == true {
+    rprintln!("RTC already running, calendar is kept! | rtc_init()");
+    return Ok(());
+  }
+
+  let prediv_s = match clock {
+    RtcClock::LSE => {
+      rcc.bdcr.modify(|_, w| w.lseon().on());
+      while rcc.bdcr.read().lserdy().is_not_ready() == true {}
+      rcc.bdcr.modify(|_, w| w.rtcsel().lse());
+      255
+    },
+    RtcClock::LSI => {
+      rcc.csr.modify(|_, w| w.lsion().on());
+      while rcc.csr.read().lsirdy().is_not_ready() == true {}
+      rcc.bdcr.modify(|_, w| w.rtcsel().lsi());
+      249
+    }
+  };
+  rcc.bdcr.modify(|_, w| w.rtcen().enabled());
+
+  // (PREDIV_A + 1) * (PREDIV_S + 1) = f_rtcclk -> 1Hz calendar clock
+  enter_init();
+  rtc.prer.modify(|_, w| w.prediv_s().bits(prediv_s));
+  rtc.prer.modify(|_, w| w.prediv_a().bits(127));
+  exit_init();
+
+  return Ok(());
+}
+
+pub fn rtc_set_datetime(datetime: &DateTime) -> Result<(), ProgError> {
+  let peripheral_ptr = stm_peripherals();
+  let rtc = &peripheral_ptr.RTC;
+
+  if datetime.year < 2000 || datetime.year > 2099 || datetime.month == 0 || datetime.month > 12
+  || datetime.day == 0 || datetime.day > 31 || datetime.weekday == 0 || datetime.weekday > 7
+  || datetime.hour > 23 || datetime.minute > 59 || datetime.second > 59 {
+    rprintln!("Date or time is not valid! | rtc_set_datetime()");
+    return Err(ProgError::InvalidConfiguration);
+  }
+
+  let tr = (to_bcd(datetime.hour) << 16) | (to_bcd(datetime.minute) << 8) | to_bcd(datetime.second);
+  let dr = (to_bcd((datetime.year - 2000) as u8) << 16) | ((datetime.weekday as u32) << 13)
+  | (to_bcd(datetime.month) << 8) | to_bcd(datetime.day);
+
+  enter_init();
+  rtc.tr.write(|w| unsafe {w.bits(tr)});
+  rtc.dr.write(|w| unsafe {w.bits(dr)});
+  exit_init();
+
+  return Ok(());
+}
+
+pub fn rtc_get_datetime() -> DateTime {
+  let peripheral_ptr = stm_peripherals();
+  let rtc = &peripheral_ptr.RTC;
+
+  // Reading TR locks the shadow registers until DR is read.
+  let tr = rtc.tr.read().bits();
+  let dr = rtc.dr.read().bits();
+
+  return DateTime {
+    year: 2000 + from_bcd((dr >> 16) & 0xFF) as u16,
+    month: from_bcd((dr >> 8) & 0x1F),
+    day: from_bcd(dr & 0x3F),
+    weekday: ((dr >> 13) & 0x7) as u8,
+    hour: from_bcd((tr >> 16) & 0x3F),
+    minute: from_bcd((tr >> 8) & 0x7F),
+    second: from_bcd(tr & 0x7F)
+  };
+}
+
+pub fn rtc_set_unix(timestamp: u32) -> Result<(), ProgError> {
+  let datetime = match DateTime::from_unix(timestamp) {
+    Ok(value) => value,
+    Err(error) => return Err(error)
+  };
+
+  return rtc_set_datetime(&datetime);
+}
+
+pub fn rtc_get_unix() -> u32 {
+  return rtc_get_datetime().to_unix();
+}
+
+/// Configures one of the alarms, the callback is run from the RTC_ALARM interrupt.
+pub fn rtc_set_alarm(alarm: Alarm, time: AlarmTime, callback: fn()) -> Result<(), ProgError> {
+  let peripheral_ptr = stm_peripherals();
+  let rtc = &peripheral_ptr.RTC;
+  let exti = &peripheral_ptr.EXTI;
+
+  let mut alrmr: u32 = 0;
+  match time.day {
+    Some(day) if day > 0 && day < 32 => alrmr |= to_bcd(day) << 24,
+    None => alrmr |= 1 << 31,
+    _ => {
+      rprintln!("Alarm day is not valid! | rtc_set_alarm()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+  };
+  match time.hour {
+    Some(hour) if hour < 24 => alrmr |= to_bcd(hour) << 16,
+    None => alrmr |= 1 << 23,
+    _ => {
+      rprintln!("Alarm hour is not valid! | rtc_set_alarm()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+  };
+  match time.minute {
+    Some(minute) if minute < 60 => alrmr |= to_bcd(minute) << 8,
+    None => alrmr |= 1 << 15,
+    _ => {
+      rprintln!("Alarm minute is not valid! | rtc_set_alarm()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+  };
+  match time.second {
+    Some(second) if second < 60 => alrmr |= to_bcd(second),
+    None => alrmr |= 1 << 7,
+    _ => {
+      rprintln!("Alarm second is not valid! | rtc_set_alarm()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+  };
+
+  // Alarm interrupts are routed over EXTI line 17
+  exti.imr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 17))});
+  exti.rtsr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 17))});
+
+  unlock();
+  match alarm {
+    Alarm::A => {
+      free(|cs| ALARM_A_CALLBACK.borrow(cs).replace(Some(callback)));
+      rtc.cr.modify(|_, w| w.alrae().clear_bit());
+      while rtc.isr.read().alrawf().bit_is_clear() == true {}
+      rtc.alrmar.write(|w| unsafe {w.bits(alrmr)});
+      rtc.cr.modify(|_, w| {
+        w.alraie().set_bit();
+        w.alrae().set_bit()
+      });
+    },
+    Alarm::B => {
+      free(|cs| ALARM_B_CALLBACK.borrow(cs).replace(Some(callback)));
+      rtc.cr.modify(|_, w| w.alrbe().clear_bit());
+      while rtc.isr.read().alrbwf().bit_is_clear() == true {}
+      rtc.alrmbr.write(|w| unsafe {w.bits(alrmr)});
+      rtc.cr.modify(|_, w| {
+        w.alrbie().set_bit();
+        w.alrbe().set_bit()
+      });
+    }
+  };
+  lock();
+
+  unsafe {NVIC::unmask(Interrupt::RTC_ALARM);}
+
+  return Ok(());
+}
+
+pub fn rtc_disable_alarm(alarm: Alarm) {
+  let peripheral_ptr = stm_peripherals();
+  let rtc = &peripheral_ptr.RTC;
+
+  unlock();
+  match alarm {
+    Alarm::A => {
+      rtc.cr.modify(|_, w| {
+        w.alraie().clear_bit();
+        w.alrae().clear_bit()
+      });
+      free(|cs| ALARM_A_CALLBACK.borrow(cs).replace(None));
+    },
+    Alarm::B => {
+      rtc.cr.modify(|_, w| {
+        w.alrbie().clear_bit();
+        w.alrbe().clear_bit()
+      });
+      free(|cs| ALARM_B_CALLBACK.borrow(cs).replace(None));
+    }
+  };
+  lock();
+}
+
+/// Starts the periodic wakeup timer with a period between 1 and 65536 seconds, the callback is
+/// run from the RTC_WKUP interrupt.
+pub fn rtc_set_wakeup(seconds: u32, callback: fn()) -> Result<(), ProgError> {
+  let peripheral_ptr = stm_peripherals();
+  let rtc = &peripheral_ptr.RTC;
+  let exti = &peripheral_ptr.EXTI;
+
+  if seconds == 0 || seconds > 65536 {
+    rprintln!("Wakeup period has to be between 1 and 65536 seconds! | rtc_set_wakeup()");
+    return Err(ProgError::InvalidConfiguration);
+  }
+
+  free(|cs| WAKEUP_CALLBACK.borrow(cs).replace(Some(callback)));
+
+  // Wakeup interrupts are routed over EXTI line 22
+  exti.imr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 22))});
+  exti.rtsr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 22))});
+
+  unlock();
+  rtc.cr.modify(|_, w| w.wute().clear_bit());
+  while rtc.isr.read().wutwf().bit_is_clear() == true {}
+  rtc.wutr.write(|w| w.wut().bits((seconds - 1) as u16));
+  // ck_spre (1Hz) as wakeup clock
+  rtc.cr.modify(|_, w| unsafe {
+    w.wucksel().bits(0b100);
+    w.wutie().set_bit();
+    w.wute().set_bit()
+  });
+  lock();
+
+  unsafe {NVIC::unmask(Interrupt::RTC_WKUP);}
+
+  return Ok(());
+}
+
+pub fn rtc_disable_wakeup() {
+  let peripheral_ptr = stm_peripherals();
+  let rtc = &peripheral_ptr.RTC;
+
+  unlock();
+  rtc.cr.modify(|_, w| {
+    w.wutie().clear_bit();
+    w.wute().clear_bit()
+  });
+  lock();
+
+  free(|cs| WAKEUP_CALLBACK.borrow(cs).replace(None));
+}
+
+/// Writes one of the 20 backup registers, they keep their value in standby mode and on VBAT.
+pub fn backup_write(index: u8, value: u32) -> Result<(), ProgError> {
+  if index > 19 {
+    rprintln!("Backup register {} does not exist! | backup_write()", index);
+    return Err(ProgError::InvalidConfiguration);
+  }
+
+  // BKP0R starts at offset 0x50 of the RTC register block
+  unsafe {write_volatile((RTC::ptr() as *mut u32).add(20 + index as usize), value);}
+
+  return Ok(());
+}
+
+pub fn backup_read(index: u8) -> Result<u32, ProgError> {
+  if index > 19 {
+    rprintln!("Backup register {} does not exist! | backup_read()", index);
+    return Err(ProgError::InvalidConfiguration);
+  }
+
+  return Ok(unsafe {read_volatile((RTC::ptr() as *const u32).add(20 + index as usize))});
+}
+
+/// Applies a smooth calibration in ppm, positive values speed the clock up. Valid range is from
+/// -487.1ppm to +488.5ppm in steps of 0.954ppm.
+pub fn rtc_calibrate(ppm: f32) -> Result<(), ProgError> {
+  let peripheral_ptr = stm_peripherals();
+  let rtc = &peripheral_ptr.RTC;
+
+  if ppm < -487.1 || ppm > 488.5 {
+    rprintln!("Calibration value outside of bounds! | rtc_calibrate()");
+    return Err(ProgError::InvalidConfiguration);
+  }
+
+  // CALP adds 512 pulses every 32s (+488.5ppm), CALM masks up to 511 pulses (-0.954ppm each)
+  let (calp, calm) = if ppm > 0.0 {(true, libm::roundf((488.5 - ppm) / 0.9537) as u16)}
+  else {(false, libm::roundf(-ppm / 0.9537) as u16)};
+
+  unlock();
+  while rtc.isr.read().recalpf().bit_is_set() == true {}
+  rtc.calr.write(|w| unsafe {
+    w.calp().bit(calp);
+    w.calm().bits(calm.min(511))
+  });
+  lock();
+
+  return Ok(());
+}
+
+
+// Private Functions ==============================================================================
+fn unlock() {
+  let peripheral_ptr = stm_peripherals();
+  let rtc = &peripheral_ptr.RTC;
+
+  rtc.wpr.write(|w| unsafe {w.bits(0xCA)});
+  rtc.wpr.write(|w| unsafe {w.bits(0x53)});
+}
+
+fn lock() {
+  let peripheral_ptr = stm_peripherals();
+  let rtc = &peripheral_ptr.RTC;
+
+  rtc.wpr.write(|w| unsafe {w.bits(0xFF)});
+}
+
+fn enter_init() {
+  let peripheral_ptr = stm_peripherals();
+  let rtc = &peripheral_ptr.RTC;
+
+  unlock();
+  rtc.isr.modify(|_, w| w.init().set_bit());
+  while rtc.isr.read().initf().bit_is_clear() == true {}
+}
+
+fn exit_init() {
+  let peripheral_ptr = stm_peripherals();
+  let rtc = &peripheral_ptr.RTC;
+
+  rtc.isr.modify(|_, w| w.init().clear_bit());
+
+  // Wait until the shadow registers are synchronized with the new calendar, RSF can only be
+  // cleared while the registers are unlocked
+  rtc.isr.modify(|_, w| w.rsf().clear_bit());
+  lock();
+  while rtc.isr.read().rsf().bit_is_clear() == true {}
+}
+
+fn to_bcd(value: u8) -> u32 {
+  return (((value / 10) << 4) | (value % 10)) as u32;
+}
+
+fn from_bcd(value: u32) -> u8 {
+  return ((value >> 4) * 10 + (value & 0xF)) as u8;
+}
+
+
+// Interrupts =====================================================================================
+#[allow(non_snake_case)]
+#[interrupt]
+fn RTC_ALARM() {
+  let peripheral_ptr = stm_peripherals();
+  let rtc = &peripheral_ptr.RTC;
+  let exti = &peripheral_ptr.EXTI;
+
+  let isr = rtc.isr.read();
+  exti.pr.write(|w| unsafe {w.bits(1 << 17)});
+
+  if isr.alraf().bit_is_set() == true {
+    rtc.isr.modify(|_, w| w.alraf().clear_bit());
+    if let Some(callback) = free(|cs| *ALARM_A_CALLBACK.borrow(cs).borrow()) {callback();}
+  }
+  if isr.alrbf().bit_is_set() == true {
+    rtc.isr.modify(|_, w| w.alrbf().clear_bit());
+    if let Some(callback) = free(|cs| *ALARM_B_CALLBACK.borrow(cs).borrow()) {callback();}
+  }
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn RTC_WKUP() {
+  let peripheral_ptr = stm_peripherals();
+  let rtc = &peripheral_ptr.RTC;
+  let exti = &peripheral_ptr.EXTI;
+
+  rtc.isr.modify(|_, w| w.wutf().clear_bit());
+  exti.pr.write(|w| unsafe {w.bits(1 << 22)});
+
+  if let Some(callback) = free(|cs| *WAKEUP_CALLBACK.borrow(cs).borrow()) {callback();}
+}
@@ -0,0 +1,479 @@
+//! This module contains an interactive command shell on top of a U(S)ART port.
+
+use crate::include::{ShellError, SerialError};
+use crate::uart::UART;
+use crate::gpio::{GpioMode, return_pinmode, digital_read, digital_write};
+use crate::analog::analog_read;
+use crate::time::pwm_write;
+use crate::{uprint, uprintln};
+use core::fmt::Write;
+use core::str::FromStr;
+use heapless::{Vec, String};
+
+/// Maximum length of a command line.
+pub const LINE_SIZE: usize = 64;
+/// Number of lines that are kept in the history.
+pub const HISTORY_SIZE: usize = 8;
+/// Maximum number of arguments of a command.
+pub const MAX_ARGS: usize = 8;
+
+// Commands that are handled by the shell itself
+static SHELL_COMMANDS: [&str; 2] = ["help", "exit"];
+
+/// Commands that are available in every shell after the commands of the application.
+pub static BUILTINS: [Command; 5] = [
+  Command::new("pins", "", "lists the mode of all pins", pins),
+  Command::new("read", "<pin>", "reads a digital input", read),
+  Command::new("write", "<pin> <0|1>", "writes a digital output", write),
+  Command::new("adc", "<pin>", "reads an analog input", adc),
+  Command::new("pwm", "<pin> <value>", "sets the duty cycle of a pwm output", pwm)
+];
+
+/// Function that is called with the arguments of a command, the name is not included.
+pub type Handler = fn(&UART, &[&str]) -> Result<(), ShellError>;
+
+/// Represents a command of the shell.
+#[derive(Clone, Copy)]
+pub struct Command {
+  pub name: &'static str,
+  /// Arguments of the command as shown by `help`.
+  pub usage: &'static str,
+  pub help: &'static str,
+  pub handler: Handler
+}
+
+impl Command {
+  pub const fn new(name: &'static str, usage: &'static str, help: &'static str, handler: Handler) -> Self {
+    return Self {
+      name,
+      usage,
+      help,
+      handler
+    };
+  }
+}
+
+// State of the parser for the escape sequences of the arrow keys
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Escape {
+  None,
+  Esc,
+  Csi
+}
+
+/// Serial console with line editing, command history and tab completion. The arrow keys up and
+/// down browse the history, tab completes the command name and Ctrl-C discards the line.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustuino::*;
+/// use rustuino::uart::*;
+/// use rustuino::shell::*;
+/// use rustuino::include::ShellError;
+///
+/// fn led(_serial: &UART, args: &[&str]) -> Result<(), ShellError> {
+///   if args.len() != 1 {return Err(ShellError::InvalidArgument);}
+///   let value: u8 = match parse_number(args[0]) {
+///     Ok(value) => value,
+///     Err(error) => return Err(error)
+///   };
+///
+///   match digital_write(PA5, value > 0) {
+///     Ok(_) => return Ok(()),
+///     Err(error) => return Err(ShellError::Gpio(error))
+///   };
+/// }
+///
+/// pin_mode(PA5, GpioMode::Output).unwrap();
+/// let serial = UART::new(2, PA2, PA3, 115200, UART_8N1).unwrap();
+///
+/// let commands = [Command::new("led", "<0|1>", "switches the LED", led)];
+/// let mut shell = Shell::new(&serial, &commands).prompt("rustuino> ");
+/// shell.run().unwrap();
+/// ```
+pub struct Shell<'a> {
+  uart: &'a UART,
+  commands: &'a [Command],
+  prompt: &'a str,
+  line: String<LINE_SIZE>,
+  history: Vec<String<LINE_SIZE>, HISTORY_SIZE>,
+  browsed: usize,
+  escape: Escape,
+  last_cr: bool
+}
+
+impl<'a> Shell<'a> {
+  /// Creates a shell with the commands of the application and the built-in commands.
+  pub fn new(uart: &'a UART, commands: &'a [Command]) -> Self {
+    return Self {
+      uart,
+      commands,
+      prompt: "> ",
+      line: String::new(),
+      history: Vec::new(),
+      browsed: 0,
+      escape: Escape::None,
+      last_cr: false
+    };
+  }
+
+  /// Sets the prompt that is printed in front of every line.
+  pub fn prompt(mut self, prompt: &'a str) -> Self {
+    self.prompt = prompt;
+    return self;
+  }
+
+  /// Prints the prompt.
+  pub fn start(&self) -> Result<(), SerialError> {
+    return self.uart.print(self.prompt);
+  }
+
+  /// Processes the received characters and runs the entered commands. Returns false if `exit` was
+  /// entered, the data after it stays in the receive buffer.
+  pub fn poll(&mut self) -> Result<bool, SerialError> {
+    while let Some(byte) = self.uart.try_read() {
+      match self.process(byte) {
+        Ok(true) => (),
+        Ok(false) => return Ok(false),
+        Err(error) => return Err(error)
+      };
+    }
+
+    return Ok(true);
+  }
+
+  /// Prints the prompt and polls the shell until `exit` is entered.
+  pub fn run(&mut self) -> Result<(), SerialError> {
+    if let Err(error) = self.start() {return Err(error);}
+
+    loop {
+      match self.poll() {
+        Ok(true) => (),
+        Ok(false) => return Ok(()),
+        Err(error) => return Err(error)
+      };
+    }
+  }
+
+  fn process(&mut self, byte: u8) -> Result<bool, SerialError> {
+    let last_cr = self.last_cr;
+    self.last_cr = byte == b'\r';
+
+    match self.escape {
+      Escape::None => (),
+      Escape::Esc => {
+        self.escape = if byte == b'[' {Escape::Csi} else {Escape::None};
+        return Ok(true);
+      },
+      Escape::Csi => {
+        // Parameters of the sequence are skipped
+        if (0x30..=0x3F).contains(&byte) == true {return Ok(true);}
+        self.escape = Escape::None;
+
+        let result = match byte {
+          b'A' => self.browse(true),
+          b'B' => self.browse(false),
+          _ => Ok(())
+        };
+        if let Err(error) = result {return Err(error);}
+        return Ok(true);
+      }
+    };
+
+    let result = match byte {
+      0x1B => {
+        self.escape = Escape::Esc;
+        Ok(())
+      },
+      b'\n' if last_cr == true => Ok(()),
+      b'\r' | b'\n' => {
+        match self.execute() {
+          Ok(true) => self.start(),
+          Ok(false) => return Ok(false),
+          Err(error) => Err(error)
+        }
+      },
+      // Backspace and delete
+      0x08 | 0x7F => {
+        if self.line.pop().is_some() == true {self.uart.print("\x08 \x08")}
+        else {Ok(())}
+      },
+      b'\t' => self.complete(),
+      // Ctrl-C
+      0x03 => {
+        self.line.clear();
+        self.browsed = self.history.len();
+        self.uart.print("^C\r\n").and_then(|_| self.start())
+      },
+      0x20..=0x7E => {
+        if self.line.push(byte as char).is_ok() == true {self.uart.write(byte)}
+        else {Ok(())}
+      },
+      _ => Ok(())
+    };
+
+    match result {
+      Ok(_) => return Ok(true),
+      Err(error) => return Err(error)
+    };
+  }
+
+  fn execute(&mut self) -> Result<bool, SerialError> {
+    let line = self.line.clone();
+    self.line.clear();
+    if let Err(error) = self.uart.print("\r\n") {return Err(error);}
+
+    let mut words = line.split_whitespace();
+    let name = match words.next() {
+      Some(value) => value,
+      None => return Ok(true)
+    };
+
+    // Repeated lines are only stored once, the oldest line is dropped when the history is full
+    let entry = line.trim();
+    if self.history.last().map(|last| last.as_str()) != Some(entry) {
+      if self.history.len() == HISTORY_SIZE {
+        self.history.rotate_left(1);
+        self.history.pop();
+      }
+      let mut stored = String::new();
+      let _ = stored.push_str(entry);
+      let _ = self.history.push(stored);
+    }
+    self.browsed = self.history.len();
+
+    let mut args: Vec<&str, MAX_ARGS> = Vec::new();
+    let mut result = Ok(());
+    for word in words {
+      if let Err(_) = args.push(word) {
+        result = Err(ShellError::InvalidArgument);
+        break;
+      }
+    }
+
+    match name {
+      "exit" => return Ok(false),
+      "help" => {
+        if let Err(error) = self.help() {return Err(error);}
+      },
+      _ => {
+        match self.commands.iter().chain(BUILTINS.iter()).find(|command| command.name == name) {
+          Some(command) => {
+            if result.is_ok() == true {result = (command.handler)(self.uart, &args);}
+          },
+          None => {
+            if let Err(error) = uprintln!(self.uart, "Unknown command: {}", name) {return Err(error);}
+          }
+        };
+      }
+    };
+
+    if let Err(error) = result {
+      if let Err(error) = uprintln!(self.uart, "Error: {:?}", error) {return Err(error);}
+    }
+
+    return Ok(true);
+  }
+
+  fn help(&self) -> Result<(), SerialError> {
+    if let Err(error) = uprintln!(self.uart, "{:<24}{}", "help", "lists all commands") {return Err(error);}
+    if let Err(error) = uprintln!(self.uart, "{:<24}{}", "exit", "closes the shell") {return Err(error);}
+
+    for command in self.commands.iter().chain(BUILTINS.iter()) {
+      let mut usage: String<LINE_SIZE> = String::new();
+      let _ = write!(usage, "{} {}", command.name, command.usage);
+      if let Err(error) = uprintln!(self.uart, "{:<24}{}", usage, command.help) {return Err(error);}
+    }
+
+    return Ok(());
+  }
+
+  // Only the command name is completed, as far as all matching names agree
+  fn complete(&mut self) -> Result<(), SerialError> {
+    if self.line.contains(' ') == true {return Ok(());}
+
+    let prefix = self.line.clone();
+    let mut matches = 0;
+    let mut common = "";
+    for name in self.names().filter(|name| name.starts_with(prefix.as_str())) {
+      if matches == 0 {common = name;}
+      else {
+        let len = common.bytes().zip(name.bytes()).take_while(|(a, b)| a == b).count();
+        common = &common[..len];
+      }
+      matches += 1;
+    }
+
+    if matches == 0 {return Ok(());}
+
+    if common.len() > prefix.len() || matches == 1 {
+      let _ = self.line.push_str(&common[prefix.len()..]);
+      if matches == 1 {let _ = self.line.push(' ');}
+      return self.redraw();
+    }
+
+    if let Err(error) = self.uart.print("\r\n") {return Err(error);}
+    for name in self.names().filter(|name| name.starts_with(prefix.as_str())) {
+      if let Err(error) = uprint!(self.uart, "{}  ", name) {return Err(error);}
+    }
+    if let Err(error) = self.uart.print("\r\n") {return Err(error);}
+
+    return self.redraw();
+  }
+
+  // The line that is being edited is replaced, browsing down past the newest line clears it
+  fn browse(&mut self, up: bool) -> Result<(), SerialError> {
+    if up == true {
+      if self.browsed == 0 {return Ok(());}
+      self.browsed -= 1;
+    }
+    else {
+      if self.browsed >= self.history.len() {return Ok(());}
+      self.browsed += 1;
+    }
+
+    self.line.clear();
+    if self.browsed < self.history.len() {
+      let _ = self.line.push_str(&self.history[self.browsed]);
+    }
+
+    return self.redraw();
+  }
+
+  fn redraw(&self) -> Result<(), SerialError> {
+    return uprint!(self.uart, "\r\x1B[K{}{}", self.prompt, self.line);
+  }
+
+  fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+    return SHELL_COMMANDS.iter().copied().chain(self.commands.iter().chain(BUILTINS.iter()).map(|command| command.name));
+  }
+}
+
+
+// Public Functions ===============================================================================
+/// Parses a pin like `PA5`, `pa5` or `A5`. Only the ports A, B, C, D and H are accepted, whether
+/// the pin exists is checked by the function it is passed to.
+pub fn parse_pin(arg: &str) -> Result<(char, u8), ShellError> {
+  let arg = arg.strip_prefix(|c: char| c == 'p' || c == 'P').unwrap_or(arg);
+
+  let mut chars = arg.chars();
+  let block = match chars.next() {
+    Some(c) if ['a', 'b', 'c', 'd', 'h'].contains(&c.to_ascii_lowercase()) == true => c.to_ascii_lowercase(),
+    _ => return Err(ShellError::InvalidArgument)
+  };
+
+  match chars.as_str().parse() {
+    Ok(pin) => return Ok((block, pin)),
+    Err(_) => return Err(ShellError::InvalidArgument)
+  };
+}
+
+/// Parses a number argument into any type that implements `FromStr`.
+pub fn parse_number<T: FromStr>(arg: &str) -> Result<T, ShellError> {
+  match arg.parse() {
+    Ok(value) => return Ok(value),
+    Err(_) => return Err(ShellError::InvalidArgument)
+  };
+}
+
+
+// Built-in Commands ==============================================================================
+fn pins(uart: &UART, args: &[&str]) -> Result<(), ShellError> {
+  if args.len() != 0 {return Err(ShellError::InvalidArgument);}
+
+  for block in ['a', 'b', 'c', 'd', 'h'].iter().copied() {
+    for pin in 0..16 {
+      if (block == 'd' && pin != 2) || (block == 'h' && pin > 1) {continue;}
+
+      let mode = match return_pinmode((block, pin)) {
+        Ok(value) => value,
+        Err(error) => return Err(ShellError::Gpio(error))
+      };
+
+      let result = match mode {
+        GpioMode::Input => uprintln!(uart, "P{}{:<4}Input", block.to_ascii_uppercase(), pin),
+        GpioMode::Output => uprintln!(uart, "P{}{:<4}Output", block.to_ascii_uppercase(), pin),
+        GpioMode::AlternateFunction(af) => uprintln!(uart, "P{}{:<4}AF{}", block.to_ascii_uppercase(), pin, af),
+        GpioMode::Analog => uprintln!(uart, "P{}{:<4}Analog", block.to_ascii_uppercase(), pin),
+        GpioMode::PWM => uprintln!(uart, "P{}{:<4}PWM", block.to_ascii_uppercase(), pin)
+      };
+      if let Err(error) = result {return Err(ShellError::Serial(error));}
+    }
+  }
+
+  return Ok(());
+}
+
+fn read(uart: &UART, args: &[&str]) -> Result<(), ShellError> {
+  if args.len() != 1 {return Err(ShellError::InvalidArgument);}
+
+  let pin = match parse_pin(args[0]) {
+    Ok(value) => value,
+    Err(error) => return Err(error)
+  };
+
+  match digital_read(pin) {
+    Ok(value) => {
+      if let Err(error) = uprintln!(uart, "{}", value as u8) {return Err(ShellError::Serial(error));}
+    },
+    Err(error) => return Err(ShellError::Gpio(error))
+  };
+
+  return Ok(());
+}
+
+fn write(_uart: &UART, args: &[&str]) -> Result<(), ShellError> {
+  if args.len() != 2 {return Err(ShellError::InvalidArgument);}
+
+  let pin = match parse_pin(args[0]) {
+    Ok(value) => value,
+    Err(error) => return Err(error)
+  };
+  let value = match args[1] {
+    "0" => false,
+    "1" => true,
+    _ => return Err(ShellError::InvalidArgument)
+  };
+
+  match digital_write(pin, value) {
+    Ok(_) => return Ok(()),
+    Err(error) => return Err(ShellError::Gpio(error))
+  };
+}
+
+fn adc(uart: &UART, args: &[&str]) -> Result<(), ShellError> {
+  if args.len() != 1 {return Err(ShellError::InvalidArgument);}
+
+  let pin = match parse_pin(args[0]) {
+    Ok(value) => value,
+    Err(error) => return Err(error)
+  };
+
+  match analog_read(pin) {
+    Ok(value) => {
+      if let Err(error) = uprintln!(uart, "{}", value) {return Err(ShellError::Serial(error));}
+    },
+    Err(error) => return Err(ShellError::Gpio(error))
+  };
+
+  return Ok(());
+}
+
+fn pwm(_uart: &UART, args: &[&str]) -> Result<(), ShellError> {
+  if args.len() != 2 {return Err(ShellError::InvalidArgument);}
+
+  let pin = match parse_pin(args[0]) {
+    Ok(value) => value,
+    Err(error) => return Err(error)
+  };
+  let value = match parse_number::<u8>(args[1]) {
+    Ok(value) => value,
+    Err(error) => return Err(error)
+  };
+
+  match pwm_write(pin, value) {
+    Ok(_) => return Ok(()),
+    Err(error) => return Err(ShellError::Gpio(error))
+  };
+}
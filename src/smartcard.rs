@@ -0,0 +1,222 @@
+//! This module contains the ISO 7816 layer with the answer to reset and the T=0 protocol, which
+//! runs on a USART in smartcard mode.
+
+use crate::include::{SmartcardError, SerialError, ProgError};
+use crate::uart::UART;
+use crate::gpio::{GpioMode::Output, pin_mode, digital_write};
+use crate::time::delay;
+use heapless::Vec;
+use rtt_target::rprintln;
+
+const NULL: u8 = 0x60;
+
+/// Answer to reset of a card.
+#[derive(Debug, Clone)]
+pub struct Atr {
+  /// All bytes of the answer, starting with TS.
+  pub bytes: Vec<u8, 33>,
+  /// The first protocol offered by the card, 0 if none is indicated.
+  pub protocol: u8,
+  pub historical: Vec<u8, 15>
+}
+
+/// Card that is connected to a USART in smartcard mode and a GPIO as reset line.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustuino::*;
+/// use rustuino::uart::*;
+/// use rustuino::smartcard::*;
+///
+/// let mut serial = UART::new_smartcard(2, PA2, PA4, SmartcardConfig::new().clock_prescaler(5)).unwrap();
+/// serial.set_timeout(1000);
+/// let card = Smartcard::new(&serial, PA1).unwrap();
+///
+/// let atr = card.reset().unwrap();
+/// rprintln!("Card offers T={}", atr.protocol);
+///
+/// // SELECT of the master file
+/// let mut response = Vec::new();
+/// let status = card.transmit([0x00, 0xA4, 0x00, 0x00, 0x02], &[0x3F, 0x00], &mut response).unwrap();
+/// rprintln!("Status: {:04X}", status);
+/// ```
+pub struct Smartcard<'a> {
+  uart: &'a UART,
+  reset_pin: (char, u8)
+}
+
+impl<'a> Smartcard<'a> {
+  /// Configures the reset line and holds the card in reset.
+  pub fn new(uart: &'a UART, reset_pin: (char, u8)) -> Result<Self, SmartcardError> {
+    if let Err(error) = pin_mode(reset_pin, Output) {
+      rprintln!("Reset pin could not be configured! | Smartcard::new()");
+      return Err(SmartcardError::Prog(error));
+    }
+    if let Err(_) = digital_write(reset_pin, false) {return Err(SmartcardError::Prog(ProgError::Internal));}
+
+    return Ok(Self {
+      uart,
+      reset_pin
+    });
+  }
+
+  /// Resets the card and reads the answer to reset. The card has to start answering within the
+  /// timeout of the port.
+  pub fn reset(&self) -> Result<Atr, SmartcardError> {
+    if let Err(_) = digital_write(self.reset_pin, false) {return Err(SmartcardError::Prog(ProgError::Internal));}
+    delay(10);
+    while self.uart.try_read().is_some() == true {}
+    if let Err(_) = digital_write(self.reset_pin, true) {return Err(SmartcardError::Prog(ProgError::Internal));}
+
+    let mut atr = Atr {
+      bytes: Vec::new(),
+      protocol: 0,
+      historical: Vec::new()
+    };
+
+    // Only the direct convention is supported
+    let ts = match self.read() {
+      Ok(byte) => byte,
+      Err(error) => return Err(error)
+    };
+    if ts != 0x3B {return Err(SmartcardError::InvalidAtr);}
+    if let Err(_) = atr.bytes.push(ts) {return Err(SmartcardError::InvalidAtr);}
+
+    let t0 = match self.read() {
+      Ok(byte) => byte,
+      Err(error) => return Err(error)
+    };
+    if let Err(_) = atr.bytes.push(t0) {return Err(SmartcardError::InvalidAtr);}
+
+    // Every TD byte announces the following interface bytes and a protocol
+    let mut indicator = t0 >> 4;
+    let mut first_td = true;
+    let mut needs_tck = false;
+    loop {
+      for bit in 0..4 {
+        if indicator & (1 << bit) == 0 {continue;}
+
+        let byte = match self.read() {
+          Ok(value) => value,
+          Err(error) => return Err(error)
+        };
+        if let Err(_) = atr.bytes.push(byte) {return Err(SmartcardError::InvalidAtr);}
+
+        // TD
+        if bit == 3 {
+          let protocol = byte & 0x0F;
+          if first_td == true {atr.protocol = protocol;}
+          if protocol != 0 {needs_tck = true;}
+          first_td = false;
+        }
+      }
+
+      if indicator & 0b1000 == 0 {break;}
+      indicator = atr.bytes[atr.bytes.len() - 1] >> 4;
+    }
+
+    for _ in 0..(t0 & 0x0F) {
+      let byte = match self.read() {
+        Ok(value) => value,
+        Err(error) => return Err(error)
+      };
+      if let Err(_) = atr.bytes.push(byte) {return Err(SmartcardError::InvalidAtr);}
+      if let Err(_) = atr.historical.push(byte) {return Err(SmartcardError::InvalidAtr);}
+    }
+
+    // The XOR from T0 up to and including TCK is zero
+    if needs_tck == true {
+      let tck = match self.read() {
+        Ok(value) => value,
+        Err(error) => return Err(error)
+      };
+      if let Err(_) = atr.bytes.push(tck) {return Err(SmartcardError::InvalidAtr);}
+      if atr.bytes[1..].iter().fold(0, |sum, byte| sum ^ byte) != 0 {return Err(SmartcardError::InvalidAtr);}
+    }
+
+    return Ok(atr);
+  }
+
+  /// Holds the card in reset.
+  pub fn deactivate(&self) {
+    let _ = digital_write(self.reset_pin, false);
+  }
+
+  /// Exchanges a command with the card in the T=0 protocol and returns the status word. The header
+  /// consists of CLA, INS, P1, P2 and P3. If data is given, P3 is its length, otherwise P3 is the
+  /// expected length of the response (0 means 256 bytes).
+  pub fn transmit(&self, header: [u8; 5], data: &[u8], response: &mut Vec<u8, 256>) -> Result<u16, SmartcardError> {
+    if data.len() > 255 || (data.len() > 0 && data.len() != header[4] as usize) {
+      rprintln!("P3 has to match the length of the command data! | Smartcard::transmit()");
+      return Err(SmartcardError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    let ins = header[1];
+    let expected = if data.len() > 0 {0}
+    else if header[4] == 0 {256}
+    else {header[4] as usize};
+
+    response.clear();
+    if let Err(error) = self.send(&header) {return Err(error);}
+
+    let mut sent = 0;
+    loop {
+      let procedure = match self.read() {
+        Ok(byte) => byte,
+        Err(error) => return Err(error)
+      };
+
+      if procedure == NULL {continue;}
+
+      // SW1 is followed by SW2
+      if procedure & 0xF0 == 0x60 || procedure & 0xF0 == 0x90 {
+        let sw2 = match self.read() {
+          Ok(byte) => byte,
+          Err(error) => return Err(error)
+        };
+        return Ok(((procedure as u16) << 8) | sw2 as u16);
+      }
+
+      // INS -> all remaining bytes, complement of INS -> only the next byte
+      let count = if procedure == ins {usize::MAX}
+      else if procedure == ins ^ 0xFF {1}
+      else {return Err(SmartcardError::Protocol);};
+
+      if data.len() > 0 {
+        if sent == data.len() {return Err(SmartcardError::Protocol);}
+        let end = sent + count.min(data.len() - sent);
+        if let Err(error) = self.send(&data[sent..end]) {return Err(error);}
+        sent = end;
+      }
+      else {
+        if response.len() == expected {return Err(SmartcardError::Protocol);}
+        for _ in 0..count.min(expected - response.len()) {
+          let byte = match self.read() {
+            Ok(value) => value,
+            Err(error) => return Err(error)
+          };
+          if let Err(_) = response.push(byte) {return Err(SmartcardError::Protocol);}
+        }
+      }
+    }
+  }
+
+  // The receiver is off while sending and is switched on again by the end of the flush
+  fn send(&self, data: &[u8]) -> Result<(), SmartcardError> {
+    for byte in data {
+      if let Err(error) = self.uart.write(*byte) {return Err(SmartcardError::Serial(error));}
+    }
+    self.uart.flush();
+
+    return Ok(());
+  }
+
+  fn read(&self) -> Result<u8, SmartcardError> {
+    match self.uart.timed_read() {
+      Ok(byte) => return Ok(byte),
+      Err(SerialError::Prog(ProgError::TimedOut)) => return Err(SmartcardError::NoResponse),
+      Err(error) => return Err(SmartcardError::Serial(error))
+    };
+  }
+}
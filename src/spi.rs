@@ -1,7 +1,25 @@
 use crate::include::{stm_peripherals, SpiError, ProgError, SPI_DATA};
 use crate::gpio::{pin_mode, digital_write, GpioMode::AlternateFunction, GpioMode::Output};
+use crate::clocks::{pclk1, pclk2};
 use heapless::FnvIndexMap;
 use rtt_target::rprintln;
+#[cfg(feature = "async")]
+use {crate::executor::WakerSlot, stm32f4::stm32f446::{NVIC, Interrupt, interrupt}, core::task::Poll, core::future::poll_fn};
+
+// Status and control register bits used by the interrupt driven functions.
+#[cfg(feature = "async")]
+const SR_RXNE: u32 = 1 << 0;
+#[cfg(feature = "async")]
+const SR_TXE: u32 = 1 << 1;
+#[cfg(feature = "async")]
+const CR2_ERRIE: u32 = 1 << 5;
+#[cfg(feature = "async")]
+const CR2_RXNEIE: u32 = 1 << 6;
+#[cfg(feature = "async")]
+const CR2_TXEIE: u32 = 1 << 7;
+
+#[cfg(feature = "async")]
+static WAKERS: [WakerSlot; 3] = [WakerSlot::new(), WakerSlot::new(), WakerSlot::new()];
 
 #[allow(non_camel_case_types)]
 pub enum ClockMode {
@@ -291,6 +309,52 @@ impl SPI {
     return Ok(());
   }
 
+  /// Sets the fastest SPI clock that is not above `freq` and returns the resulting frequency in Hz.
+  /// The clock mode stays unchanged.
+  pub fn set_frequency(&self, freq: u32) -> Result<u32, SpiError> {
+    let peripheral_ptr = stm_peripherals();
+
+    if self.active == true {
+      rprintln!("Cannot configure SPI core while active! | .set_frequency()");
+      return Err(SpiError::Prog(ProgError::PermissionDenied));
+    }
+
+    let pclk = if self.core == 1 {pclk2()} else {pclk1()};
+
+    // f = PCLK / 2^(BR + 1)
+    let mut br: u8 = 0;
+    while pclk >> (br + 1) > freq && br < 7 {br += 1;}
+
+    if pclk >> (br + 1) > freq {
+      rprintln!("{}Hz is too slow for the SPI clock! | .set_frequency()", freq);
+      return Err(SpiError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    match self.core {
+      1 => {
+        let spi1 = &peripheral_ptr.SPI1;
+        spi1.cr1.modify(|_, w| w.spe().disabled());
+        spi1.cr1.modify(|_, w| w.br().bits(br));
+        spi1.cr1.modify(|_, w| w.spe().enabled());
+      },
+      2 => {
+        let spi2 = &peripheral_ptr.SPI2;
+        spi2.cr1.modify(|_, w| w.spe().disabled());
+        spi2.cr1.modify(|_, w| w.br().bits(br));
+        spi2.cr1.modify(|_, w| w.spe().enabled());
+      },
+      3 => {
+        let spi3 = &peripheral_ptr.SPI3;
+        spi3.cr1.modify(|_, w| w.spe().disabled());
+        spi3.cr1.modify(|_, w| w.br().bits(br));
+        spi3.cr1.modify(|_, w| w.spe().enabled());
+      },
+      _ => unreachable!()
+    };
+
+    return Ok(pclk >> (br + 1));
+  }
+
   pub fn set_frame_format(&self, frame: FrameFormat) -> Result<(), SpiError> {
     let peripheral_ptr = stm_peripherals();
 
@@ -473,6 +537,41 @@ impl SPI {
 }
 
 
+// Async Functions ================================================================================
+#[cfg(feature = "async")]
+impl SPI {
+  /// Async version of [SPI::write] that waits for the TXE interrupt instead of polling the flag.
+  pub async fn write_async(&self, data: u8) -> Result<(), SpiError> {
+    if let SpiMode::SIMPLEX_INPUT = self.mode {
+      rprintln!("Cannot send data in SIMPLEX_INPUT configuration! | .write_async()");
+      return Err(SpiError::Prog(ProgError::PermissionDenied));
+    }
+
+    if let SpiMode::HALF_DUPLEX = self.mode {set_bidioe(self.core, true);}
+    if let Err(error) = wait_flag(self.core, SR_TXE).await {return Err(error);}
+    write_dr(self.core, data.into());
+
+    return Ok(());
+  }
+
+  /// Async version of [SPI::read] that waits for the TXE and RXNE interrupts instead of polling the flags.
+  pub async fn read_async(&self) -> Result<u8, SpiError> {
+    if let SpiMode::SIMPLEX_OUTPUT = self.mode {
+      rprintln!("Cannot read data in SIMPLEX_OUTPUT configuration! | .read_async()");
+      return Err(SpiError::Prog(ProgError::PermissionDenied));
+    }
+
+    if let SpiMode::HALF_DUPLEX = self.mode {set_bidioe(self.core, true);}
+    if let Err(error) = wait_flag(self.core, SR_TXE).await {return Err(error);}
+    write_dr(self.core, 0xFF);
+    if let Err(error) = wait_flag(self.core, SR_RXNE).await {return Err(error);}
+    if let SpiMode::HALF_DUPLEX = self.mode {set_bidioe(self.core, false);}
+
+    return Ok(read_dr(self.core) as u8);
+  }
+}
+
+
 // Private Functions ==============================================================================
 fn check_spi(core: u8, sck: (char, u8), miso: (char, u8), mosi: (char, u8)) -> Result<u8, ProgError> {
   // SPI1 -> AF5
@@ -513,3 +612,124 @@ fn scan_spi_error(sr: u16) -> Result<(), SpiError> {
   else if status &  0b0000000000010000 > 0 {return Err(SpiError::CRCError);}
   else {return Ok(());}
 }
+
+
+// Private Async Functions ========================================================================
+#[cfg(feature = "async")]
+async fn wait_flag(core: u8, flag: u32) -> Result<(), SpiError> {
+  return poll_fn(|cx| {
+    let sr = read_sr(core);
+    if let Err(error) = scan_spi_error(sr as u16) {return Poll::Ready(Err(error));}
+    if sr & flag > 0 {return Poll::Ready(Ok(()));}
+
+    WAKERS[(core - 1) as usize].register(cx.waker());
+    unsafe {NVIC::unmask(spi_interrupt(core));}
+    if flag == SR_TXE {listen(core, CR2_TXEIE | CR2_ERRIE, true);}
+    else {listen(core, CR2_RXNEIE | CR2_ERRIE, true);}
+    return Poll::Pending;
+  }).await;
+}
+
+#[cfg(feature = "async")]
+fn read_sr(core: u8) -> u32 {
+  let peripheral_ptr = stm_peripherals();
+
+  let bits = match core {
+    1 => peripheral_ptr.SPI1.sr.read().bits(),
+    2 => peripheral_ptr.SPI2.sr.read().bits(),
+    3 => peripheral_ptr.SPI3.sr.read().bits(),
+    _ => unreachable!()
+  };
+
+  return bits;
+}
+
+#[cfg(feature = "async")]
+fn read_dr(core: u8) -> u16 {
+  let peripheral_ptr = stm_peripherals();
+
+  let data = match core {
+    1 => peripheral_ptr.SPI1.dr.read().dr().bits(),
+    2 => peripheral_ptr.SPI2.dr.read().dr().bits(),
+    3 => peripheral_ptr.SPI3.dr.read().dr().bits(),
+    _ => unreachable!()
+  };
+
+  return data;
+}
+
+#[cfg(feature = "async")]
+fn write_dr(core: u8, data: u16) {
+  let peripheral_ptr = stm_peripherals();
+
+  match core {
+    1 => peripheral_ptr.SPI1.dr.write(|w| w.dr().bits(data)),
+    2 => peripheral_ptr.SPI2.dr.write(|w| w.dr().bits(data)),
+    3 => peripheral_ptr.SPI3.dr.write(|w| w.dr().bits(data)),
+    _ => unreachable!()
+  };
+}
+
+#[cfg(feature = "async")]
+fn set_bidioe(core: u8, output: bool) {
+  let peripheral_ptr = stm_peripherals();
+
+  match core {
+    1 => peripheral_ptr.SPI1.cr1.modify(|_, w| w.bidioe().bit(output)),
+    2 => peripheral_ptr.SPI2.cr1.modify(|_, w| w.bidioe().bit(output)),
+    3 => peripheral_ptr.SPI3.cr1.modify(|_, w| w.bidioe().bit(output)),
+    _ => unreachable!()
+  };
+}
+
+#[cfg(feature = "async")]
+fn listen(core: u8, mask: u32, enable: bool) {
+  let peripheral_ptr = stm_peripherals();
+
+  match core {
+    1 => peripheral_ptr.SPI1.cr2.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    2 => peripheral_ptr.SPI2.cr2.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    3 => peripheral_ptr.SPI3.cr2.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    _ => unreachable!()
+  };
+}
+
+#[cfg(feature = "async")]
+fn spi_interrupt(core: u8) -> Interrupt {
+  match core {
+    1 => return Interrupt::SPI1,
+    2 => return Interrupt::SPI2,
+    3 => return Interrupt::SPI3,
+    _ => unreachable!()
+  };
+}
+
+
+// Interrupts =====================================================================================
+#[cfg(feature = "async")]
+fn spi_handler(core: u8) {
+  // The interrupts get masked again so they do not fire until the waiting future polls again.
+  listen(core, CR2_TXEIE | CR2_RXNEIE | CR2_ERRIE, false);
+  WAKERS[(core - 1) as usize].wake();
+}
+
+#[cfg(feature = "async")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn SPI1() {
+  spi_handler(1);
+}
+
+#[cfg(feature = "async")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn SPI2() {
+  spi_handler(2);
+}
+
+#[cfg(feature = "async")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn SPI3() {
+  spi_handler(3);
+}
@@ -2,12 +2,24 @@
 
 use crate::include::{stm_peripherals, GpioError, ProgError, PWM_MAP};
 use crate::gpio::{pin_mode, GpioMode::AlternateFunction, return_pinmode};
+use crate::clocks::{timer_clk1, timer_clk2};
 use stm32f4::stm32f446::{NVIC, Interrupt, interrupt};
 use cortex_m::interrupt::{Mutex, free};
 use core::cell::RefCell;
 use rtt_target::rprintln;
+#[cfg(feature = "async")]
+use {core::task::{Poll, Waker}, core::future::poll_fn, heapless::Vec};
 
 static TIME_COUNTER: Mutex<RefCell<usize>> = Mutex::new(RefCell::new(0));
+#[cfg(feature = "async")]
+static DELAY_WAKERS: Mutex<RefCell<Vec<Waker, 8>>> = Mutex::new(RefCell::new(Vec::new()));
+
+// Counter clock of the PWM timers, gives a PWM frequency of 62.5Hz with 8-bit resolution.
+const PWM_COUNTER_FREQ: u32 = 16000;
+// Counter clock of the timers in servo mode, one tick is one microsecond of pulse width.
+const SERVO_COUNTER_FREQ: u32 = 1000000;
+// Servos expect a pulse every 20ms.
+const SERVO_PERIOD: u16 = 20000;
 
 
 // Public PWM Functions ===========================================================================
@@ -27,7 +39,7 @@ pub fn setup_pwm(pin: (char, u8)) -> Result<(), ProgError>{
       let tim1 = &peripheral_ptr.TIM1;
       rcc.apb2enr.modify(|_, w| w.tim1en().enabled());
       tim1.cr1.modify(|_, w| w.arpe().enabled());
-      tim1.psc.write(|w| w.psc().bits(1000));
+      tim1.psc.write(|w| w.psc().bits(calc_pwm_psc(timer_clk2())));
       tim1.arr.write(|w| w.arr().bits(255));
       tim1.egr.write(|w| w.ug().set_bit());
       match ccch {
@@ -38,13 +50,15 @@ pub fn setup_pwm(pin: (char, u8)) -> Result<(), ProgError>{
         _ => unreachable!()
       };
       tim1.ccer.modify(|r, w| unsafe {w.bits(r.bits() | (1 << (4 * (ccch - 1))))});
+      // The outputs of the advanced timer stay off until the main output is enabled
+      tim1.bdtr.modify(|_, w| w.moe().set_bit());
       tim1.cr1.modify(|_, w| w.cen().enabled());
     },
     2 => {
       let tim2 = &peripheral_ptr.TIM2;
-      rcc.apb2enr.modify(|_, w| w.tim1en().enabled());
+      rcc.apb1enr.modify(|_, w| w.tim2en().enabled());
       tim2.cr1.modify(|_, w| w.arpe().enabled());
-      tim2.psc.write(|w| w.psc().bits(1000));
+      tim2.psc.write(|w| w.psc().bits(calc_pwm_psc(timer_clk1())));
       tim2.arr.write(|w| w.arr().bits(255));
       tim2.egr.write(|w| w.ug().set_bit());
       match ccch {
@@ -59,9 +73,9 @@ pub fn setup_pwm(pin: (char, u8)) -> Result<(), ProgError>{
     },
     3 => {
       let tim3 = &peripheral_ptr.TIM3;
-      rcc.apb2enr.modify(|_, w| w.tim1en().enabled());
+      rcc.apb1enr.modify(|_, w| w.tim3en().enabled());
       tim3.cr1.modify(|_, w| w.arpe().enabled());
-      tim3.psc.write(|w| w.psc().bits(1000));
+      tim3.psc.write(|w| w.psc().bits(calc_pwm_psc(timer_clk1())));
       tim3.arr.write(|w| w.arr().bits(255));
       tim3.egr.write(|w| w.ug().set_bit());
       match ccch {
@@ -76,9 +90,9 @@ pub fn setup_pwm(pin: (char, u8)) -> Result<(), ProgError>{
     },
     4 => {
       let tim4 = &peripheral_ptr.TIM4;
-      rcc.apb2enr.modify(|_, w| w.tim1en().enabled());
+      rcc.apb1enr.modify(|_, w| w.tim4en().enabled());
       tim4.cr1.modify(|_, w| w.arpe().enabled());
-      tim4.psc.write(|w| w.psc().bits(1000));
+      tim4.psc.write(|w| w.psc().bits(calc_pwm_psc(timer_clk1())));
       tim4.arr.write(|w| w.arr().bits(255));
       tim4.egr.write(|w| w.ug().set_bit());
       match ccch {
@@ -98,8 +112,6 @@ pub fn setup_pwm(pin: (char, u8)) -> Result<(), ProgError>{
 }
 
 pub fn pwm_write(pin: (char, u8), value: u8) -> Result<(), GpioError> {
-  let peripheral_ptr = stm_peripherals();
-
   let (timer, ccch, af) = match check_pwm(pin) {
     Ok(target) => target,
     Err(error) => return Err(GpioError::Prog(error))
@@ -118,6 +130,108 @@ pub fn pwm_write(pin: (char, u8), value: u8) -> Result<(), GpioError> {
     }
   };
 
+  write_ccr(timer, ccch, value.into());
+
+  return Ok(());
+}
+
+/// Configures the pin for a servo with a period of 20ms. The timer is shared with other pwm pins,
+/// which run with the servo period afterwards. [setup_pwm] on a pin of the same timer switches it
+/// back to the pwm period, so servos and pwm pins should not share a timer.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustuino::*;
+/// use rustuino::time::*;
+///
+/// setup_servo(PA0).unwrap();
+///
+/// // Center position
+/// servo_write(PA0, 1500).unwrap();
+/// ```
+pub fn setup_servo(pin: (char, u8)) -> Result<(), ProgError> {
+  let peripheral_ptr = stm_peripherals();
+
+  if let Err(error) = setup_pwm(pin) {return Err(error);}
+  let (timer, _, _) = match check_pwm(pin) {
+    Ok(target) => target,
+    Err(error) => return Err(error)
+  };
+
+  match timer {
+    1 => {
+      let tim1 = &peripheral_ptr.TIM1;
+      tim1.psc.write(|w| w.psc().bits(calc_servo_psc(timer_clk2())));
+      tim1.arr.write(|w| w.arr().bits(SERVO_PERIOD - 1));
+      tim1.egr.write(|w| w.ug().set_bit());
+    },
+    2 => {
+      let tim2 = &peripheral_ptr.TIM2;
+      tim2.psc.write(|w| w.psc().bits(calc_servo_psc(timer_clk1())));
+      tim2.arr.write(|w| w.arr().bits((SERVO_PERIOD - 1).into()));
+      tim2.egr.write(|w| w.ug().set_bit());
+    },
+    3 => {
+      let tim3 = &peripheral_ptr.TIM3;
+      tim3.psc.write(|w| w.psc().bits(calc_servo_psc(timer_clk1())));
+      tim3.arr.write(|w| w.arr().bits(SERVO_PERIOD - 1));
+      tim3.egr.write(|w| w.ug().set_bit());
+    },
+    4 => {
+      let tim4 = &peripheral_ptr.TIM4;
+      tim4.psc.write(|w| w.psc().bits(calc_servo_psc(timer_clk1())));
+      tim4.arr.write(|w| w.arr().bits(SERVO_PERIOD - 1));
+      tim4.egr.write(|w| w.ug().set_bit());
+    },
+    _ => unreachable!()
+  };
+
+  return Ok(());
+}
+
+/// Sets the pulse width of a servo pin in microseconds, most servos move between 1000 and 2000.
+pub fn servo_write(pin: (char, u8), pulse: u16) -> Result<(), GpioError> {
+  let (timer, ccch, af) = match check_pwm(pin) {
+    Ok(target) => target,
+    Err(error) => return Err(GpioError::Prog(error))
+  };
+
+  match return_pinmode(pin) {
+    Ok(AlternateFunction(af_pin)) if af as u32 == af_pin => (),
+    _ => {
+      rprintln!("P{}{} is not configured for servo output! | servo_write()", pin.0.to_uppercase(), pin.1);
+      return Err(GpioError::WrongMode);
+    }
+  };
+
+  write_ccr(timer, ccch, pulse.min(SERVO_PERIOD));
+
+  return Ok(());
+}
+
+
+// Private PWM Functions ==========================================================================
+fn check_pwm(pin: (char, u8)) -> Result<(u8, u8, u8), ProgError> {
+  if PWM_MAP.pins.contains(&pin) == false {return Err(ProgError::InvalidConfiguration);}
+  else {
+    let timer = PWM_MAP.timers[PWM_MAP.pins.iter().position(|&i| i == pin).unwrap()];
+    let ccch = PWM_MAP.ccchs[PWM_MAP.pins.iter().position(|&i| i == pin).unwrap()];
+    let af = match timer {
+      1 => 1,
+      2 => 1,
+      3 => 2,
+      4 => 2,
+      _  => unreachable!()
+    };
+
+    return Ok((timer, ccch, af));
+  }
+}
+
+fn write_ccr(timer: u8, ccch: u8, value: u16) {
+  let peripheral_ptr = stm_peripherals();
+
   match timer {
     1 => {
       let tim1 = &peripheral_ptr.TIM1;
@@ -161,27 +275,14 @@ pub fn pwm_write(pin: (char, u8), value: u8) -> Result<(), GpioError> {
     },
     _ => unreachable!()
   };
-
-  return Ok(());
 }
 
+fn calc_pwm_psc(timer_clk: u32) -> u16 {
+  return (timer_clk / PWM_COUNTER_FREQ - 1) as u16;
+}
 
-// Private PWM Functions ==========================================================================
-fn check_pwm(pin: (char, u8)) -> Result<(u8, u8, u8), ProgError> {
-  if PWM_MAP.pins.contains(&pin) == false {return Err(ProgError::InvalidConfiguration);}
-  else {
-    let timer = PWM_MAP.timers[PWM_MAP.pins.iter().position(|&i| i == pin).unwrap()];
-    let ccch = PWM_MAP.ccchs[PWM_MAP.pins.iter().position(|&i| i == pin).unwrap()];
-    let af = match timer {
-      1 => 1,
-      2 => 1,
-      3 => 2,
-      4 => 2,
-      _  => unreachable!()
-    };
-
-    return Ok((timer, ccch, af));
-  }
+fn calc_servo_psc(timer_clk: u32) -> u16 {
+  return (timer_clk / SERVO_COUNTER_FREQ - 1) as u16;
 }
 
 
@@ -203,25 +304,26 @@ fn check_pwm(pin: (char, u8)) -> Result<(u8, u8, u8), ProgError> {
 /// }
 /// ```
 pub fn delay(ms: u16) {
-  let peripheral_ptr = stm_peripherals();
-  let rcc = &peripheral_ptr.RCC;
-  let tim6 = &peripheral_ptr.TIM6;
-
-  if rcc.apb1enr.read().tim6en().is_disabled() == true {
-    rcc.apb1enr.modify(|_, w| w.tim6en().enabled());
-    tim6.cr1.modify(|_, w| {
-      w.arpe().enabled();
-      w.opm().set_bit()
-    });
-
-    // 16MHz -> 1MHz : 1000 = 1kHz -> 1ms
-    tim6.psc.write(|w| w.psc().bits(16000));
-  }
+  run_delay_timer(ms, false);
+}
 
-  tim6.arr.write(|w| w.arr().bits(ms));
-  tim6.egr.write(|w| w.ug().update());
-  tim6.cr1.modify(|_, w| w.cen().enabled());
-  while tim6.cr1.read().cen().bit_is_set() == true {}
+/// Same as [delay], but the core sleeps with `WFI` between the timer interrupts instead of polling
+/// the timer. Other interrupts are still served while waiting.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustuino::*;
+///
+/// loop {
+///   digital_write(A5, true).unwrap();
+///   delay_sleep(1000);
+///   digital_write(A5, false).unwrap();
+///   delay_sleep(1000);
+/// }
+/// ```
+pub fn delay_sleep(ms: u16) {
+  run_delay_timer(ms, true);
 }
 
 /// Starts a timer that will continuously count the time in milliseconds.
@@ -260,9 +362,9 @@ pub fn start_time() {
   tim7.dier.modify(|_, w| w.uie().enabled());
   unsafe {NVIC::unmask(Interrupt::TIM7);}
 
-  // 16MHz -> 1MHz : 1000 = 1kHz -> 1ms
-  tim7.psc.write(|w| w.psc().bits(16));
-  tim7.arr.write(|w| w.arr().bits(1000));
+  // Timer clock -> 1MHz : 1000 = 1kHz -> 1ms
+  tim7.psc.write(|w| w.psc().bits((timer_clk1() / 1000000 - 1) as u16));
+  tim7.arr.write(|w| w.arr().bits(999));
   tim7.egr.write(|w| w.ug().update());
   tim7.cr1.modify(|_, w| w.cen().enabled());
 }
@@ -286,22 +388,146 @@ pub fn start_time() {
 /// }
 /// ```
 pub fn millis() -> usize {
-  let peripheral_ptr = stm_peripherals();
-  let tim7 = &peripheral_ptr.TIM6;
-
   let buffer: usize;
 
-  tim7.cr1.modify(|_, w| w.cen().disabled());
+  // The counter is only touched in the interrupt, so the timer keeps running while it is read
   buffer = free(|cs| *TIME_COUNTER.borrow(cs).borrow());
-  tim7.cr1.modify(|_, w| w.cen().enabled());
 
   return buffer;
 }
 
+/// Gives back the time in microseconds since [start_time] was invoked. The value overflows after
+/// about 71 minutes, so time differences should be calculated with `wrapping_sub`.
+pub fn micros() -> usize {
+  let peripheral_ptr = stm_peripherals();
+  let tim7 = &peripheral_ptr.TIM7;
+
+  return free(|cs| {
+    let mut ms = *TIME_COUNTER.borrow(cs).borrow();
+    let mut us = tim7.cnt.read().cnt().bits() as usize;
+
+    // An overflow is not counted before the critical section ends
+    if tim7.sr.read().uif().bit_is_set() == true {
+      ms += 1;
+      us = tim7.cnt.read().cnt().bits() as usize;
+    }
+
+    return ms.wrapping_mul(1000).wrapping_add(us);
+  });
+}
+
+/// Async version of [delay] that lets other tasks run while waiting. The millisecond timer from
+/// [start_time] is started if it is not already running.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustuino::*;
+/// use rustuino::time::delay_ms;
+///
+/// async fn blink() {
+///   loop {
+///     digital_write(A5, true).unwrap();
+///     delay_ms(1000).await;
+///     digital_write(A5, false).unwrap();
+///     delay_ms(1000).await;
+///   }
+/// }
+/// ```
+#[cfg(feature = "async")]
+pub async fn delay_ms(ms: usize) {
+  let peripheral_ptr = stm_peripherals();
+  let rcc = &peripheral_ptr.RCC;
+
+  if rcc.apb1enr.read().tim7en().is_disabled() == true {start_time();}
+
+  let start = millis();
+
+  poll_fn(|cx| {
+    if millis().wrapping_sub(start) >= ms {return Poll::Ready(());}
+
+    free(|cs| {
+      let mut wakers = DELAY_WAKERS.borrow(cs).borrow_mut();
+      if wakers.iter().any(|i| i.will_wake(cx.waker())) == false {
+        // If all slots are taken the task polls again on the next executor round.
+        if wakers.push(cx.waker().clone()).is_err() {cx.waker().wake_by_ref();}
+      }
+    });
+
+    return Poll::Pending;
+  }).await;
+}
+
+
+// Private Time Functions =========================================================================
+fn run_delay_timer(ms: u16, sleep: bool) {
+  let peripheral_ptr = stm_peripherals();
+  let rcc = &peripheral_ptr.RCC;
+  let tim6 = &peripheral_ptr.TIM6;
+
+  if rcc.apb1enr.read().tim6en().is_disabled() == true {
+    rcc.apb1enr.modify(|_, w| w.tim6en().enabled());
+    tim6.cr1.modify(|_, w| {
+      w.arpe().enabled();
+      w.opm().set_bit()
+    });
+  }
+
+  // The prescaler is only 16 bit wide, so fast timer clocks need more than one tick per ms.
+  let ticks_per_ms = (timer_clk1() / 1000 + 65535) / 65536;
+  tim6.psc.write(|w| w.psc().bits((timer_clk1() / (1000 * ticks_per_ms) - 1) as u16));
+
+  let mut remaining = ms as u32 * ticks_per_ms;
+  while remaining > 0 {
+    let ticks = remaining.min(65535);
+
+    tim6.arr.write(|w| w.arr().bits(ticks as u16));
+    tim6.egr.write(|w| w.ug().update());
+    if sleep == true {
+      tim6.sr.modify(|_, w| w.uif().clear_bit());
+      tim6.dier.modify(|_, w| w.uie().enabled());
+      unsafe {NVIC::unmask(Interrupt::TIM6_DAC);}
+    }
+
+    tim6.cr1.modify(|_, w| w.cen().enabled());
+    while tim6.cr1.read().cen().bit_is_set() == true {
+      // The update interrupt between the check and WFI still wakes the core.
+      if sleep == true {
+        free(|_| {
+          if tim6.cr1.read().cen().bit_is_set() == true {cortex_m::asm::wfi();}
+        });
+      }
+    }
+
+    remaining -= ticks;
+  }
+
+  tim6.dier.modify(|_, w| w.uie().disabled());
+}
+
 
 // Interrupts =====================================================================================
 #[allow(non_snake_case)]
 #[interrupt]
 fn TIM7() {
+  let peripheral_ptr = stm_peripherals();
+  let tim7 = &peripheral_ptr.TIM7;
+
+  tim7.sr.modify(|_, w| w.uif().clear_bit());
   free(|cs| TIME_COUNTER.borrow(cs).replace_with(|&mut i| i + 1));
+
+  #[cfg(feature = "async")]
+  free(|cs| {
+    let mut wakers = DELAY_WAKERS.borrow(cs).borrow_mut();
+    while let Some(waker) = wakers.pop() {waker.wake();}
+  });
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn TIM6_DAC() {
+  let peripheral_ptr = stm_peripherals();
+  let tim6 = &peripheral_ptr.TIM6;
+
+  tim6.sr.modify(|_, w| w.uif().clear_bit());
 }
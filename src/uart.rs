@@ -1,153 +1,632 @@
 //! This module contains everything that is used for UART communication.
 
-use crate::include::{stm_peripherals, SerialError, ProgError, UART_MAP};
-use crate::gpio::{GpioMode::AlternateFunction, pin_mode};
-use stm32f4::stm32f446::{NVIC, Interrupt};
+use crate::include::{stm_peripherals, SerialError, ProgError, PWM_MAP, UART_MAP, UART_FLOW_MAP, UART_CLOCK_MAP};
+use crate::gpio::{GpioMode::AlternateFunction, GpioMode::Output, GpioBias, pin_mode, digital_write, open_drain, set_bias};
+use crate::clocks::{pclk1, pclk2, sysclk, timer_clk1, timer_clk2};
+use crate::time::{start_time, millis, micros};
+use stm32f4::stm32f446::{NVIC, Interrupt, interrupt};
+use cortex_m::interrupt::{Mutex, free, CriticalSection};
+use cortex_m::peripheral::{SCB, scb::VectActive};
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU8, Ordering};
+use core::fmt::{Arguments, Write};
+use heapless::{spsc::Queue, String};
 use rtt_target::rprintln;
+pub use rustuino_core::uart::{BaudRate, calc_baud, next_match};
+#[cfg(feature = "async")]
+use {crate::executor::WakerSlot, core::task::Poll, core::future::poll_fn};
+#[cfg(feature = "uart-dma")]
+use {crate::include::UART_DMA_MAP, stm32f4::stm32f446::{DMA1, DMA2, dma2}};
 
-// 8 = bits, 4 = stops, 2,1 = parity
-pub const UART_8N1: u8 = 0;
-pub const UART_8N2: u8 = 4;
-pub const UART_8E1: u8 = 1;
-pub const UART_8E2: u8 = 5;
-pub const UART_8O1: u8 = 2;
-pub const UART_8O2: u8 = 6;
-pub const UART_9N1: u8 = 8;
-pub const UART_9N2: u8 = 12;
-pub const UART_9E1: u8 = 9;
-pub const UART_9E2: u8 = 13;
-pub const UART_9O1: u8 = 10;
-pub const UART_9O2: u8 = 14;
+// Status and control register bits used by the interrupt driven functions.
+const SR_ERRORS: u32 = 0x7;
+const SR_ORE: u32 = 1 << 3;
+#[cfg(feature = "uart-dma")]
+const SR_IDLE: u32 = 1 << 4;
+const SR_RXNE: u32 = 1 << 5;
+const SR_TC: u32 = 1 << 6;
+const SR_TXE: u32 = 1 << 7;
+const SR_FE: u32 = 1 << 1;
+const SR_LBD: u32 = 1 << 8;
+const CR1_SBK: u32 = 1 << 0;
+const CR1_RWU: u32 = 1 << 1;
+const CR1_RE: u32 = 1 << 2;
+#[cfg(feature = "uart-dma")]
+const CR1_IDLEIE: u32 = 1 << 4;
+const CR1_RXNEIE: u32 = 1 << 5;
+const CR1_TCIE: u32 = 1 << 6;
+const CR1_TXEIE: u32 = 1 << 7;
+const CR2_LBDL: u32 = 1 << 5;
+const CR2_LBDIE: u32 = 1 << 6;
+const CR2_LBCL: u32 = 1 << 8;
+const CR2_CPHA: u32 = 1 << 9;
+const CR2_CPOL: u32 = 1 << 10;
+const CR2_CLKEN: u32 = 1 << 11;
+const CR2_LINEN: u32 = 1 << 14;
+const CR3_IREN: u32 = 1 << 1;
+const CR3_IRLP: u32 = 1 << 2;
+const CR3_HDSEL: u32 = 1 << 3;
+const CR3_NACK: u32 = 1 << 4;
+const CR3_SCEN: u32 = 1 << 5;
+#[cfg(feature = "uart-dma")]
+const CR3_DMAR: u32 = 1 << 6;
+const CR3_DMAT: u32 = 1 << 7;
+const CR3_RTSE: u32 = 1 << 8;
+const CR3_CTSE: u32 = 1 << 9;
 
+// DMA stream configuration bits. The DMA functions are only compiled with the uart-dma feature, so
+// the DMA interrupts are left to the application otherwise.
+#[cfg(feature = "uart-dma")]
+const DMA_EN: u32 = 1 << 0;
+#[cfg(feature = "uart-dma")]
+const DMA_HTIE: u32 = 1 << 3;
+#[cfg(feature = "uart-dma")]
+const DMA_TCIE: u32 = 1 << 4;
+#[cfg(feature = "uart-dma")]
+const DMA_M2P: u32 = 1 << 6;
+#[cfg(feature = "uart-dma")]
+const DMA_CIRC: u32 = 1 << 8;
+#[cfg(feature = "uart-dma")]
+const DMA_MINC: u32 = 1 << 10;
+
+// Deviation between the measured and the detected baud rate in percent
+const AUTOBAUD_TOLERANCE: u32 = 5;
+// Frequency of the pulses in IrDA low-power mode
+const IRDA_LP_FREQ: u32 = 1843200;
+// ISO 7816 cards are clocked with 1MHz to 5MHz
+const SMARTCARD_MAX_CLOCK: u32 = 5000000;
+
+// One slot of the ring buffers always stays empty, so every port can hold 64 words per direction.
+const RX_BUFFER_SIZE: usize = 65;
+// Marks a received word that followed the silence set with set_rx_gap, words have at most 9 bits
+const RX_GAP: u16 = 1 << 15;
+const TX_BUFFER_SIZE: usize = 65;
+
+static RX_BUFFERS: Mutex<RefCell<[Queue<u16, RX_BUFFER_SIZE>; 6]>> = Mutex::new(RefCell::new([Queue::new(), Queue::new(), Queue::new(), Queue::new(), Queue::new(), Queue::new()]));
+static TX_BUFFERS: Mutex<RefCell<[Queue<u16, TX_BUFFER_SIZE>; 6]>> = Mutex::new(RefCell::new([Queue::new(), Queue::new(), Queue::new(), Queue::new(), Queue::new(), Queue::new()]));
+static RX_STATS: Mutex<RefCell<[RxStats; 6]>> = Mutex::new(RefCell::new([RxStats::new(); 6]));
+static RX_HOOKS: Mutex<RefCell<[Option<fn(u16) -> bool>; 6]>> = Mutex::new(RefCell::new([None; 6]));
+// Silence that marks the next word and the time of the last word in µs, per port
+static RX_GAPS: Mutex<RefCell<[(usize, usize); 6]>> = Mutex::new(RefCell::new([(0, 0); 6]));
+#[cfg(feature = "uart-dma")]
+static DMA_RX: Mutex<RefCell<[Option<DmaRx>; 6]>> = Mutex::new(RefCell::new([None; 6]));
+#[cfg(feature = "uart-dma")]
+static DMA_TX_CALLBACKS: Mutex<RefCell<[Option<fn()>; 6]>> = Mutex::new(RefCell::new([None; 6]));
+// One bit per port, set by the interrupt when a LIN break was detected.
+static LIN_BREAKS: AtomicU8 = AtomicU8::new(0);
+static DIRECTION: Mutex<RefCell<[Option<Direction>; 6]>> = Mutex::new(RefCell::new([None; 6]));
+
+#[cfg(feature = "async")]
+static TX_WAKERS: [WakerSlot; 6] = [WakerSlot::new(), WakerSlot::new(), WakerSlot::new(), WakerSlot::new(), WakerSlot::new(), WakerSlot::new()];
+#[cfg(feature = "async")]
+static RX_WAKERS: [WakerSlot; 6] = [WakerSlot::new(), WakerSlot::new(), WakerSlot::new(), WakerSlot::new(), WakerSlot::new(), WakerSlot::new()];
+
+// Presets for the common frame formats: data bits (incl. parity), parity, stop bits
+pub const UART_8N1: UartConfig = UartConfig::new();
+pub const UART_8N2: UartConfig = UartConfig::new().stop_bits(StopBits::Two);
+pub const UART_8E1: UartConfig = UartConfig::new().parity(Parity::Even);
+pub const UART_8E2: UartConfig = UartConfig::new().parity(Parity::Even).stop_bits(StopBits::Two);
+pub const UART_8O1: UartConfig = UartConfig::new().parity(Parity::Odd);
+pub const UART_8O2: UartConfig = UartConfig::new().parity(Parity::Odd).stop_bits(StopBits::Two);
+pub const UART_9N1: UartConfig = UartConfig::new().data_bits(DataBits::Nine);
+pub const UART_9N2: UartConfig = UartConfig::new().data_bits(DataBits::Nine).stop_bits(StopBits::Two);
+pub const UART_9E1: UartConfig = UartConfig::new().data_bits(DataBits::Nine).parity(Parity::Even);
+pub const UART_9E2: UartConfig = UartConfig::new().data_bits(DataBits::Nine).parity(Parity::Even).stop_bits(StopBits::Two);
+pub const UART_9O1: UartConfig = UartConfig::new().data_bits(DataBits::Nine).parity(Parity::Odd);
+pub const UART_9O2: UartConfig = UartConfig::new().data_bits(DataBits::Nine).parity(Parity::Odd).stop_bits(StopBits::Two);
+
+/// Represents the word length. If parity is enabled, the parity bit is the last bit of the word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataBits {
+  Eight,
+  Nine
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+  None,
+  Even,
+  Odd
+}
+
+/// Represents the number of stop bits. Half and one and a half stop bits are not available on UART4
+/// and UART5.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+  One,
+  Half,
+  Two,
+  OneAndHalf
+}
+
+/// Represents the level of the CK pin between the frames in synchronous mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockPolarity {
+  IdleLow,
+  IdleHigh
+}
+
+/// Represents the clock edge on which the data bits are captured in synchronous mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockPhase {
+  FirstEdge,
+  SecondEdge
+}
+
+/// Represents the frame format and the settings of a port. The default is 8N1 with 16 times
+/// oversampling, both directions enabled and no flow control. 8 times oversampling allows twice the
+/// baud rate but is less tolerant to clock deviations.
+///
+/// Hardware flow control is enabled by giving an RTS and/or CTS pin, which is only possible on
+/// USART1-3. With RTS the receive interrupt stops reading when the receive buffer is full, so the
+/// sender gets paused instead of data being dropped.
+///
+/// Giving a CK pin switches the port to synchronous mode, which is only possible on USART1-3 and
+/// USART6. The port is then the clock master, a clock pulse is generated for every data bit sent
+/// and RX is sampled on the same clock. Whether the clock is also generated for the last data bit
+/// is set with `last_bit_clock`, many shift registers need it.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustuino::*;
+/// use rustuino::uart::*;
+///
+/// let config = UartConfig::new().parity(Parity::Even).stop_bits(StopBits::Two).oversampling_8(true);
+/// let serial = UART::new(1, PA9, PA10, 2000000, config).unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UartConfig {
+  pub data_bits: DataBits,
+  pub parity: Parity,
+  pub stop_bits: StopBits,
+  pub over8: bool,
+  pub tx_enable: bool,
+  pub rx_enable: bool,
+  pub rts_pin: Option<(char, u8)>,
+  pub cts_pin: Option<(char, u8)>,
+  pub ck_pin: Option<(char, u8)>,
+  pub clock_polarity: ClockPolarity,
+  pub clock_phase: ClockPhase,
+  pub last_bit_clock: bool
+}
+
+impl UartConfig {
+  pub const fn new() -> Self {
+    return Self {
+      data_bits: DataBits::Eight,
+      parity: Parity::None,
+      stop_bits: StopBits::One,
+      over8: false,
+      tx_enable: true,
+      rx_enable: true,
+      rts_pin: None,
+      cts_pin: None,
+      ck_pin: None,
+      clock_polarity: ClockPolarity::IdleLow,
+      clock_phase: ClockPhase::FirstEdge,
+      last_bit_clock: false
+    };
+  }
+
+  pub const fn data_bits(mut self, data_bits: DataBits) -> Self {
+    self.data_bits = data_bits;
+    return self;
+  }
+
+  pub const fn parity(mut self, parity: Parity) -> Self {
+    self.parity = parity;
+    return self;
+  }
+
+  pub const fn stop_bits(mut self, stop_bits: StopBits) -> Self {
+    self.stop_bits = stop_bits;
+    return self;
+  }
+
+  pub const fn oversampling_8(mut self, over8: bool) -> Self {
+    self.over8 = over8;
+    return self;
+  }
+
+  pub const fn tx_enable(mut self, enable: bool) -> Self {
+    self.tx_enable = enable;
+    return self;
+  }
+
+  pub const fn rx_enable(mut self, enable: bool) -> Self {
+    self.rx_enable = enable;
+    return self;
+  }
+
+  pub const fn rts(mut self, pin: (char, u8)) -> Self {
+    self.rts_pin = Some(pin);
+    return self;
+  }
+
+  pub const fn cts(mut self, pin: (char, u8)) -> Self {
+    self.cts_pin = Some(pin);
+    return self;
+  }
+
+  pub const fn clock(mut self, pin: (char, u8), polarity: ClockPolarity, phase: ClockPhase) -> Self {
+    self.ck_pin = Some(pin);
+    self.clock_polarity = polarity;
+    self.clock_phase = phase;
+    return self;
+  }
+
+  pub const fn last_bit_clock(mut self, enable: bool) -> Self {
+    self.last_bit_clock = enable;
+    return self;
+  }
+}
+
+/// Represents the settings of a port in smartcard mode. The card is clocked with the peripheral
+/// clock divided by `2 * clock_prescaler` (1-31), the baud rate is derived from it with the
+/// default ISO 7816 factor of 372 clock cycles per bit. Without a prescaler the fastest card clock
+/// up to 5MHz is used. The guard time is the number of additional bit times between two characters
+/// sent to the card.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SmartcardConfig {
+  pub clock_prescaler: Option<u8>,
+  pub guard_time: u8,
+  pub nack: bool
+}
+
+impl SmartcardConfig {
+  pub const fn new() -> Self {
+    return Self {
+      clock_prescaler: None,
+      guard_time: 2,
+      nack: true
+    };
+  }
+
+  pub const fn clock_prescaler(mut self, prescaler: u8) -> Self {
+    self.clock_prescaler = Some(prescaler);
+    return self;
+  }
+
+  pub const fn guard_time(mut self, bits: u8) -> Self {
+    self.guard_time = bits;
+    return self;
+  }
+
+  /// Sends a NACK to the card when a character with a parity error was received.
+  pub const fn nack(mut self, enable: bool) -> Self {
+    self.nack = enable;
+    return self;
+  }
+}
+
+/// Represents the pulse width of the IrDA SIR encoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrdaMode {
+  /// Pulses of 3/16 bit time.
+  Normal,
+  /// Pulses of three periods of a 1.8432MHz clock, independent of the baud rate.
+  LowPower
+}
+
+/// Represents the behavior of the write functions when the transmit buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxPolicy {
+  /// Wait until the interrupt has made room in the buffer. Inside of interrupt handlers the USART
+  /// interrupt might never run, so the buffer is sent by polling the data register instead.
+  Block,
+  /// Discard the data that does not fit.
+  Drop,
+  /// Return `SerialError::BufferFull`.
+  Error
+}
+
+/// Represents the number base of [UART::print_int].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Base {
+  Bin,
+  Oct,
+  Dec,
+  Hex
+}
+
+/// Represents how a muted receiver wakes up in multiprocessor communication.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeupMethod {
+  /// Wakes up when the line is idle for one frame.
+  IdleLine,
+  /// Wakes up when an address word with the own address arrives.
+  AddressMark
+}
+
+/// Represents the length of a break that is detected in LIN mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakLength {
+  Bits10,
+  Bits11
+}
+
+/// Receive statistics of a UART port since it was opened or the statistics were cleared.
+#[derive(Debug, Clone, Copy)]
+pub struct RxStats {
+  /// Bytes that were dropped because the receive buffer was full.
+  pub buffer_overruns: u32,
+  /// Bytes that were lost in the peripheral before the interrupt could read them.
+  pub hardware_overruns: u32,
+  /// Bytes that were received with a noise, framing or parity error.
+  pub line_errors: u32
+}
+
+impl RxStats {
+  const fn new() -> Self {
+    return Self {
+      buffer_overruns: 0,
+      hardware_overruns: 0,
+      line_errors: 0
+    };
+  }
+}
+
+// Circular receive buffer of a port in DMA mode.
+#[cfg(feature = "uart-dma")]
+#[derive(Clone, Copy)]
+struct DmaRx {
+  address: u32,
+  len: usize,
+  position: usize,
+  callback: fn(&[u8])
+}
+
+// Bus turnaround of a port in RS-485 or single-wire mode. The receiver is switched off while
+// transmitting, the driver enable pin is only used for RS-485.
+#[derive(Clone, Copy)]
+struct Direction {
+  pin: Option<(char, u8)>,
+  pre_delay: u32,
+  post_delay: u32,
+  transmitting: bool,
+  receiver: bool,
+  // Time in microseconds when the post delay started
+  release: Option<usize>
+}
+
+impl Direction {
+  const fn single_wire() -> Self {
+    return Self {
+      pin: None,
+      pre_delay: 0,
+      post_delay: 0,
+      transmitting: false,
+      receiver: false,
+      release: None
+    };
+  }
+}
+
+// Passes formatted text to a port and keeps the error, which core::fmt cannot carry.
+struct FmtWriter<'a> {
+  uart: &'a UART,
+  error: Option<SerialError>
+}
+
+impl Write for FmtWriter<'_> {
+  fn write_str(&mut self, s: &str) -> core::fmt::Result {
+    if let Err(error) = self.uart.print(s) {
+      self.error = Some(error);
+      return Err(core::fmt::Error);
+    }
+
+    return Ok(());
+  }
+}
+
+/// Every port receives and transmits in the background through the RXNE and TXE interrupts with a
+/// ring buffer of 64 bytes per direction. No data is lost while the program is busy with other
+/// things and the print functions return as soon as the data is queued.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustuino::*;
+/// use rustuino::uart::*;
+///
+/// let serial = UART::new(2, PA2, PA3, 115200, UART_8N1).unwrap();
+///
+/// loop {
+///   if serial.available() > 0 {
+///     let byte = serial.try_read().unwrap();
+///     serial.write(byte).unwrap();
+///   }
+///   // Do something else
+/// }
+/// ```
 pub struct UART {
-  core: u8
+  core: u8,
+  rx_pin: Option<(char, u8)>,
+  tx_policy: TxPolicy,
+  timeout: usize
 }
 
 impl UART {
-  pub fn new(core: u8, tx_pin: (char, u8), rx_pin: (char, u8), baud: u32, conf: u8) -> Result<Self, ProgError> {
-    let peripheral_ptr = stm_peripherals();
-    let rcc = &peripheral_ptr.RCC;
-    
+  pub fn new(core: u8, tx_pin: (char, u8), rx_pin: (char, u8), baud: u32, conf: UartConfig) -> Result<Self, ProgError> {
     if UART_MAP.tx_pins.iter().zip(UART_MAP.rx_pins.iter())
     .zip(UART_MAP.cores.iter()).any(|i| i == ((&tx_pin, &rx_pin), &core)) == false {
       rprintln!("These pins are not available for UART communication! | UART::new()");
       return Err(ProgError::InvalidConfiguration);
     }
+
+    return Self::setup(core, tx_pin, Some(rx_pin), baud, conf);
+  }
+
+  /// Sets up a port in single-wire half-duplex mode, where TX and RX share the TX pin. The pin is
+  /// configured as open-drain with pull-up, so several nodes can be wired together. The receiver
+  /// is switched off while transmitting, so the own data is not echoed back.
+  ///
+  /// # Example
+  ///
+  /// ```rust,no_run
+  /// use rustuino::*;
+  /// use rustuino::uart::*;
+  ///
+  /// let serial = UART::new_half_duplex(1, PA9, 115200, UART_8N1).unwrap();
+  /// serial.println("ping").unwrap();
+  /// let reply = serial.timed_read().unwrap();
+  /// ```
+  pub fn new_half_duplex(core: u8, tx_pin: (char, u8), baud: u32, conf: UartConfig) -> Result<Self, ProgError> {
+    if UART_MAP.tx_pins.iter().zip(UART_MAP.cores.iter()).any(|i| i == (&tx_pin, &core)) == false {
+      rprintln!("This pin is not available for UART communication! | UART::new_half_duplex()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+    if conf.rts_pin.is_some() == true || conf.cts_pin.is_some() == true {
+      rprintln!("Flow control is not available in single-wire mode! | UART::new_half_duplex()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+    if conf.ck_pin.is_some() == true {
+      rprintln!("Synchronous mode is not available in single-wire mode! | UART::new_half_duplex()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+
+    return Self::setup(core, tx_pin, None, baud, conf);
+  }
+
+  /// Sets up a port in smartcard mode (ISO 7816), which is available on USART1-3 and USART6. The
+  /// card I/O line is connected to the TX pin, which is configured as open-drain with pull-up, and
+  /// the card clock to the CK pin. The frame format is fixed to 8 data bits, even parity and 1.5
+  /// stop bits. The reset line of the card is an ordinary GPIO, see [crate::smartcard].
+  pub fn new_smartcard(core: u8, io_pin: (char, u8), ck_pin: (char, u8), conf: SmartcardConfig) -> Result<Self, ProgError> {
+    if UART_MAP.tx_pins.iter().zip(UART_MAP.cores.iter()).any(|i| i == (&io_pin, &core)) == false {
+      rprintln!("This pin is not available for UART communication! | UART::new_smartcard()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+
+    let pclk = if core == 1 || core == 6 {pclk2()} else {pclk1()};
+    let prescaler = match conf.clock_prescaler {
+      Some(value) => value,
+      None => ((pclk + 2 * SMARTCARD_MAX_CLOCK - 1) / (2 * SMARTCARD_MAX_CLOCK)) as u8
+    };
+    if prescaler == 0 || prescaler > 31 {
+      rprintln!("Clock prescaler has to be between 1 and 31! | UART::new_smartcard()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+
+    let baud = pclk / (2 * prescaler as u32) / 372;
+    let frame = UartConfig::new().data_bits(DataBits::Nine).parity(Parity::Even).stop_bits(StopBits::OneAndHalf)
+    .clock(ck_pin, ClockPolarity::IdleLow, ClockPhase::FirstEdge);
+
+    let uart = match Self::setup(core, io_pin, None, baud, frame) {
+      Ok(value) => value,
+      Err(error) => return Err(error)
+    };
+
+    // The I/O line is shared like in single-wire mode, but HDSEL has to stay cleared. UE -> bit 13
+    modify_cr1(core, 1 << 13, false);
+    modify_cr3(core, CR3_HDSEL, false);
+    write_gtpr(core, ((conf.guard_time as u16) << 8) | prescaler as u16);
+    modify_cr3(core, CR3_NACK, conf.nack);
+    modify_cr3(core, CR3_SCEN, true);
+    modify_cr1(core, 1 << 13, true);
+
+    return Ok(uart);
+  }
+
+  // Shared by all constructors, a missing RX pin selects single-wire mode.
+  fn setup(core: u8, tx_pin: (char, u8), rx_pin: Option<(char, u8)>, baud: u32, conf: UartConfig) -> Result<Self, ProgError> {
+    let peripheral_ptr = stm_peripherals();
+    let rcc = &peripheral_ptr.RCC;
+
+    if conf.tx_enable == false && conf.rx_enable == false {
+      rprintln!("At least one direction has to be enabled! | UART::new()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+    if (core == 4 || core == 5) && (conf.stop_bits == StopBits::Half || conf.stop_bits == StopBits::OneAndHalf) {
+      rprintln!("UART{} only supports one or two stop bits! | UART::new()", core);
+      return Err(ProgError::InvalidConfiguration);
+    }
     
     let af = if core == 1 || core == 2 || core == 3 {7}
     else {8};
     
     if let Err(_) = pin_mode(tx_pin, AlternateFunction(af)) {return Err(ProgError::Internal);}
-    if let Err(_) = pin_mode(rx_pin, AlternateFunction(af)) {return Err(ProgError::Internal);}
+    match rx_pin {
+      Some(pin) => {
+        if let Err(_) = pin_mode(pin, AlternateFunction(af)) {return Err(ProgError::Internal);}
+      },
+      None => {
+        if let Err(_) = open_drain(tx_pin, true) {return Err(ProgError::Internal);}
+        if let Err(_) = set_bias(tx_pin, GpioBias::Pullup) {return Err(ProgError::Internal);}
+      }
+    };
+
+    if let Some(pin) = conf.rts_pin {
+      if UART_FLOW_MAP.rts_pins.iter().zip(UART_FLOW_MAP.cores.iter()).any(|i| i == (&pin, &core)) == false {
+        rprintln!("P{}{} is not available as RTS pin! | UART::new()", pin.0.to_uppercase(), pin.1);
+        return Err(ProgError::InvalidConfiguration);
+      }
+      if let Err(_) = pin_mode(pin, AlternateFunction(af)) {return Err(ProgError::Internal);}
+    }
+    if let Some(pin) = conf.cts_pin {
+      if UART_FLOW_MAP.cts_pins.iter().zip(UART_FLOW_MAP.cores.iter()).any(|i| i == (&pin, &core)) == false {
+        rprintln!("P{}{} is not available as CTS pin! | UART::new()", pin.0.to_uppercase(), pin.1);
+        return Err(ProgError::InvalidConfiguration);
+      }
+      if let Err(_) = pin_mode(pin, AlternateFunction(af)) {return Err(ProgError::Internal);}
+    }
+    if let Some(pin) = conf.ck_pin {
+      if UART_CLOCK_MAP.ck_pins.iter().zip(UART_CLOCK_MAP.cores.iter()).any(|i| i == (&pin, &core)) == false {
+        rprintln!("P{}{} is not available as CK pin! | UART::new()", pin.0.to_uppercase(), pin.1);
+        return Err(ProgError::InvalidConfiguration);
+      }
+      if let Err(_) = pin_mode(pin, AlternateFunction(af)) {return Err(ProgError::Internal);}
+    }
     
+    let pclk = if core == 1 || core == 6 {pclk2()} else {pclk1()};
+    if let Err(_) = calc_baud(pclk, baud, conf.over8) {
+      rprintln!("{} baud cannot be generated from {}Hz! | UART::new()", baud, pclk);
+      return Err(ProgError::InvalidConfiguration);
+    }
+
     match core {
       1 => {
-        let uart1 = &peripheral_ptr.USART1;
         if rcc.apb2enr.read().usart1en().is_enabled() == true {
           rprintln!("U(S)ART{} is already configured! | UART::new()", core);
           return Err(ProgError::InvalidConfiguration);
         }
         rcc.apb2enr.modify(|_, w| w.usart1en().enabled());
-        set_baud(core, baud);
-        if conf & 8 > 0 {uart1.cr1.modify(|_, w| w.m().m9());}
-        if conf & 4 > 0 {uart1.cr2.modify(|_, w| w.stop().stop2());}
-        if conf & 3 == 1 {uart1.cr1.modify(|_, w| w.pce().enabled());}
-        else if conf & 3 == 2 {uart1.cr1.modify(|_, w| {w.ps().odd(); w.pce().enabled()});}
-        uart1.cr1.modify(|_, w| {
-          w.te().enabled();
-          w.re().enabled();
-          w.ue().enabled()
-        });
       },
       2 => {
-        let uart2 = &peripheral_ptr.USART2;
         if rcc.apb1enr.read().usart2en().is_enabled() == true {
           rprintln!("U(S)ART{} is already configured! | UART::new()", core);
           return Err(ProgError::InvalidConfiguration);
         }
         rcc.apb1enr.modify(|_, w| w.usart2en().enabled());
-        set_baud(core, baud);
-        if conf & 8 > 0 {uart2.cr1.modify(|_, w| w.m().m9());}
-        if conf & 4 > 0 {uart2.cr2.modify(|_, w| w.stop().stop2());}
-        if conf & 3 == 1 {uart2.cr1.modify(|_, w| w.pce().enabled());}
-        else if conf & 3 == 2 {uart2.cr1.modify(|_, w| {w.ps().odd(); w.pce().enabled()});}
-        uart2.cr1.modify(|_, w| {
-          w.te().enabled();
-          w.re().enabled();
-          w.ue().enabled()
-        });
       },
       3 => {
-        let uart3 = &peripheral_ptr.USART3;
         if rcc.apb1enr.read().usart3en().is_enabled() == true {
           rprintln!("U(S)ART{} is already configured! | UART::new()", core);
           return Err(ProgError::InvalidConfiguration);
         }
         rcc.apb1enr.modify(|_, w| w.usart3en().enabled());
-        set_baud(core, baud);
-        if conf & 8 > 0 {uart3.cr1.modify(|_, w| w.m().m9());}
-        if conf & 4 > 0 {uart3.cr2.modify(|_, w| w.stop().stop2());}
-        if conf & 3 == 1 {uart3.cr1.modify(|_, w| w.pce().enabled());}
-        else if conf & 3 == 2 {uart3.cr1.modify(|_, w| {w.ps().odd(); w.pce().enabled()});}
-        uart3.cr1.modify(|_, w| {
-          w.te().enabled();
-          w.re().enabled();
-          w.ue().enabled()
-        });
       },
       4 => {
-        let uart4 = &peripheral_ptr.UART4;
         if rcc.apb1enr.read().uart4en().is_enabled() == true {
           rprintln!("U(S)ART{} is already configured! | UART::new()", core);
           return Err(ProgError::InvalidConfiguration);
         }
         rcc.apb1enr.modify(|_, w| w.uart4en().enabled());
-        set_baud(core, baud);
-        if conf & 8 > 0 {uart4.cr1.modify(|_, w| w.m().m9());}
-        if conf & 4 > 0 {uart4.cr2.modify(|_, w| w.stop().stop2());}
-        if conf & 3 == 1 {uart4.cr1.modify(|_, w| w.pce().enabled());}
-        else if conf & 3 == 2 {uart4.cr1.modify(|_, w| {w.ps().odd(); w.pce().enabled()});}
-        uart4.cr1.modify(|_, w| {
-          w.te().enabled();
-          w.re().enabled();
-          w.ue().enabled()
-        });
       },
       5 => {
-        let uart5 = &peripheral_ptr.UART5;
         if rcc.apb1enr.read().uart5en().is_enabled() == true {
           rprintln!("U(S)ART{} is already configured! | UART::new()", core);
           return Err(ProgError::InvalidConfiguration);
         }
         rcc.apb1enr.modify(|_, w| w.uart5en().enabled());
-        set_baud(core, baud);
-        if conf & 8 > 0 {uart5.cr1.modify(|_, w| w.m().m9());}
-        if conf & 4 > 0 {uart5.cr2.modify(|_, w| w.stop().stop2());}
-        if conf & 3 == 1 {uart5.cr1.modify(|_, w| w.pce().enabled());}
-        else if conf & 3 == 2 {uart5.cr1.modify(|_, w| {w.ps().odd(); w.pce().enabled()});}
-        uart5.cr1.modify(|_, w| {
-          w.te().enabled();
-          w.re().enabled();
-          w.ue().enabled()
-        });
       },
       6 => {
-        let uart6 = &peripheral_ptr.USART6;
         if rcc.apb2enr.read().usart6en().is_enabled() == true {
           rprintln!("U(S)ART{} is already configured! | UART::new()", core);
           return Err(ProgError::InvalidConfiguration);
         }
         rcc.apb2enr.modify(|_, w| w.usart6en().enabled());
-        set_baud(core, baud);
-        if conf & 8 > 0 {uart6.cr1.modify(|_, w| w.m().m9());}
-        if conf & 4 > 0 {uart6.cr2.modify(|_, w| w.stop().stop2());}
-        if conf & 3 == 1 {uart6.cr1.modify(|_, w| w.pce().enabled());}
-        else if conf & 3 == 2 {uart6.cr1.modify(|_, w| {w.ps().odd(); w.pce().enabled()});}
-        uart6.cr1.modify(|_, w| {
-          w.te().enabled();
-          w.re().enabled();
-          w.ue().enabled()
-        });
       },
       _ => {
         rprintln!("U(S)ART{} is not a valid U(S)ART peripheral! | UART::new()", core);
@@ -155,21 +634,69 @@ impl UART {
       }
     };
 
+    // M -> bit 12, PCE -> bit 10, PS -> bit 9, OVER8 -> bit 15, STOP -> CR2 bits 13:12
+    if conf.data_bits == DataBits::Nine {modify_cr1(core, 1 << 12, true);}
+    if conf.parity != Parity::None {modify_cr1(core, 1 << 10, true);}
+    if conf.parity == Parity::Odd {modify_cr1(core, 1 << 9, true);}
+    if conf.over8 == true {modify_cr1(core, 1 << 15, true);}
+    match conf.stop_bits {
+      StopBits::One => (),
+      StopBits::Half => modify_cr2(core, 0b01 << 12, true),
+      StopBits::Two => modify_cr2(core, 0b10 << 12, true),
+      StopBits::OneAndHalf => modify_cr2(core, 0b11 << 12, true)
+    };
+    if conf.rts_pin.is_some() == true {modify_cr3(core, CR3_RTSE, true);}
+    if conf.cts_pin.is_some() == true {modify_cr3(core, CR3_CTSE, true);}
+    if rx_pin.is_none() == true {modify_cr3(core, CR3_HDSEL, true);}
+    if conf.ck_pin.is_some() == true {
+      modify_cr2(core, CR2_CPOL, conf.clock_polarity == ClockPolarity::IdleHigh);
+      modify_cr2(core, CR2_CPHA, conf.clock_phase == ClockPhase::SecondEdge);
+      modify_cr2(core, CR2_LBCL, conf.last_bit_clock);
+      modify_cr2(core, CR2_CLKEN, true);
+    }
+    if let Err(_) = set_baud(core, baud) {return Err(ProgError::Internal);}
+
+    // TE -> bit 3, RE -> bit 2, UE -> bit 13
+    if conf.tx_enable == true {modify_cr1(core, 1 << 3, true);}
+    if conf.rx_enable == true {modify_cr1(core, 1 << 2, true);}
+    modify_cr1(core, 1 << 13, true);
+
+    free(|cs| {
+      RX_BUFFERS.borrow(cs).borrow_mut()[(core - 1) as usize] = Queue::new();
+      TX_BUFFERS.borrow(cs).borrow_mut()[(core - 1) as usize] = Queue::new();
+      RX_STATS.borrow(cs).borrow_mut()[(core - 1) as usize] = RxStats::new();
+      RX_HOOKS.borrow(cs).borrow_mut()[(core - 1) as usize] = None;
+      RX_GAPS.borrow(cs).borrow_mut()[(core - 1) as usize] = (0, 0);
+      DIRECTION.borrow(cs).borrow_mut()[(core - 1) as usize] = if rx_pin.is_none() == true {Some(Direction::single_wire())} else {None};
+    });
+    modify_cr1(core, CR1_RXNEIE, true);
+    unsafe {NVIC::unmask(uart_interrupt(core));}
+
     return Ok(Self {
-      core
+      core,
+      rx_pin,
+      tx_policy: TxPolicy::Block,
+      timeout: 1000
     });
   }
 
+  /// Waits for the transmit buffer to be sent and disables the peripheral.
   pub fn end(self) {
     let peripheral_ptr = stm_peripherals();
     let rcc = &peripheral_ptr.RCC;
 
+    self.flush();
+    #[cfg(feature = "uart-dma")]
+    self.disable_dma_rx();
+    free(|cs| DIRECTION.borrow(cs).borrow_mut()[(self.core - 1) as usize] = None);
+
     match self.core {
       1 => {
         let uart1 = &peripheral_ptr.USART1;
         rcc.apb2enr.modify(|_, w| w.usart1en().disabled());
         uart1.cr1.reset();
         uart1.cr2.reset();
+        uart1.cr3.reset();
         NVIC::mask(Interrupt::USART1);
       },
       2 => {
@@ -177,6 +704,7 @@ impl UART {
         rcc.apb1enr.modify(|_, w| w.usart2en().disabled());
         uart2.cr1.reset();
         uart2.cr2.reset();
+        uart2.cr3.reset();
         NVIC::mask(Interrupt::USART2);
       },
       3 => {
@@ -184,6 +712,7 @@ impl UART {
         rcc.apb1enr.modify(|_, w| w.usart3en().disabled());
         uart3.cr1.reset();
         uart3.cr2.reset();
+        uart3.cr3.reset();
         NVIC::mask(Interrupt::USART3);
       },
       4 => {
@@ -191,6 +720,7 @@ impl UART {
         rcc.apb1enr.modify(|_, w| w.uart4en().disabled());
         uart4.cr1.reset();
         uart4.cr2.reset();
+        uart4.cr3.reset();
         NVIC::mask(Interrupt::UART4);
       },
       5 => {
@@ -198,6 +728,7 @@ impl UART {
         rcc.apb1enr.modify(|_, w| w.uart5en().disabled());
         uart5.cr1.reset();
         uart5.cr2.reset();
+        uart5.cr3.reset();
         NVIC::mask(Interrupt::UART5);
       },
       6 => {
@@ -205,74 +736,54 @@ impl UART {
         rcc.apb2enr.modify(|_, w| w.usart6en().disabled());
         uart6.cr1.reset();
         uart6.cr2.reset();
+        uart6.cr3.reset();
         NVIC::mask(Interrupt::USART6);
       },
       _ => unreachable!()
     };
   }
 
+  /// Changes the baud rate after the transmit buffer was sent and returns the generated baud rate.
+  /// The frame format stays the same.
+  ///
+  /// # Example
+  ///
+  /// ```rust,no_run
+  /// use rustuino::*;
+  /// use rustuino::uart::*;
+  ///
+  /// let serial = UART::new(1, PA9, PA10, 9600, UART_8N1).unwrap();
+  /// let rate = serial.set_baud_rate(921600).unwrap();
+  /// rprintln!("{} baud, {}% error", rate.achieved, rate.error);
+  /// ```
+  pub fn set_baud_rate(&self, baud: u32) -> Result<BaudRate, SerialError> {
+    self.flush();
+
+    let result = set_baud(self.core, baud);
+    if let Err(_) = result {rprintln!("{} baud cannot be generated! | .set_baud_rate()", baud);}
+
+    return result;
+  }
+
+  /// Returns the baud rate that is generated with the current peripheral clock.
+  pub fn baud(&self) -> u32 {
+    let pclk = if self.core == 1 || self.core == 6 {pclk2()} else {pclk1()};
+    let brr = read_brr(self.core);
+
+    // With OVER8 bit 3 of the fraction is not used
+    let div = if read_cr1(self.core) & (1 << 15) > 0 {((brr >> 4) << 3) | (brr & 0x7)}
+    else {brr};
+    if div == 0 {return 0;}
+
+    return (pclk + div / 2) / div;
+  }
+
+  /// Queues the string in the transmit buffer. What happens if the buffer is full depends on the
+  /// [TxPolicy].
   pub fn print(&self, data: &str) -> Result<(), SerialError> {
-    let peripheral_ptr = stm_peripherals();
-    
-    let bytes = data.as_bytes();
-    
-    match self.core {
-      1 => {
-        let uart1 = &peripheral_ptr.USART1;
-        for byte in bytes {
-          while uart1.sr.read().txe().bit_is_clear() == true {
-            if let Err(error) = check_uart_errors(uart1.sr.read().bits()) {return Err(error);}
-          }
-          uart1.dr.write(|w| w.dr().bits(byte.clone().into()));
-        }
-      },
-      2 => {
-        let uart2 = &peripheral_ptr.USART2;
-        for byte in bytes {
-          while uart2.sr.read().txe().bit_is_clear() == true {
-            if let Err(error) = check_uart_errors(uart2.sr.read().bits()) {return Err(error);}
-          }
-          uart2.dr.write(|w| w.dr().bits(byte.clone().into()));
-        }
-      },
-      3 => {
-        let uart3 = &peripheral_ptr.USART3;
-        for byte in bytes {
-          while uart3.sr.read().txe().bit_is_clear() == true {
-            if let Err(error) = check_uart_errors(uart3.sr.read().bits()) {return Err(error);}
-          }
-          uart3.dr.write(|w| w.dr().bits(byte.clone().into()));
-        }
-      },
-      4 => {
-        let uart4 = &peripheral_ptr.UART4;
-        for byte in bytes {
-          while uart4.sr.read().txe().bit_is_clear() == true {
-            if let Err(error) = check_uart_errors(uart4.sr.read().bits()) {return Err(error);}
-          }
-          uart4.dr.write(|w| w.dr().bits(byte.clone().into()));
-        }
-      },
-      5 => {
-        let uart5 = &peripheral_ptr.UART5;
-        for byte in bytes {
-          while uart5.sr.read().txe().bit_is_clear() == true {
-            if let Err(error) = check_uart_errors(uart5.sr.read().bits()) {return Err(error);}
-          }
-          uart5.dr.write(|w| w.dr().bits(byte.clone().into()));
-        }
-      },
-      6 => {
-        let uart6 = &peripheral_ptr.USART6;
-        for byte in bytes {
-          while uart6.sr.read().txe().bit_is_clear() == true {
-            if let Err(error) = check_uart_errors(uart6.sr.read().bits()) {return Err(error);}
-          }
-          uart6.dr.write(|w| w.dr().bits(byte.clone().into()));
-        }
-      },
-      _ => unreachable!()
-    };
+    for byte in data.as_bytes() {
+      if let Err(error) = self.queue_tx(byte.clone().into()) {return Err(error);}
+    }
 
     return Ok(());
   }
@@ -284,265 +795,1882 @@ impl UART {
     return Ok(());
   }
 
-  pub fn write(&self, data: u8) -> Result<(), SerialError> {
-    let peripheral_ptr = stm_peripherals();
-
-    match self.core {
-      1 => {
-        let uart1 = &peripheral_ptr.USART1;
-        while uart1.sr.read().txe().bit_is_clear() == true {
-          if let Err(error) = check_uart_errors(uart1.sr.read().bits()) {return Err(error);}
-        }
-        uart1.dr.write(|w| w.dr().bits(data.into()));
-      },
-      2 => {
-        let uart2 = &peripheral_ptr.USART2;
-        while uart2.sr.read().txe().bit_is_clear() == true {
-          if let Err(error) = check_uart_errors(uart2.sr.read().bits()) {return Err(error);}
-        }
-        uart2.dr.write(|w| w.dr().bits(data.into()));
-      },
-      3 => {
-        let uart3 = &peripheral_ptr.USART3;
-        while uart3.sr.read().txe().bit_is_clear() == true {
-          if let Err(error) = check_uart_errors(uart3.sr.read().bits()) {return Err(error);}
-        }
-        uart3.dr.write(|w| w.dr().bits(data.into()));
-      },
-      4 => {
-        let uart4 = &peripheral_ptr.UART4;
-        while uart4.sr.read().txe().bit_is_clear() == true {
-          if let Err(error) = check_uart_errors(uart4.sr.read().bits()) {return Err(error);}
-        }
-        uart4.dr.write(|w| w.dr().bits(data.into()));
-      },
-      5 => {
-        let uart5 = &peripheral_ptr.UART5;
-        while uart5.sr.read().txe().bit_is_clear() == true {
-          if let Err(error) = check_uart_errors(uart5.sr.read().bits()) {return Err(error);}
-        }
-        uart5.dr.write(|w| w.dr().bits(data.into()));
-      },
-      6 => {
-        let uart6 = &peripheral_ptr.USART6;
-        while uart6.sr.read().txe().bit_is_clear() == true {
-          if let Err(error) = check_uart_errors(uart6.sr.read().bits()) {return Err(error);}
-        }
-        uart6.dr.write(|w| w.dr().bits(data.into()));
-      },
-      _ => unreachable!()
+  /// Prints formatted text without allocation, usually called through the [uprint!] and
+  /// [uprintln!] macros.
+  pub fn print_fmt(&self, args: Arguments) -> Result<(), SerialError> {
+    let mut writer = FmtWriter {
+      uart: self,
+      error: None
     };
 
+    if let Err(_) = writer.write_fmt(args) {
+      match writer.error {
+        Some(error) => return Err(error),
+        None => return Err(SerialError::Prog(ProgError::Internal))
+      };
+    }
+
     return Ok(());
   }
 
-  pub fn read_char(&self) -> Option<char> {
-    let peripheral_ptr = stm_peripherals();
-
-    let buffer: u8;
-    
-    match self.core {
-      1 => {
-        let uart1 = &peripheral_ptr.USART1;
-        while uart1.sr.read().rxne().bit_is_clear() == true {
-          if let Err(_) = check_uart_errors(uart1.sr.read().bits()) {return None;}
-        }
-        buffer = uart1.dr.read().dr().bits() as u8;
-      },
-      2 => {
-        let uart2 = &peripheral_ptr.USART2;
-        while uart2.sr.read().rxne().bit_is_clear() == true {
-          if let Err(_) = check_uart_errors(uart2.sr.read().bits()) {return None;}
-        }
-        buffer = uart2.dr.read().dr().bits() as u8;
-      },
-      3 => {
-        let uart3 = &peripheral_ptr.USART3;
-        while uart3.sr.read().rxne().bit_is_clear() == true {
-          if let Err(_) = check_uart_errors(uart3.sr.read().bits()) {return None;}
-        }
-        buffer = uart3.dr.read().dr().bits() as u8;
-      },
-      4 => {
-        let uart4 = &peripheral_ptr.UART4;
-        while uart4.sr.read().rxne().bit_is_clear() == true {
-          if let Err(_) = check_uart_errors(uart4.sr.read().bits()) {return None;}
-        }
-        buffer = uart4.dr.read().dr().bits() as u8;
-      },
-      5 => {
-        let uart5 = &peripheral_ptr.UART5;
-        while uart5.sr.read().rxne().bit_is_clear() == true {
-          if let Err(_) = check_uart_errors(uart5.sr.read().bits()) {return None;}
-        }
-        buffer = uart5.dr.read().dr().bits() as u8;
-      },
-      6 => {
-        let uart6 = &peripheral_ptr.USART6;
-        while uart6.sr.read().rxne().bit_is_clear() == true {
-          if let Err(_) = check_uart_errors(uart6.sr.read().bits()) {return None;}
-        }
-        buffer = uart6.dr.read().dr().bits() as u8;
-      },
-      _ => unreachable!()
+  /// Prints an integer in the given base. Negative values only get a sign in decimal, the other
+  /// bases print the two's complement like Arduino does.
+  ///
+  /// # Example
+  ///
+  /// ```rust,no_run
+  /// use rustuino::*;
+  /// use rustuino::uart::*;
+  ///
+  /// let serial = UART::new(2, PA2, PA3, 115200, UART_8N1).unwrap();
+  /// serial.print_int(255, Base::Hex).unwrap();    // FF
+  /// serial.print_int(-42, Base::Dec).unwrap();    // -42
+  /// serial.print_float(3.14159, 2).unwrap();      // 3.14
+  /// ```
+  pub fn print_int(&self, value: i64, base: Base) -> Result<(), SerialError> {
+    let radix: u64 = match base {
+      Base::Bin => 2,
+      Base::Oct => 8,
+      Base::Dec => 10,
+      Base::Hex => 16
     };
 
-    return Some(buffer as char);
-  }
+    if base == Base::Dec && value < 0 {
+      if let Err(error) = self.write(b'-') {return Err(error);}
+    }
 
-  pub fn read_byte(&self) -> Option<u8> {
-    let peripheral_ptr = stm_peripherals();
+    let mut number = if base == Base::Dec {value.unsigned_abs()} else {value as u64};
+    let mut buffer: [u8; 64] = [0; 64];
+    let mut index = buffer.len();
 
-    let buffer: u8;
-    
-    match self.core {
-      1 => {
-        let uart1 = &peripheral_ptr.USART1;
-        while uart1.sr.read().rxne().bit_is_clear() == true {
-          if let Err(_) = check_uart_errors(uart1.sr.read().bits()) {return None;}
-        }
-        buffer = uart1.dr.read().dr().bits() as u8;
-      },
-      2 => {
-        let uart2 = &peripheral_ptr.USART2;
-        while uart2.sr.read().rxne().bit_is_clear() == true {
-          if let Err(_) = check_uart_errors(uart2.sr.read().bits()) {return None;}
-        }
-        buffer = uart2.dr.read().dr().bits() as u8;
-      },
-      3 => {
-        let uart3 = &peripheral_ptr.USART3;
-        while uart3.sr.read().rxne().bit_is_clear() == true {
-          if let Err(_) = check_uart_errors(uart3.sr.read().bits()) {return None;}
-        }
-        buffer = uart3.dr.read().dr().bits() as u8;
-      },
-      4 => {
-        let uart4 = &peripheral_ptr.UART4;
-        while uart4.sr.read().rxne().bit_is_clear() == true {
-          if let Err(_) = check_uart_errors(uart4.sr.read().bits()) {return None;}
-        }
-        buffer = uart4.dr.read().dr().bits() as u8;
-      },
-      5 => {
-        let uart5 = &peripheral_ptr.UART5;
-        while uart5.sr.read().rxne().bit_is_clear() == true {
-          if let Err(_) = check_uart_errors(uart5.sr.read().bits()) {return None;}
-        }
-        buffer = uart5.dr.read().dr().bits() as u8;
-      },
-      6 => {
-        let uart6 = &peripheral_ptr.USART6;
-        while uart6.sr.read().rxne().bit_is_clear() == true {
-          if let Err(_) = check_uart_errors(uart6.sr.read().bits()) {return None;}
-        }
-        buffer = uart6.dr.read().dr().bits() as u8;
-      },
-      _ => unreachable!()
+    loop {
+      let digit = (number % radix) as u8;
+      index -= 1;
+      buffer[index] = if digit < 10 {b'0' + digit} else {b'A' + digit - 10};
+      number /= radix;
+      if number == 0 {break;}
+    }
+
+    for byte in &buffer[index..] {
+      if let Err(error) = self.write(*byte) {return Err(error);}
+    }
+
+    return Ok(());
+  }
+
+  /// Prints a float rounded to the given number of decimal places. Values outside of the u32 range
+  /// are printed as `ovf`.
+  pub fn print_float(&self, value: f64, digits: u8) -> Result<(), SerialError> {
+    if value.is_nan() == true {return self.print("nan");}
+    if value.is_infinite() == true {
+      if value < 0.0 {return self.print("-inf");}
+      else {return self.print("inf");}
+    }
+    if value > 4294967040.0 || value < -4294967040.0 {return self.print("ovf");}
+
+    let mut number = value;
+    if number < 0.0 {
+      if let Err(error) = self.write(b'-') {return Err(error);}
+      number = -number;
+    }
+
+    let mut rounding = 0.5;
+    for _ in 0..digits {rounding /= 10.0;}
+    number += rounding;
+
+    let int_part = number as u32;
+    let mut remainder = number - int_part as f64;
+    if let Err(error) = self.print_int(int_part.into(), Base::Dec) {return Err(error);}
+
+    if digits > 0 {
+      if let Err(error) = self.write(b'.') {return Err(error);}
+    }
+    for _ in 0..digits {
+      remainder *= 10.0;
+      let digit = remainder as u8;
+      if let Err(error) = self.write(b'0' + digit) {return Err(error);}
+      remainder -= digit as f64;
+    }
+
+    return Ok(());
+  }
+
+  pub fn write(&self, data: u8) -> Result<(), SerialError> {
+    return self.queue_tx(data.into());
+  }
+
+  /// Queues a 9-bit word, for ports that were configured with [DataBits::Nine].
+  pub fn write_word(&self, data: u16) -> Result<(), SerialError> {
+    if data > 0x1FF {
+      rprintln!("Data word is longer than 9 bits! | .write_word()");
+      return Err(SerialError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    return self.queue_tx(data);
+  }
+
+  /// Blocks until the transmit buffer is empty and the last byte has left the shift register. Inside
+  /// of interrupt handlers the buffer is sent by polling and the RS-485 driver is released by the
+  /// USART interrupt after the handler returned.
+  pub fn flush(&self) {
+    if in_handler() == true {
+      while free(|cs| TX_BUFFERS.borrow(cs).borrow()[(self.core - 1) as usize].len()) > 0 {transmit_polled(self.core);}
+      while read_sr(self.core) & SR_TC == 0 {}
+      return;
+    }
+
+    while free(|cs| TX_BUFFERS.borrow(cs).borrow()[(self.core - 1) as usize].len()) > 0 {}
+    while read_cr1(self.core) & CR1_TXEIE > 0 {}
+    while read_sr(self.core) & SR_TC == 0 {}
+    while free(|cs| DIRECTION.borrow(cs).borrow()[(self.core - 1) as usize].map_or(false, |i| i.transmitting)) == true {}
+  }
+
+  /// Returns the number of bytes that can be queued without blocking.
+  pub fn available_for_write(&self) -> usize {
+    return free(|cs| {
+      let buffers = TX_BUFFERS.borrow(cs).borrow();
+      buffers[(self.core - 1) as usize].capacity() - buffers[(self.core - 1) as usize].len()
+    });
+  }
+
+  pub fn set_tx_policy(&mut self, policy: TxPolicy) {
+    self.tx_policy = policy;
+  }
+
+  /// The number of the port as it was passed to the constructor.
+  pub fn core(&self) -> u8 {
+    return self.core;
+  }
+
+  /// Sets a function that is called from the receive interrupt with every received word. Words for
+  /// which it returns true are consumed and not put into the receive buffer. The hook must not
+  /// block, see [queue_word] for sending data from it.
+  pub fn set_rx_hook(&self, hook: Option<fn(u16) -> bool>) {
+    free(|cs| RX_HOOKS.borrow(cs).borrow_mut()[(self.core - 1) as usize] = hook);
+  }
+
+  /// Blocks until a byte is in the receive buffer and returns it as a character.
+  pub fn read_char(&self) -> Option<char> {
+    match self.read_byte() {
+      Some(byte) => return Some(byte as char),
+      None => return None
+    };
+  }
+
+  /// Blocks until a byte is in the receive buffer and returns it.
+  pub fn read_byte(&self) -> Option<u8> {
+    loop {
+      if let Some(byte) = self.try_read() {return Some(byte);}
+    }
+  }
+
+  /// Returns the number of bytes that are waiting in the receive buffer.
+  pub fn available(&self) -> usize {
+    return free(|cs| RX_BUFFERS.borrow(cs).borrow()[(self.core - 1) as usize].len());
+  }
+
+  /// Returns the next byte of the receive buffer without removing it.
+  pub fn peek(&self) -> Option<u8> {
+    return free(|cs| {
+      match RX_BUFFERS.borrow(cs).borrow()[(self.core - 1) as usize].peek() {
+        Some(&data) => Some(data as u8),
+        None => None
+      }
+    });
+  }
+
+  /// Removes and returns the next byte of the receive buffer or `None` if it is empty.
+  pub fn try_read(&self) -> Option<u8> {
+    match dequeue_rx(self.core) {
+      Some(data) => return Some(data as u8),
+      None => return None
     };
+  }
+
+  /// Blocks until a word is in the receive buffer and returns all 9 bits of it.
+  pub fn read_word(&self) -> Option<u16> {
+    loop {
+      if let Some(word) = self.try_read_word() {return Some(word);}
+    }
+  }
+
+  pub fn try_read_word(&self) -> Option<u16> {
+    match dequeue_rx(self.core) {
+      Some(data) => return Some(data & !RX_GAP),
+      None => return None
+    };
+  }
+
+  /// Marks every received byte that follows at least `us` µs of silence, which is measured in the
+  /// receive interrupt, so frame boundaries are kept no matter how late the buffer is read. 0
+  /// turns the marks off. The microsecond timer from [start_time] is started if it is not already
+  /// running.
+  pub fn set_rx_gap(&self, us: usize) {
+    let peripheral_ptr = stm_peripherals();
+    let rcc = &peripheral_ptr.RCC;
+
+    if us > 0 && rcc.apb1enr.read().tim7en().is_disabled() == true {start_time();}
+    free(|cs| RX_GAPS.borrow(cs).borrow_mut()[(self.core - 1) as usize] = (us, micros()));
+  }
+
+  /// Removes the next byte of the receive buffer and returns it together with true if the silence
+  /// set with [UART::set_rx_gap] came before it.
+  pub fn try_read_gap(&self) -> Option<(u8, bool)> {
+    match dequeue_rx(self.core) {
+      Some(data) => return Some((data as u8, data & RX_GAP > 0)),
+      None => return None
+    };
+  }
+
+  /// Returns the µs since the last byte was received, as far as it is measured with
+  /// [UART::set_rx_gap].
+  pub fn rx_silence(&self) -> usize {
+    let last = free(|cs| RX_GAPS.borrow(cs).borrow()[(self.core - 1) as usize].1);
+    return micros().wrapping_sub(last);
+  }
+
+  /// Moves as many bytes as are available and fit into `buffer` out of the receive buffer and
+  /// returns their number.
+  pub fn read_bytes(&self, buffer: &mut [u8]) -> usize {
+    let mut count: usize = 0;
+
+    while count < buffer.len() {
+      match self.try_read() {
+        Some(byte) => buffer[count] = byte,
+        None => break
+      };
+      count += 1;
+    }
+
+    return count;
+  }
+
+  pub fn rx_stats(&self) -> RxStats {
+    return free(|cs| RX_STATS.borrow(cs).borrow()[(self.core - 1) as usize]);
+  }
+
+  pub fn clear_rx_stats(&self) {
+    free(|cs| RX_STATS.borrow(cs).borrow_mut()[(self.core - 1) as usize] = RxStats::new());
+  }
+
+  fn queue_tx(&self, data: u16) -> Result<(), SerialError> {
+    loop {
+      if enqueue_tx(self.core, data) == true {return Ok(());}
+
+      match self.tx_policy {
+        TxPolicy::Block => {
+          if in_handler() == true {transmit_polled(self.core);}
+        },
+        TxPolicy::Drop => return Ok(()),
+        TxPolicy::Error => return Err(SerialError::BufferFull)
+      };
+    }
+  }
+}
+
+
+// Multiprocessor Functions =======================================================================
+impl UART {
+  /// Sets the own 4-bit node address and the wakeup method for multi-drop networks. With
+  /// [WakeupMethod::AddressMark] a muted receiver ignores all words until an address word (MSB set)
+  /// with its own address arrives.
+  ///
+  /// # Example
+  ///
+  /// ```rust,no_run
+  /// use rustuino::*;
+  /// use rustuino::uart::*;
+  ///
+  /// let serial = UART::new(2, PA2, PA3, 115200, UART_9N1).unwrap();
+  /// serial.set_address(3, WakeupMethod::AddressMark).unwrap();
+  /// serial.mute();
+  ///
+  /// // Wakes up when the master calls write_address(3)
+  /// let command = serial.read_word().unwrap();
+  /// ```
+  pub fn set_address(&self, address: u8, wakeup: WakeupMethod) -> Result<(), SerialError> {
+    if address > 15 {
+      rprintln!("Node address has to be between 0 and 15! | .set_address()");
+      return Err(SerialError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    // ADD -> CR2 bits 3:0, WAKE -> CR1 bit 11
+    modify_cr2(self.core, 0xF, false);
+    modify_cr2(self.core, address as u32, true);
+    modify_cr1(self.core, 1 << 11, wakeup == WakeupMethod::AddressMark);
+
+    return Ok(());
+  }
+
+  /// Puts the receiver into mute mode until the wakeup condition occurs.
+  pub fn mute(&self) {
+    modify_cr1(self.core, CR1_RWU, true);
+  }
+
+  pub fn unmute(&self) {
+    modify_cr1(self.core, CR1_RWU, false);
+  }
+
+  pub fn is_muted(&self) -> bool {
+    return read_cr1(self.core) & CR1_RWU > 0;
+  }
+
+  /// Sends an address word that wakes up the node with the given address. The MSB of the word marks
+  /// it as address, which is bit 8 in 9-bit mode and bit 7 in 8-bit mode.
+  pub fn write_address(&self, address: u8) -> Result<(), SerialError> {
+    if address > 15 {
+      rprintln!("Node address has to be between 0 and 15! | .write_address()");
+      return Err(SerialError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    let mark: u16 = if read_cr1(self.core) & (1 << 12) > 0 {0x100} else {0x80};
+    return self.queue_tx(mark | address as u16);
+  }
+}
+
+
+// RS-485 Functions ===============================================================================
+impl UART {
+  /// Switches the port to RS-485 half-duplex mode. The driver enable pin is set before the first
+  /// byte of a transmission and cleared after the stop bit of the last byte. The delays in
+  /// microseconds give the transceiver time to turn the bus around. The receiver is disabled while
+  /// transmitting, so the own data is not echoed back.
+  ///
+  /// The post delay is timed with TIM14 and its interrupt, so the USART interrupt does not have to
+  /// wait for it.
+  ///
+  /// # Example
+  ///
+  /// ```rust,no_run
+  /// use rustuino::*;
+  /// use rustuino::uart::*;
+  ///
+  /// let serial = UART::new(2, PA2, PA3, 19200, UART_8N1).unwrap();
+  /// serial.enable_rs485(PA1, 10, 10).unwrap();
+  /// serial.println("Hello bus!").unwrap();
+  /// ```
+  pub fn enable_rs485(&self, de_pin: (char, u8), pre_delay_us: u32, post_delay_us: u32) -> Result<(), SerialError> {
+    if let Err(error) = pin_mode(de_pin, Output) {
+      rprintln!("Driver enable pin could not be configured! | .enable_rs485()");
+      return Err(SerialError::Prog(error));
+    }
+
+    self.flush();
+    if let Err(_) = digital_write(de_pin, false) {return Err(SerialError::Prog(ProgError::Internal));}
+
+    // The post delay is measured with micros
+    let peripheral_ptr = stm_peripherals();
+    let rcc = &peripheral_ptr.RCC;
+    if post_delay_us > 0 && rcc.apb1enr.read().tim7en().is_disabled() == true {start_time();}
+
+    free(|cs| DIRECTION.borrow(cs).borrow_mut()[(self.core - 1) as usize] = Some(Direction {
+      pin: Some(de_pin),
+      pre_delay: pre_delay_us,
+      post_delay: post_delay_us,
+      transmitting: false,
+      receiver: false,
+      release: None
+    }));
+
+    return Ok(());
+  }
+
+  /// Leaves RS-485 mode, the driver enable pin stays low. A single-wire port keeps switching its
+  /// receiver off while transmitting.
+  pub fn disable_rs485(&self) {
+    self.flush();
+
+    let direction = if read_cr3(self.core) & CR3_HDSEL > 0 {Some(Direction::single_wire())} else {None};
+    free(|cs| DIRECTION.borrow(cs).borrow_mut()[(self.core - 1) as usize] = direction);
+  }
+}
+
+
+// LIN Functions ==================================================================================
+impl UART {
+  /// Switches the port to LIN mode, which adds break detection and sending. The port has to be
+  /// configured with 8N1. The frame layer is in the [lin](crate::lin) module.
+  pub fn enable_lin(&self, break_length: BreakLength) {
+    self.flush();
+    free(|cs| RX_BUFFERS.borrow(cs).borrow_mut()[(self.core - 1) as usize] = Queue::new());
+    LIN_BREAKS.fetch_and(!(1 << (self.core - 1)), Ordering::SeqCst);
+
+    modify_cr2(self.core, CR2_LBDL, break_length == BreakLength::Bits11);
+    modify_cr2(self.core, CR2_LINEN | CR2_LBDIE, true);
+  }
+
+  pub fn disable_lin(&self) {
+    modify_cr2(self.core, CR2_LINEN | CR2_LBDIE, false);
+  }
+
+  /// Sends a break after the transmit buffer was sent and waits until it is done.
+  pub fn send_break(&self) {
+    self.flush();
+    modify_cr1(self.core, CR1_SBK, true);
+    while read_cr1(self.core) & CR1_SBK > 0 {}
+  }
+
+  /// Returns true if a break was received since the last call. The receive buffer gets cleared with
+  /// every break, so it only contains the data of the current frame.
+  pub fn break_detected(&self) -> bool {
+    let mask: u8 = 1 << (self.core - 1);
+    return LIN_BREAKS.fetch_and(!mask, Ordering::SeqCst) & mask > 0;
+  }
+}
+
+
+// IrDA Functions =================================================================================
+impl UART {
+  /// Switches the port to IrDA SIR mode, where the data is encoded as short pulses for an infrared
+  /// transceiver. The baud rate must not exceed 115200 and only one stop bit is allowed. The
+  /// low-power mode is only available on USART1-3 and USART6.
+  ///
+  /// # Example
+  ///
+  /// ```rust,no_run
+  /// use rustuino::*;
+  /// use rustuino::uart::*;
+  ///
+  /// let serial = UART::new(2, PA2, PA3, 9600, UART_8N1).unwrap();
+  /// serial.enable_irda(IrdaMode::Normal).unwrap();
+  /// serial.println("Hello infrared!").unwrap();
+  /// ```
+  pub fn enable_irda(&self, mode: IrdaMode) -> Result<(), SerialError> {
+    if read_cr2(self.core) & (0b11 << 12) != 0 {
+      rprintln!("IrDA mode only supports one stop bit! | .enable_irda()");
+      return Err(SerialError::Prog(ProgError::InvalidConfiguration));
+    }
+    if read_cr2(self.core) & (CR2_LINEN | CR2_CLKEN) > 0 || read_cr3(self.core) & (CR3_HDSEL | CR3_SCEN) > 0 {
+      rprintln!("IrDA mode cannot be combined with LIN, synchronous, single-wire or smartcard mode! | .enable_irda()");
+      return Err(SerialError::Prog(ProgError::InvalidConfiguration));
+    }
+    if mode == IrdaMode::LowPower && (self.core == 4 || self.core == 5) {
+      rprintln!("UART{} does not support the IrDA low-power mode! | .enable_irda()", self.core);
+      return Err(SerialError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    self.flush();
+
+    // The prescaler has to be 1 in normal mode and divides the peripheral clock down to the pulse
+    // frequency in low-power mode
+    let psc = match mode {
+      IrdaMode::Normal => 1,
+      IrdaMode::LowPower => {
+        let pclk = if self.core == 1 || self.core == 6 {pclk2()} else {pclk1()};
+        ((pclk + IRDA_LP_FREQ / 2) / IRDA_LP_FREQ).max(1).min(255) as u16
+      }
+    };
+    if self.core != 4 && self.core != 5 {write_gtpr(self.core, psc);}
+
+    modify_cr3(self.core, CR3_IRLP, mode == IrdaMode::LowPower);
+    modify_cr3(self.core, CR3_IREN, true);
+
+    return Ok(());
+  }
+
+  pub fn disable_irda(&self) {
+    self.flush();
+    modify_cr3(self.core, CR3_IREN | CR3_IRLP, false);
+  }
+}
+
+
+// Synchronous Functions ==========================================================================
+impl UART {
+  /// Sends a byte and returns the byte that was clocked in at the same time. Only available in
+  /// synchronous mode, bytes that were received by earlier writes are discarded.
+  ///
+  /// # Example
+  ///
+  /// ```rust,no_run
+  /// use rustuino::*;
+  /// use rustuino::uart::*;
+  ///
+  /// let config = UartConfig::new().clock(PA8, ClockPolarity::IdleLow, ClockPhase::FirstEdge).last_bit_clock(true);
+  /// let serial = UART::new(1, PA9, PA10, 1000000, config).unwrap();
+  /// let status = serial.transfer(0x9F).unwrap();
+  /// ```
+  pub fn transfer(&self, data: u8) -> Result<u8, SerialError> {
+    if read_cr2(self.core) & CR2_CLKEN == 0 {
+      rprintln!("Port is not in synchronous mode! | .transfer()");
+      return Err(SerialError::Prog(ProgError::InvalidConfiguration));
+    }
+    if read_cr1(self.core) & CR1_RE == 0 {
+      rprintln!("Receiver is disabled! | .transfer()");
+      return Err(SerialError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    self.flush();
+    free(|cs| RX_BUFFERS.borrow(cs).borrow_mut()[(self.core - 1) as usize] = Queue::new());
+
+    if let Err(error) = self.write(data) {return Err(error);}
+    return self.timed_read();
+  }
+
+  /// Sends the buffer and replaces every byte with the byte that was clocked in at the same time.
+  pub fn transfer_bytes(&self, buffer: &mut [u8]) -> Result<(), SerialError> {
+    for byte in buffer.iter_mut() {
+      match self.transfer(*byte) {
+        Ok(value) => *byte = value,
+        Err(error) => return Err(error)
+      };
+    }
+
+    return Ok(());
+  }
+}
+
+
+// Autobaud Functions =============================================================================
+impl UART {
+  /// Measures the first byte that is received and switches the port to the closest of the candidate
+  /// baud rates, which is returned. The RX pin is connected to its timer in input capture mode while
+  /// measuring, so the timer must not be used for PWM at the same time. The measured byte is not
+  /// received by the port.
+  ///
+  /// The shortest pulse of the byte is taken as one bit, so the first byte has to contain a single
+  /// bit, like the sync byte 0x55 or any byte starting with a 1. The timeout is given in ms.
+  ///
+  /// | RX pin | Timer   |
+  /// | ------ | ------- |
+  /// | PA10   | TIM1    |
+  /// | PB7    | TIM4    |
+  /// | PB11   | TIM2    |
+  /// | PA1    | TIM2    |
+  /// | PC7    | TIM3    |
+  ///
+  /// # Example
+  ///
+  /// ```rust,no_run
+  /// use rustuino::*;
+  /// use rustuino::uart::*;
+  ///
+  /// let serial = UART::new(1, PA9, PA10, 115200, UART_8N1).unwrap();
+  /// let baud = serial.detect_baud(&[9600, 19200, 57600, 115200], 5000).unwrap();
+  /// rprintln!("Host talks with {} baud", baud);
+  /// ```
+  pub fn detect_baud(&self, candidates: &[u32], timeout: usize) -> Result<u32, SerialError> {
+    let pin = match self.rx_pin {
+      Some(value) => value,
+      None => {
+        rprintln!("Autobaud needs a separate RX pin! | .detect_baud()");
+        return Err(SerialError::Prog(ProgError::InvalidConfiguration));
+      }
+    };
+    let (timer, ccch) = match PWM_MAP.pins.iter().position(|&i| i == pin) {
+      Some(index) => (PWM_MAP.timers[index], PWM_MAP.ccchs[index]),
+      None => {
+        rprintln!("P{}{} is not connected to a timer! | .detect_baud()", pin.0.to_uppercase(), pin.1);
+        return Err(SerialError::Prog(ProgError::InvalidConfiguration));
+      }
+    };
+    let min_baud = match candidates.iter().min() {
+      Some(value) if *value > 0 => *value,
+      _ => {
+        rprintln!("No valid candidates were given! | .detect_baud()");
+        return Err(SerialError::Prog(ProgError::InvalidConfiguration));
+      }
+    };
+
+    // The 16-bit counter has to cover one frame of the slowest candidate
+    let timer_clk = if timer == 1 {timer_clk2()} else {timer_clk1()};
+    let psc = (timer_clk as u64 * 11 / (min_baud as u64 * 65536)) as u32;
+    let counter_freq = timer_clk / (psc + 1);
+    let frame_ticks = (counter_freq as u64 * 11 / min_baud as u64) as u16;
+
+    // The receiver would see garbage while the pin is connected to the timer
+    self.flush();
+    let receiver = read_cr1(self.core) & CR1_RE > 0;
+    modify_cr1(self.core, CR1_RE, false);
+    let af = if timer == 1 || timer == 2 {1} else {2};
+    if let Err(error) = pin_mode(pin, AlternateFunction(af)) {return Err(SerialError::Prog(error));}
+    capture_start(timer, ccch, psc as u16);
+
+    let mut edges: [u16; 10] = [0; 10];
+    let mut count = 0;
+    let start = now();
+    loop {
+      if let Some(value) = capture_read(timer, ccch) {
+        edges[count] = value;
+        count += 1;
+        if count == edges.len() {break;}
+      }
+
+      // The line has been idle for a whole frame after the last edge
+      if count > 0 && capture_counter(timer).wrapping_sub(edges[count - 1]) > frame_ticks {break;}
+      if count == 0 && now().wrapping_sub(start) >= timeout {break;}
+    }
+
+    capture_stop(timer);
+    let uart_af = if self.core == 1 || self.core == 2 || self.core == 3 {7} else {8};
+    if let Err(error) = pin_mode(pin, AlternateFunction(uart_af)) {return Err(SerialError::Prog(error));}
+
+    let result = match evaluate_edges(&edges[..count], counter_freq, candidates) {
+      Ok(baud) => set_baud(self.core, baud).map(|_| baud),
+      Err(error) => Err(error)
+    };
+
+    free(|cs| RX_BUFFERS.borrow(cs).borrow_mut()[(self.core - 1) as usize] = Queue::new());
+    modify_cr1(self.core, CR1_RE, receiver);
+
+    return result;
+  }
+}
+
+
+// Stream Functions ===============================================================================
+impl UART {
+  /// Sets the time in milliseconds that the stream functions wait for the next byte, the default is
+  /// one second. The timeouts use the [millis] timer, which gets started if it is not running yet.
+  pub fn set_timeout(&mut self, ms: usize) {
+    self.timeout = ms;
+  }
+
+  /// Reads bytes into `buffer` until the terminator arrives, which is not stored. Returns the number
+  /// of bytes read, or stops early if the buffer is full.
+  pub fn read_bytes_until(&self, terminator: u8, buffer: &mut [u8]) -> Result<usize, SerialError> {
+    let mut count: usize = 0;
+
+    while count < buffer.len() {
+      let byte = match self.timed_read() {
+        Ok(value) => value,
+        Err(error) => return Err(error)
+      };
+      if byte == terminator {break;}
+
+      buffer[count] = byte;
+      count += 1;
+    }
+
+    return Ok(count);
+  }
+
+  /// Reads a line that is terminated with `\n` or `\r\n`, without the line ending.
+  ///
+  /// # Example
+  ///
+  /// ```rust,no_run
+  /// use rustuino::*;
+  /// use rustuino::uart::*;
+  ///
+  /// let mut serial = UART::new(2, PA2, PA3, 115200, UART_8N1).unwrap();
+  /// serial.set_timeout(5000);
+  ///
+  /// loop {
+  ///   match serial.read_line::<64>() {
+  ///     Ok(line) if line == "led on" => digital_write(PA5, true).unwrap(),
+  ///     Ok(line) if line == "led off" => digital_write(PA5, false).unwrap(),
+  ///     Ok(line) => rprintln!("Unknown command: {}", line),
+  ///     Err(_) => rprintln!("No command received")
+  ///   };
+  /// }
+  /// ```
+  pub fn read_line<const N: usize>(&self) -> Result<String<N>, SerialError> {
+    let mut line: String<N> = String::new();
+
+    loop {
+      let byte = match self.timed_read() {
+        Ok(value) => value,
+        Err(error) => return Err(error)
+      };
+      if byte == b'\n' {break;}
+
+      if line.push(byte as char).is_err() == true {
+        rprintln!("Line does not fit into the string! | .read_line()");
+        return Err(SerialError::Prog(ProgError::OutOfMemory));
+      }
+    }
+
+    if line.ends_with('\r') == true {line.pop();}
+
+    return Ok(line);
+  }
+
+  /// Reads from the stream until `pattern` was received.
+  pub fn find(&self, pattern: &[u8]) -> Result<(), SerialError> {
+    let mut index: usize = 0;
+
+    while index < pattern.len() {
+      let byte = match self.timed_read() {
+        Ok(value) => value,
+        Err(error) => return Err(error)
+      };
+
+      index = next_match(pattern, index, byte);
+    }
+
+    return Ok(());
+  }
+
+  /// Skips all characters up to the first digit or minus sign and returns the integer that starts
+  /// there. The number ends at the first other character or when no more data arrives.
+  pub fn parse_int(&self) -> Result<i64, SerialError> {
+    let mut value: i64 = 0;
+
+    let negative = match self.skip_to_number(false) {
+      Ok(byte) => byte == b'-',
+      Err(error) => return Err(error)
+    };
+    if negative == true {self.try_read();}
+
+    while let Ok(byte) = self.timed_peek() {
+      if byte.is_ascii_digit() == false {break;}
+
+      value = value.saturating_mul(10).saturating_add((byte - b'0') as i64);
+      self.try_read();
+    }
+
+    if negative == true {return Ok(-value);}
+    else {return Ok(value);}
+  }
+
+  /// Same as [UART::parse_int], but also accepts a decimal point.
+  pub fn parse_float(&self) -> Result<f64, SerialError> {
+    let mut value: f64 = 0.0;
+    let mut fraction: f64 = 1.0;
+    let mut decimal = false;
+
+    let negative = match self.skip_to_number(true) {
+      Ok(byte) => byte == b'-',
+      Err(error) => return Err(error)
+    };
+    if negative == true {self.try_read();}
+
+    while let Ok(byte) = self.timed_peek() {
+      if byte == b'.' && decimal == false {decimal = true;}
+      else if byte.is_ascii_digit() == true {
+        value = value * 10.0 + (byte - b'0') as f64;
+        if decimal == true {fraction *= 0.1;}
+      }
+      else {break;}
+
+      self.try_read();
+    }
+
+    if negative == true {return Ok(-value * fraction);}
+    else {return Ok(value * fraction);}
+  }
+
+  /// Waits up to the timeout for the next byte.
+  pub fn timed_read(&self) -> Result<u8, SerialError> {
+    let start = now();
+
+    loop {
+      if let Some(byte) = self.try_read() {return Ok(byte);}
+      if now().wrapping_sub(start) >= self.timeout {return Err(SerialError::Prog(ProgError::TimedOut));}
+    }
+  }
+
+  fn timed_peek(&self) -> Result<u8, SerialError> {
+    let start = now();
+
+    loop {
+      if let Some(byte) = self.peek() {return Ok(byte);}
+      if now().wrapping_sub(start) >= self.timeout {return Err(SerialError::Prog(ProgError::TimedOut));}
+    }
+  }
+
+  // Discards everything up to the start of a number and returns its first character
+  fn skip_to_number(&self, decimal: bool) -> Result<u8, SerialError> {
+    loop {
+      let byte = match self.timed_peek() {
+        Ok(value) => value,
+        Err(error) => return Err(error)
+      };
+      if byte.is_ascii_digit() == true || byte == b'-' || (decimal == true && byte == b'.') {return Ok(byte);}
+
+      self.try_read();
+    }
+  }
+}
+
+impl Write for UART {
+  fn write_str(&mut self, s: &str) -> core::fmt::Result {
+    match self.print(s) {
+      Ok(_) => return Ok(()),
+      Err(_) => return Err(core::fmt::Error)
+    };
+  }
+}
+
+/// Prints formatted text on a UART port, like `print!` in the standard library.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustuino::*;
+/// use rustuino::uart::*;
+///
+/// let serial = UART::new(2, PA2, PA3, 115200, UART_8N1).unwrap();
+/// uprint!(serial, "Temperature: {:.1}C ", 21.5).unwrap();
+/// uprintln!(serial, "Time: {}ms", millis()).unwrap();
+/// ```
+#[macro_export]
+macro_rules! uprint {
+  ($serial:expr, $($arg:tt)*) => {
+    $serial.print_fmt(core::format_args!($($arg)*))
+  };
+}
+
+/// Same as [uprint!], but ends the line with `\r\n`.
+#[macro_export]
+macro_rules! uprintln {
+  ($serial:expr) => {
+    $serial.print("\r\n")
+  };
+  ($serial:expr, $($arg:tt)*) => {
+    $serial.print_fmt(core::format_args!("{}\r\n", core::format_args!($($arg)*)))
+  };
+}
+
+
+// DMA Functions ==================================================================================
+#[cfg(feature = "uart-dma")]
+impl UART {
+  /// Receives continuously into `buffer` with DMA instead of the receive interrupt. The callback is
+  /// called from an interrupt with all new bytes when the line goes idle after a frame and when the
+  /// buffer is half full or wraps around, so a frame can arrive in two parts. Until
+  /// [UART::disable_dma_rx] is called the normal read functions return nothing.
+  ///
+  /// The DMA functions need the `uart-dma` feature, which lets this crate handle the interrupts of
+  /// DMA1 and of the DMA2 streams 1, 2, 6 and 7.
+  ///
+  /// # Example
+  ///
+  /// ```rust,no_run
+  /// use rustuino::*;
+  /// use rustuino::uart::*;
+  ///
+  /// static mut RX_BUFFER: [u8; 256] = [0; 256];
+  ///
+  /// fn on_frame(data: &[u8]) {
+  ///   rprintln!("Received {} bytes", data.len());
+  /// }
+  ///
+  /// let serial = UART::new(2, PA2, PA3, 921600, UART_8N1).unwrap();
+  /// serial.enable_dma_rx(unsafe {&mut RX_BUFFER}, on_frame).unwrap();
+  /// ```
+  pub fn enable_dma_rx(&self, buffer: &'static mut [u8], callback: fn(&[u8])) -> Result<(), SerialError> {
+    let peripheral_ptr = stm_peripherals();
+    let rcc = &peripheral_ptr.RCC;
+
+    if buffer.len() == 0 || buffer.len() > 65535 {
+      rprintln!("DMA buffer has to be between 1 and 65535 bytes long! | .enable_dma_rx()");
+      return Err(SerialError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    let index = (self.core - 1) as usize;
+    let (dma, stream) = dma_stream(self.core, false);
+
+    if UART_DMA_MAP.dmas[index] == 1 {rcc.ahb1enr.modify(|_, w| w.dma1en().enabled());}
+    else {rcc.ahb1enr.modify(|_, w| w.dma2en().enabled());}
+
+    modify_cr1(self.core, CR1_RXNEIE, false);
+    stop_dma(self.core, false);
+
+    free(|cs| DMA_RX.borrow(cs).borrow_mut()[index] = Some(DmaRx {
+      address: buffer.as_ptr() as u32,
+      len: buffer.len(),
+      position: 0,
+      callback
+    }));
+
+    dma.st[stream].par.write(|w| unsafe {w.bits(dr_address(self.core))});
+    dma.st[stream].m0ar.write(|w| unsafe {w.bits(buffer.as_ptr() as u32)});
+    dma.st[stream].ndtr.write(|w| unsafe {w.bits(buffer.len() as u32)});
+    dma.st[stream].cr.write(|w| unsafe {
+      w.bits((UART_DMA_MAP.channels[index] << 25) | DMA_MINC | DMA_CIRC | DMA_TCIE | DMA_HTIE)
+    });
+    unsafe {NVIC::unmask(dma_interrupt(self.core, false));}
+    dma.st[stream].cr.modify(|r, w| unsafe {w.bits(r.bits() | DMA_EN)});
+
+    // Reading SR and DR clears a pending idle flag
+    read_sr(self.core);
+    read_dr(self.core);
+    modify_cr3(self.core, CR3_DMAR, true);
+    modify_cr1(self.core, CR1_IDLEIE, true);
+
+    return Ok(());
+  }
+
+  /// Stops the DMA reception and switches back to the interrupt driven receive buffer.
+  pub fn disable_dma_rx(&self) {
+    let index = (self.core - 1) as usize;
+
+    if free(|cs| DMA_RX.borrow(cs).borrow_mut()[index].take()).is_none() == true {return;}
+
+    modify_cr1(self.core, CR1_IDLEIE, false);
+    modify_cr3(self.core, CR3_DMAR, false);
+    stop_dma(self.core, false);
+    modify_cr1(self.core, CR1_RXNEIE, true);
+  }
+
+  /// Sends `data` with DMA and returns immediately. The optional callback is called from an
+  /// interrupt when the transfer is complete. Data that was queued with the normal print functions
+  /// should be flushed first, otherwise both get mixed up.
+  pub fn write_dma(&self, data: &'static [u8], callback: Option<fn()>) -> Result<(), SerialError> {
+    let peripheral_ptr = stm_peripherals();
+    let rcc = &peripheral_ptr.RCC;
+
+    if data.len() == 0 || data.len() > 65535 {
+      rprintln!("DMA transfers have to be between 1 and 65535 bytes long! | .write_dma()");
+      return Err(SerialError::Prog(ProgError::InvalidConfiguration));
+    }
+    if self.dma_tx_busy() == true {
+      rprintln!("DMA transfer is still running! | .write_dma()");
+      return Err(SerialError::Prog(ProgError::PermissionDenied));
+    }
+
+    let index = (self.core - 1) as usize;
+    let (dma, stream) = dma_stream(self.core, true);
+
+    if UART_DMA_MAP.dmas[index] == 1 {rcc.ahb1enr.modify(|_, w| w.dma1en().enabled());}
+    else {rcc.ahb1enr.modify(|_, w| w.dma2en().enabled());}
+
+    free(|cs| DMA_TX_CALLBACKS.borrow(cs).borrow_mut()[index] = callback);
+    clear_dma_flags(self.core, true);
+    delay_us(direction_begin(self.core));
+
+    dma.st[stream].par.write(|w| unsafe {w.bits(dr_address(self.core))});
+    dma.st[stream].m0ar.write(|w| unsafe {w.bits(data.as_ptr() as u32)});
+    dma.st[stream].ndtr.write(|w| unsafe {w.bits(data.len() as u32)});
+    dma.st[stream].cr.write(|w| unsafe {
+      w.bits((UART_DMA_MAP.channels[index] << 25) | DMA_MINC | DMA_M2P | DMA_TCIE)
+    });
+    unsafe {NVIC::unmask(dma_interrupt(self.core, true));}
+
+    modify_cr3(self.core, CR3_DMAT, true);
+    dma.st[stream].cr.modify(|r, w| unsafe {w.bits(r.bits() | DMA_EN)});
+
+    return Ok(());
+  }
+
+  pub fn dma_tx_busy(&self) -> bool {
+    let (dma, stream) = dma_stream(self.core, true);
+    return dma.st[stream].cr.read().bits() & DMA_EN > 0;
+  }
+}
+
+
+// Async Functions ================================================================================
+#[cfg(feature = "async")]
+impl UART {
+  /// Async version of [UART::print] that yields while the transmit buffer is full, regardless of
+  /// the [TxPolicy].
+  pub async fn print_async(&self, data: &str) -> Result<(), SerialError> {
+    for byte in data.as_bytes() {
+      poll_fn(|cx| {
+        // Registered first, so room that is made in between still wakes the task
+        TX_WAKERS[(self.core - 1) as usize].register(cx.waker());
+
+        if enqueue_tx(self.core, byte.clone().into()) == true {return Poll::Ready(());}
+        else {return Poll::Pending;}
+      }).await;
+    }
+
+    return Ok(());
+  }
+
+  pub async fn println_async(&self, data: &str) -> Result<(), SerialError> {
+    if let Err(error) = self.print_async(data).await {return Err(error);}
+    if let Err(error) = self.print_async("\r\n").await {return Err(error);}
+
+    return Ok(());
+  }
+
+  /// Async version of [UART::read_byte] that waits for the receive interrupt instead of polling
+  /// the buffer.
+  pub async fn read_byte_async(&self) -> Option<u8> {
+    return poll_fn(|cx| {
+      // Registered first, so a byte that arrives in between still wakes the task
+      RX_WAKERS[(self.core - 1) as usize].register(cx.waker());
+
+      match self.try_read() {
+        Some(byte) => return Poll::Ready(Some(byte)),
+        None => return Poll::Pending
+      };
+    }).await;
+  }
+}
+  
+  
+// Public Functions ===============================================================================
+/// Queues a word in the transmit buffer of the port without blocking, e.g. from an interrupt or an
+/// RX hook where the [UART] itself is not available. Returns false if the buffer is full.
+pub fn queue_word(core: u8, data: u16) -> bool {
+  return enqueue_tx(core, data);
+}
+
+
+// Private Functions ==============================================================================
+fn set_baud(core: u8, baud: u32) -> Result<BaudRate, SerialError> {
+  let peripheral_ptr = stm_peripherals();
+
+  // USART1 and USART6 are on APB2, all others on APB1
+  let pclk = if core == 1 || core == 6 {pclk2()} else {pclk1()};
+
+  let rate = match calc_baud(pclk, baud, read_cr1(core) & (1 << 15) > 0) {
+    Ok(value) => value,
+    Err(error) => return Err(error)
+  };
+
+  match core {
+    1 => peripheral_ptr.USART1.brr.write(|w| unsafe {w.bits(rate.brr as u32)}),
+    2 => peripheral_ptr.USART2.brr.write(|w| unsafe {w.bits(rate.brr as u32)}),
+    3 => peripheral_ptr.USART3.brr.write(|w| unsafe {w.bits(rate.brr as u32)}),
+    4 => peripheral_ptr.UART4.brr.write(|w| unsafe {w.bits(rate.brr as u32)}),
+    5 => peripheral_ptr.UART5.brr.write(|w| unsafe {w.bits(rate.brr as u32)}),
+    6 => peripheral_ptr.USART6.brr.write(|w| unsafe {w.bits(rate.brr as u32)}),
+    _ => unreachable!()
+  };
+
+  return Ok(rate);
+}
+
+fn read_brr(core: u8) -> u32 {
+  let peripheral_ptr = stm_peripherals();
+
+  let bits = match core {
+    1 => peripheral_ptr.USART1.brr.read().bits(),
+    2 => peripheral_ptr.USART2.brr.read().bits(),
+    3 => peripheral_ptr.USART3.brr.read().bits(),
+    4 => peripheral_ptr.UART4.brr.read().bits(),
+    5 => peripheral_ptr.UART5.brr.read().bits(),
+    6 => peripheral_ptr.USART6.brr.read().bits(),
+    _ => unreachable!()
+  };
+
+  return bits & 0xFFFF;
+}
+
+fn read_sr(core: u8) -> u32 {
+  let peripheral_ptr = stm_peripherals();
+
+  let bits = match core {
+    1 => peripheral_ptr.USART1.sr.read().bits(),
+    2 => peripheral_ptr.USART2.sr.read().bits(),
+    3 => peripheral_ptr.USART3.sr.read().bits(),
+    4 => peripheral_ptr.UART4.sr.read().bits(),
+    5 => peripheral_ptr.UART5.sr.read().bits(),
+    6 => peripheral_ptr.USART6.sr.read().bits(),
+    _ => unreachable!()
+  };
+
+  return bits;
+}
 
-    return Some(buffer);
-  }
+fn read_cr1(core: u8) -> u32 {
+  let peripheral_ptr = stm_peripherals();
+
+  let bits = match core {
+    1 => peripheral_ptr.USART1.cr1.read().bits(),
+    2 => peripheral_ptr.USART2.cr1.read().bits(),
+    3 => peripheral_ptr.USART3.cr1.read().bits(),
+    4 => peripheral_ptr.UART4.cr1.read().bits(),
+    5 => peripheral_ptr.UART5.cr1.read().bits(),
+    6 => peripheral_ptr.USART6.cr1.read().bits(),
+    _ => unreachable!()
+  };
+
+  return bits;
 }
-  
-  
-// Private Functions ==============================================================================
-fn check_uart_errors(sr: u32) -> Result<(), SerialError> {
-  let bits = sr & 0xF;
 
-  if bits & 0x8 > 1 {return Err(SerialError::Overrun);}
-  else if bits & 0x4 > 1 {return Err(SerialError::Noise);}
-  else if bits & 0x2 > 1 {return Err(SerialError::FrameFormat);}
-  else if bits & 0x1 > 1 {return Err(SerialError::Parity);}
+fn read_cr2(core: u8) -> u32 {
+  let peripheral_ptr = stm_peripherals();
+
+  let bits = match core {
+    1 => peripheral_ptr.USART1.cr2.read().bits(),
+    2 => peripheral_ptr.USART2.cr2.read().bits(),
+    3 => peripheral_ptr.USART3.cr2.read().bits(),
+    4 => peripheral_ptr.UART4.cr2.read().bits(),
+    5 => peripheral_ptr.UART5.cr2.read().bits(),
+    6 => peripheral_ptr.USART6.cr2.read().bits(),
+    _ => unreachable!()
+  };
 
-  return Ok(());
+  return bits;
 }
 
-fn set_baud(core: u8, baud: u32) {
+fn read_cr3(core: u8) -> u32 {
+  let peripheral_ptr = stm_peripherals();
+
+  let bits = match core {
+    1 => peripheral_ptr.USART1.cr3.read().bits(),
+    2 => peripheral_ptr.USART2.cr3.read().bits(),
+    3 => peripheral_ptr.USART3.cr3.read().bits(),
+    4 => peripheral_ptr.UART4.cr3.read().bits(),
+    5 => peripheral_ptr.UART5.cr3.read().bits(),
+    6 => peripheral_ptr.USART6.cr3.read().bits(),
+    _ => unreachable!()
+  };
+
+  return bits;
+}
+
+// The flags can only be cleared by writing 0, writing 1 has no effect
+fn clear_sr(core: u8, mask: u32) {
+  let peripheral_ptr = stm_peripherals();
+
+  match core {
+    1 => peripheral_ptr.USART1.sr.write(|w| unsafe {w.bits(!mask)}),
+    2 => peripheral_ptr.USART2.sr.write(|w| unsafe {w.bits(!mask)}),
+    3 => peripheral_ptr.USART3.sr.write(|w| unsafe {w.bits(!mask)}),
+    4 => peripheral_ptr.UART4.sr.write(|w| unsafe {w.bits(!mask)}),
+    5 => peripheral_ptr.UART5.sr.write(|w| unsafe {w.bits(!mask)}),
+    6 => peripheral_ptr.USART6.sr.write(|w| unsafe {w.bits(!mask)}),
+    _ => unreachable!()
+  };
+}
+
+fn read_dr(core: u8) -> u16 {
+  let peripheral_ptr = stm_peripherals();
+
+  let data = match core {
+    1 => peripheral_ptr.USART1.dr.read().dr().bits(),
+    2 => peripheral_ptr.USART2.dr.read().dr().bits(),
+    3 => peripheral_ptr.USART3.dr.read().dr().bits(),
+    4 => peripheral_ptr.UART4.dr.read().dr().bits(),
+    5 => peripheral_ptr.UART5.dr.read().dr().bits(),
+    6 => peripheral_ptr.USART6.dr.read().dr().bits(),
+    _ => unreachable!()
+  };
+
+  return data;
+}
+
+fn modify_cr1(core: u8, mask: u32, enable: bool) {
+  let peripheral_ptr = stm_peripherals();
+
+  match core {
+    1 => peripheral_ptr.USART1.cr1.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    2 => peripheral_ptr.USART2.cr1.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    3 => peripheral_ptr.USART3.cr1.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    4 => peripheral_ptr.UART4.cr1.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    5 => peripheral_ptr.UART5.cr1.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    6 => peripheral_ptr.USART6.cr1.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    _ => unreachable!()
+  };
+}
+
+fn uart_interrupt(core: u8) -> Interrupt {
+  match core {
+    1 => return Interrupt::USART1,
+    2 => return Interrupt::USART2,
+    3 => return Interrupt::USART3,
+    4 => return Interrupt::UART4,
+    5 => return Interrupt::UART5,
+    6 => return Interrupt::USART6,
+    _ => unreachable!()
+  };
+}
+
+fn write_dr(core: u8, data: u16) {
+  let peripheral_ptr = stm_peripherals();
+
+  match core {
+    1 => peripheral_ptr.USART1.dr.write(|w| w.dr().bits(data)),
+    2 => peripheral_ptr.USART2.dr.write(|w| w.dr().bits(data)),
+    3 => peripheral_ptr.USART3.dr.write(|w| w.dr().bits(data)),
+    4 => peripheral_ptr.UART4.dr.write(|w| w.dr().bits(data)),
+    5 => peripheral_ptr.UART5.dr.write(|w| w.dr().bits(data)),
+    6 => peripheral_ptr.USART6.dr.write(|w| w.dr().bits(data)),
+    _ => unreachable!()
+  };
+}
+
+fn modify_cr2(core: u8, mask: u32, enable: bool) {
+  let peripheral_ptr = stm_peripherals();
+
+  match core {
+    1 => peripheral_ptr.USART1.cr2.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    2 => peripheral_ptr.USART2.cr2.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    3 => peripheral_ptr.USART3.cr2.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    4 => peripheral_ptr.UART4.cr2.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    5 => peripheral_ptr.UART5.cr2.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    6 => peripheral_ptr.USART6.cr2.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    _ => unreachable!()
+  };
+}
+
+fn modify_cr3(core: u8, mask: u32, enable: bool) {
   let peripheral_ptr = stm_peripherals();
-  
-  // (Mantisse, Fractal)
-  let uartdiv: (f64, f64) = modf(16000000.0 / (16.0 * baud as f64));
 
   match core {
+    1 => peripheral_ptr.USART1.cr3.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    2 => peripheral_ptr.USART2.cr3.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    3 => peripheral_ptr.USART3.cr3.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    4 => peripheral_ptr.UART4.cr3.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    5 => peripheral_ptr.UART5.cr3.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    6 => peripheral_ptr.USART6.cr3.modify(|r, w| unsafe {w.bits(if enable == true {r.bits() | mask} else {r.bits() & !mask})}),
+    _ => unreachable!()
+  };
+}
+
+// Returns the candidate that matches the edges of the measured byte.
+fn evaluate_edges(edges: &[u16], counter_freq: u32, candidates: &[u32]) -> Result<u32, SerialError> {
+  if edges.len() == 0 {return Err(SerialError::Prog(ProgError::TimedOut));}
+  if edges.len() < 2 {return Err(SerialError::FrameFormat);}
+
+  // Every pulse is a whole number of bits, averaging over all of them gives a finer resolution
+  let pulses = edges.windows(2).map(|i| i[1].wrapping_sub(i[0]) as u32);
+  let shortest = pulses.clone().min().unwrap().max(1);
+  let bits: u32 = pulses.clone().map(|i| (i + shortest / 2) / shortest).sum();
+  let ticks: u32 = pulses.sum::<u32>().max(1);
+  let measured = (counter_freq as u64 * bits as u64 / ticks as u64) as u32;
+
+  let baud = *candidates.iter().min_by_key(|&&i| (i as i64 - measured as i64).abs()).unwrap();
+  if (baud as i64 - measured as i64).abs() as u32 * 100 > baud * AUTOBAUD_TOLERANCE {
+    rprintln!("Measured {} baud, which is none of the candidates! | .detect_baud()", measured);
+    return Err(SerialError::FrameFormat);
+  }
+
+  return Ok(baud);
+}
+
+// Captures both edges on the channel with a free running 16-bit counter.
+fn capture_start(timer: u8, ccch: u8, psc: u16) {
+  let peripheral_ptr = stm_peripherals();
+  let rcc = &peripheral_ptr.RCC;
+
+  // CCxS = 01 -> input mapped on its own TI, CCxP and CCxNP -> both edges
+  let ccmr: u32 = 0b01 << (8 * ((ccch - 1) % 2));
+  let ccer: u32 = 0b1011 << (4 * (ccch - 1));
+
+  match timer {
     1 => {
-      let uart1 = &peripheral_ptr.USART1;
-      uart1.brr.modify(|_, w| {
-        w.div_mantissa().bits(uartdiv.1 as u16);
-        w.div_fraction().bits((uartdiv.0 * 16.0) as u8)
-      });
+      let tim1 = &peripheral_ptr.TIM1;
+      rcc.apb2enr.modify(|_, w| w.tim1en().enabled());
+      tim1.cr1.reset();
+      tim1.psc.write(|w| w.psc().bits(psc));
+      tim1.arr.write(|w| unsafe {w.bits(0xFFFF)});
+      tim1.egr.write(|w| w.ug().set_bit());
+      if ccch < 3 {tim1.ccmr1_input_mut().write(|w| unsafe {w.bits(ccmr)});}
+      else {tim1.ccmr2_input_mut().write(|w| unsafe {w.bits(ccmr)});}
+      tim1.ccer.write(|w| unsafe {w.bits(ccer)});
+      tim1.sr.write(|w| unsafe {w.bits(0)});
+      tim1.cr1.modify(|_, w| w.cen().enabled());
     },
     2 => {
-      let uart2 = &peripheral_ptr.USART2;
-      uart2.brr.modify(|_, w| {
-        w.div_mantissa().bits(uartdiv.1 as u16);
-        w.div_fraction().bits((uartdiv.0 * 16.0) as u8)
-      });
+      let tim2 = &peripheral_ptr.TIM2;
+      rcc.apb1enr.modify(|_, w| w.tim2en().enabled());
+      tim2.cr1.reset();
+      tim2.psc.write(|w| w.psc().bits(psc));
+      tim2.arr.write(|w| unsafe {w.bits(0xFFFF)});
+      tim2.egr.write(|w| w.ug().set_bit());
+      if ccch < 3 {tim2.ccmr1_input_mut().write(|w| unsafe {w.bits(ccmr)});}
+      else {tim2.ccmr2_input_mut().write(|w| unsafe {w.bits(ccmr)});}
+      tim2.ccer.write(|w| unsafe {w.bits(ccer)});
+      tim2.sr.write(|w| unsafe {w.bits(0)});
+      tim2.cr1.modify(|_, w| w.cen().enabled());
     },
     3 => {
-      let uart3 = &peripheral_ptr.USART3;
-      uart3.brr.modify(|_, w| {
-        w.div_mantissa().bits(uartdiv.1 as u16);
-        w.div_fraction().bits((uartdiv.0 * 16.0) as u8)
-      });
+      let tim3 = &peripheral_ptr.TIM3;
+      rcc.apb1enr.modify(|_, w| w.tim3en().enabled());
+      tim3.cr1.reset();
+      tim3.psc.write(|w| w.psc().bits(psc));
+      tim3.arr.write(|w| unsafe {w.bits(0xFFFF)});
+      tim3.egr.write(|w| w.ug().set_bit());
+      if ccch < 3 {tim3.ccmr1_input_mut().write(|w| unsafe {w.bits(ccmr)});}
+      else {tim3.ccmr2_input_mut().write(|w| unsafe {w.bits(ccmr)});}
+      tim3.ccer.write(|w| unsafe {w.bits(ccer)});
+      tim3.sr.write(|w| unsafe {w.bits(0)});
+      tim3.cr1.modify(|_, w| w.cen().enabled());
     },
     4 => {
-      let uart4 = &peripheral_ptr.UART4;
-      uart4.brr.modify(|_, w| {
-        w.div_mantissa().bits(uartdiv.1 as u16);
-        w.div_fraction().bits((uartdiv.0 * 16.0) as u8)
-      });
+      let tim4 = &peripheral_ptr.TIM4;
+      rcc.apb1enr.modify(|_, w| w.tim4en().enabled());
+      tim4.cr1.reset();
+      tim4.psc.write(|w| w.psc().bits(psc));
+      tim4.arr.write(|w| unsafe {w.bits(0xFFFF)});
+      tim4.egr.write(|w| w.ug().set_bit());
+      if ccch < 3 {tim4.ccmr1_input_mut().write(|w| unsafe {w.bits(ccmr)});}
+      else {tim4.ccmr2_input_mut().write(|w| unsafe {w.bits(ccmr)});}
+      tim4.ccer.write(|w| unsafe {w.bits(ccer)});
+      tim4.sr.write(|w| unsafe {w.bits(0)});
+      tim4.cr1.modify(|_, w| w.cen().enabled());
     },
-    5 => {
-      let uart5 = &peripheral_ptr.UART5;
-      uart5.brr.modify(|_, w| {
-        w.div_mantissa().bits(uartdiv.1 as u16);
-        w.div_fraction().bits((uartdiv.0 * 16.0) as u8)
-      });
+    _ => unreachable!()
+  };
+}
+
+// Reading the capture register clears the CCxIF flag
+fn capture_read(timer: u8, ccch: u8) -> Option<u16> {
+  let peripheral_ptr = stm_peripherals();
+
+  let sr = match timer {
+    1 => peripheral_ptr.TIM1.sr.read().bits(),
+    2 => peripheral_ptr.TIM2.sr.read().bits(),
+    3 => peripheral_ptr.TIM3.sr.read().bits(),
+    4 => peripheral_ptr.TIM4.sr.read().bits(),
+    _ => unreachable!()
+  };
+  if sr & (1 << ccch) == 0 {return None;}
+
+  let value = match (timer, ccch) {
+    (1, 1) => peripheral_ptr.TIM1.ccr1.read().bits(),
+    (1, 2) => peripheral_ptr.TIM1.ccr2.read().bits(),
+    (1, 3) => peripheral_ptr.TIM1.ccr3.read().bits(),
+    (1, 4) => peripheral_ptr.TIM1.ccr4.read().bits(),
+    (2, 1) => peripheral_ptr.TIM2.ccr1.read().bits(),
+    (2, 2) => peripheral_ptr.TIM2.ccr2.read().bits(),
+    (2, 3) => peripheral_ptr.TIM2.ccr3.read().bits(),
+    (2, 4) => peripheral_ptr.TIM2.ccr4.read().bits(),
+    (3, 1) => peripheral_ptr.TIM3.ccr1.read().bits(),
+    (3, 2) => peripheral_ptr.TIM3.ccr2.read().bits(),
+    (3, 3) => peripheral_ptr.TIM3.ccr3.read().bits(),
+    (3, 4) => peripheral_ptr.TIM3.ccr4.read().bits(),
+    (4, 1) => peripheral_ptr.TIM4.ccr1.read().bits(),
+    (4, 2) => peripheral_ptr.TIM4.ccr2.read().bits(),
+    (4, 3) => peripheral_ptr.TIM4.ccr3.read().bits(),
+    (4, 4) => peripheral_ptr.TIM4.ccr4.read().bits(),
+    _ => unreachable!()
+  };
+
+  return Some(value as u16);
+}
+
+fn capture_counter(timer: u8) -> u16 {
+  let peripheral_ptr = stm_peripherals();
+
+  let value = match timer {
+    1 => peripheral_ptr.TIM1.cnt.read().bits(),
+    2 => peripheral_ptr.TIM2.cnt.read().bits(),
+    3 => peripheral_ptr.TIM3.cnt.read().bits(),
+    4 => peripheral_ptr.TIM4.cnt.read().bits(),
+    _ => unreachable!()
+  };
+
+  return value as u16;
+}
+
+fn capture_stop(timer: u8) {
+  let peripheral_ptr = stm_peripherals();
+
+  match timer {
+    1 => {
+      peripheral_ptr.TIM1.cr1.reset();
+      peripheral_ptr.TIM1.ccer.reset();
     },
-    6 => {
-      let uart6 = &peripheral_ptr.USART6;
-      uart6.brr.modify(|_, w| {
-        w.div_mantissa().bits(uartdiv.1 as u16);
-        w.div_fraction().bits((uartdiv.0 * 16.0) as u8)
-      });
+    2 => {
+      peripheral_ptr.TIM2.cr1.reset();
+      peripheral_ptr.TIM2.ccer.reset();
+    },
+    3 => {
+      peripheral_ptr.TIM3.cr1.reset();
+      peripheral_ptr.TIM3.ccer.reset();
+    },
+    4 => {
+      peripheral_ptr.TIM4.cr1.reset();
+      peripheral_ptr.TIM4.ccer.reset();
     },
     _ => unreachable!()
-  }
+  };
+}
+
+// GTPR is not available on UART4 and UART5
+fn write_gtpr(core: u8, value: u16) {
+  let peripheral_ptr = stm_peripherals();
+
+  match core {
+    1 => peripheral_ptr.USART1.gtpr.write(|w| unsafe {w.bits(value as u32)}),
+    2 => peripheral_ptr.USART2.gtpr.write(|w| unsafe {w.bits(value as u32)}),
+    3 => peripheral_ptr.USART3.gtpr.write(|w| unsafe {w.bits(value as u32)}),
+    6 => peripheral_ptr.USART6.gtpr.write(|w| unsafe {w.bits(value as u32)}),
+    _ => unreachable!()
+  };
+}
+
+fn now() -> usize {
+  let peripheral_ptr = stm_peripherals();
+  let rcc = &peripheral_ptr.RCC;
+
+  if rcc.apb1enr.read().tim7en().is_disabled() == true {start_time();}
+  return millis();
+}
+
+// The queued data keeps direction_end from releasing the driver until the interrupt is enabled
+fn enqueue_tx(core: u8, data: u16) -> bool {
+  let pre_delay = free(|cs| {
+    if TX_BUFFERS.borrow(cs).borrow_mut()[(core - 1) as usize].enqueue(data).is_err() == true {return None;}
+    return Some(direction_begin(core));
+  });
+
+  match pre_delay {
+    Some(value) => delay_us(value),
+    None => return false
+  };
+  free(|_| modify_cr1(core, CR1_TXEIE, true));
+
+  return true;
+}
+
+fn dequeue_rx(core: u8) -> Option<u16> {
+  return free(|cs| {
+    let data = RX_BUFFERS.borrow(cs).borrow_mut()[(core - 1) as usize].dequeue();
+
+    // With RTS the interrupt pauses while the buffer is full and continues when there is room again
+    if read_cr3(core) & CR3_RTSE > 0 && read_cr1(core) & CR1_RXNEIE == 0 && dma_rx_active(cs, core) == false {
+      modify_cr1(core, CR1_RXNEIE, true);
+    }
+
+    return data;
+  });
+}
+
+// Returns the delay before the first byte may be sent, callers wait for it outside of critical
+// sections, so interrupts are not blocked during the turnaround
+fn direction_begin(core: u8) -> u32 {
+  return free(|cs| {
+    if let Some(direction) = DIRECTION.borrow(cs).borrow_mut()[(core - 1) as usize].as_mut() {
+      // A new transmission during the post delay keeps the driver enabled
+      if direction.transmitting == true {
+        direction.release = None;
+        return 0;
+      }
+
+      direction.transmitting = true;
+      direction.receiver = read_cr1(core) & CR1_RE > 0;
+      modify_cr1(core, CR1_RE, false);
+      if let Some(pin) = direction.pin {let _ = digital_write(pin, true);}
+      return direction.pre_delay;
+    }
+    return 0;
+  });
+}
+
+// Called after the TC flag, the transmission is only over if nothing new was queued in between.
+// The driver is released by release_drivers after the post delay.
+fn direction_end(core: u8) {
+  let start = micros();
+
+  free(|cs| {
+    if transmission_pending(cs, core) == true {return;}
+
+    if let Some(direction) = DIRECTION.borrow(cs).borrow_mut()[(core - 1) as usize].as_mut() {
+      if direction.transmitting == true {direction.release = Some(start);}
+    }
+  });
+
+  release_drivers();
 }
 
+// Releases the drivers whose post delay is over and arms TIM14 for the next one.
+fn release_drivers() {
+  let now = micros();
+
+  free(|cs| {
+    let mut next: Option<u32> = None;
 
-pub fn modf(x: f64) -> (f64, f64) {
-  let rv2: f64;
-  let mut u = x.to_bits();
-  let mask: u64;
-  let e = ((u >> 52 & 0x7ff) as i32) - 0x3ff;
+    for (index, slot) in DIRECTION.borrow(cs).borrow_mut().iter_mut().enumerate() {
+      let core = index as u8 + 1;
+      let direction = match slot.as_mut() {
+        Some(direction) => direction,
+        None => continue
+      };
+      let start = match direction.release {
+        Some(start) => start,
+        None => continue
+      };
 
-  // no fractional part
-  if e >= 52 {
-      rv2 = x;
-      if e == 0x400 && (u << 12) != 0 {
-          /* nan */
-          return (x, rv2);
+      let elapsed = now.wrapping_sub(start) as u32;
+      if elapsed < direction.post_delay {
+        let remaining = direction.post_delay - elapsed;
+        next = Some(next.map_or(remaining, |i| i.min(remaining)));
+        continue;
       }
-      u &= 1 << 63;
-      return (f64::from_bits(u), rv2);
+
+      direction.release = None;
+      if transmission_pending(cs, core) == true {continue;}
+
+      if let Some(pin) = direction.pin {let _ = digital_write(pin, false);}
+      modify_cr1(core, CR1_RE, direction.receiver);
+      direction.transmitting = false;
+    }
+
+    if let Some(us) = next {arm_release_timer(us);}
+  });
+}
+
+// Starts TIM14 with a 1MHz counter clock, its update interrupt calls release_drivers again.
+fn arm_release_timer(us: u32) {
+  let peripheral_ptr = stm_peripherals();
+  let rcc = &peripheral_ptr.RCC;
+  let tim14 = &peripheral_ptr.TIM14;
+
+  if rcc.apb1enr.read().tim14en().is_disabled() == true {
+    rcc.apb1enr.modify(|_, w| w.tim14en().enabled());
+    // Only an overflow raises the update interrupt, not the update event of the reload
+    tim14.cr1.modify(|_, w| w.urs().set_bit());
+    tim14.dier.modify(|_, w| w.uie().enabled());
+    unsafe {NVIC::unmask(Interrupt::TIM8_TRG_COM_TIM14);}
+  }
+
+  tim14.cr1.modify(|_, w| w.cen().disabled());
+  tim14.psc.write(|w| w.psc().bits((timer_clk1() / 1000000 - 1) as u16));
+  tim14.arr.write(|w| w.arr().bits(us.max(1).min(65535) as u16));
+  tim14.egr.write(|w| w.ug().set_bit());
+  tim14.cr1.modify(|_, w| w.cen().enabled());
+}
+
+fn transmission_pending(cs: &CriticalSection, core: u8) -> bool {
+  if TX_BUFFERS.borrow(cs).borrow()[(core - 1) as usize].len() > 0 {return true;}
+  return read_cr1(core) & CR1_TXEIE > 0 || read_cr3(core) & CR3_DMAT > 0;
+}
+
+// The USART interrupt cannot preempt a handler with the same or a higher priority.
+fn in_handler() -> bool {
+  return SCB::vect_active() != VectActive::ThreadMode;
+}
+
+// Does the work of the TXE interrupt for callers that cannot wait for it.
+fn transmit_polled(core: u8) {
+  while read_sr(core) & SR_TXE == 0 {}
+
+  free(|cs| {
+    if let Some(data) = TX_BUFFERS.borrow(cs).borrow_mut()[(core - 1) as usize].dequeue() {write_dr(core, data);}
+  });
+}
+
+fn delay_us(us: u32) {
+  if us > 0 {cortex_m::asm::delay(sysclk() / 1000000 * us);}
+}
+
+
+// Private DMA Functions ==========================================================================
+#[cfg(feature = "uart-dma")]
+fn dma_stream(core: u8, tx: bool) -> (&'static dma2::RegisterBlock, usize) {
+  let index = (core - 1) as usize;
+  let stream = if tx == true {UART_DMA_MAP.tx_streams[index]} else {UART_DMA_MAP.rx_streams[index]};
+
+  if UART_DMA_MAP.dmas[index] == 1 {return (unsafe {&*DMA1::ptr()}, stream);}
+  else {return (unsafe {&*DMA2::ptr()}, stream);}
+}
+
+#[cfg(feature = "uart-dma")]
+fn dma_interrupt(core: u8, tx: bool) -> Interrupt {
+  if tx == true {
+    match core {
+      1 => return Interrupt::DMA2_STREAM7,
+      2 => return Interrupt::DMA1_STREAM6,
+      3 => return Interrupt::DMA1_STREAM3,
+      4 => return Interrupt::DMA1_STREAM4,
+      5 => return Interrupt::DMA1_STREAM7,
+      6 => return Interrupt::DMA2_STREAM6,
+      _ => unreachable!()
+    };
+  }
+  else {
+    match core {
+      1 => return Interrupt::DMA2_STREAM2,
+      2 => return Interrupt::DMA1_STREAM5,
+      3 => return Interrupt::DMA1_STREAM1,
+      4 => return Interrupt::DMA1_STREAM2,
+      5 => return Interrupt::DMA1_STREAM0,
+      6 => return Interrupt::DMA2_STREAM1,
+      _ => unreachable!()
+    };
+  }
+}
+
+#[cfg(feature = "uart-dma")]
+fn dr_address(core: u8) -> u32 {
+  let peripheral_ptr = stm_peripherals();
+
+  match core {
+    1 => return &peripheral_ptr.USART1.dr as *const _ as u32,
+    2 => return &peripheral_ptr.USART2.dr as *const _ as u32,
+    3 => return &peripheral_ptr.USART3.dr as *const _ as u32,
+    4 => return &peripheral_ptr.UART4.dr as *const _ as u32,
+    5 => return &peripheral_ptr.UART5.dr as *const _ as u32,
+    6 => return &peripheral_ptr.USART6.dr as *const _ as u32,
+    _ => unreachable!()
+  };
+}
+
+#[cfg(feature = "uart-dma")]
+fn clear_dma_flags(core: u8, tx: bool) {
+  let (dma, stream) = dma_stream(core, tx);
+
+  // Every stream has 6 flag bits: streams 0-3 are in the low register and 4-7 in the high register
+  let offset = [0, 6, 16, 22][stream % 4];
+  if stream < 4 {dma.lifcr.write(|w| unsafe {w.bits(0x3D << offset)});}
+  else {dma.hifcr.write(|w| unsafe {w.bits(0x3D << offset)});}
+}
+
+#[cfg(feature = "uart-dma")]
+fn stop_dma(core: u8, tx: bool) {
+  let (dma, stream) = dma_stream(core, tx);
+
+  dma.st[stream].cr.modify(|r, w| unsafe {w.bits(r.bits() & !DMA_EN)});
+  while dma.st[stream].cr.read().bits() & DMA_EN > 0 {}
+  clear_dma_flags(core, tx);
+}
+
+#[cfg(feature = "uart-dma")]
+fn dma_rx_process(core: u8) {
+  // The new data lies between the last position and the current write position of the DMA
+  let frame = free(|cs| {
+    let mut dma_rx = DMA_RX.borrow(cs).borrow_mut();
+    let rx = match dma_rx[(core - 1) as usize].as_mut() {
+      Some(rx) => rx,
+      None => return None
+    };
+
+    let (dma, stream) = dma_stream(core, false);
+    let position = (rx.len - dma.st[stream].ndtr.read().bits() as usize) % rx.len;
+    let last = rx.position;
+    rx.position = position;
+
+    return Some((rx.clone(), last, position));
+  });
+
+  if let Some((rx, last, position)) = frame {
+    let buffer = unsafe {core::slice::from_raw_parts(rx.address as *const u8, rx.len)};
+
+    if position > last {(rx.callback)(&buffer[last..position]);}
+    else if position < last {
+      (rx.callback)(&buffer[last..]);
+      if position > 0 {(rx.callback)(&buffer[..position]);}
+    }
+  }
+}
+
+#[cfg(feature = "uart-dma")]
+fn dma_rx_handler(core: u8) {
+  clear_dma_flags(core, false);
+  dma_rx_process(core);
+}
+
+#[cfg(feature = "uart-dma")]
+fn dma_tx_handler(core: u8) {
+  clear_dma_flags(core, true);
+  modify_cr3(core, CR3_DMAT, false);
+
+  // The DMA is done when the last byte was written, the bus gets released after it was sent
+  if free(|cs| DIRECTION.borrow(cs).borrow()[(core - 1) as usize].is_some()) == true {modify_cr1(core, CR1_TCIE, true);}
+
+  if let Some(callback) = free(|cs| DMA_TX_CALLBACKS.borrow(cs).borrow_mut()[(core - 1) as usize].take()) {callback();}
+}
+
+#[cfg(feature = "uart-dma")]
+fn dma_rx_active(cs: &CriticalSection, core: u8) -> bool {
+  return DMA_RX.borrow(cs).borrow()[(core - 1) as usize].is_some();
+}
+
+#[cfg(not(feature = "uart-dma"))]
+fn dma_rx_active(_cs: &CriticalSection, _core: u8) -> bool {
+  return false;
+}
+
+
+// Interrupts =====================================================================================
+fn uart_handler(core: u8) {
+  let sr = read_sr(core);
+  let cr1 = read_cr1(core);
+
+  #[cfg(feature = "uart-dma")]
+  if cr1 & CR1_IDLEIE > 0 && sr & SR_IDLE > 0 {
+    // Reading DR after SR clears the idle flag, the data itself was already moved by the DMA
+    read_dr(core);
+    dma_rx_process(core);
+  }
+
+  if sr & SR_LBD > 0 && read_cr2(core) & CR2_LBDIE > 0 {
+    clear_sr(core, SR_LBD);
+    free(|cs| RX_BUFFERS.borrow(cs).borrow_mut()[(core - 1) as usize] = Queue::new());
+    LIN_BREAKS.fetch_or(1 << (core - 1), Ordering::SeqCst);
+  }
+
+  // With RTS the data stays in DR while the buffer is full, which makes the sender pause
+  if cr1 & CR1_RXNEIE > 0 && read_cr3(core) & CR3_RTSE > 0 && free(|cs| RX_BUFFERS.borrow(cs).borrow()[(core - 1) as usize].is_full()) == true {
+    modify_cr1(core, CR1_RXNEIE, false);
+  }
+  else if cr1 & CR1_RXNEIE > 0 && sr & (SR_RXNE | SR_ORE) > 0 {
+    // Reading DR after SR also clears the error flags
+    let data = read_dr(core);
+
+    // In LIN mode a break is received as zero with a framing error, it is handled above
+    let lin_break = sr & SR_FE > 0 && data == 0 && read_cr2(core) & CR2_LINEN > 0;
+
+    if lin_break == false {
+      let consumed = match free(|cs| RX_HOOKS.borrow(cs).borrow()[(core - 1) as usize]) {
+        Some(hook) => hook(data),
+        None => false
+      };
+
+      free(|cs| {
+        let mut data = data;
+        let mut gaps = RX_GAPS.borrow(cs).borrow_mut();
+        let (gap, last) = &mut gaps[(core - 1) as usize];
+
+        if *gap > 0 {
+          let time = micros();
+          if time.wrapping_sub(*last) >= *gap {data |= RX_GAP;}
+          *last = time;
+        }
+
+        let mut stats = RX_STATS.borrow(cs).borrow_mut();
+        let stats = &mut stats[(core - 1) as usize];
+
+        if sr & SR_ORE > 0 {stats.hardware_overruns += 1;}
+        if sr & SR_ERRORS > 0 {stats.line_errors += 1;}
+        if consumed == false && RX_BUFFERS.borrow(cs).borrow_mut()[(core - 1) as usize].enqueue(data).is_err() == true {
+          stats.buffer_overruns += 1;
+        }
+      });
+    }
+
+    #[cfg(feature = "async")]
+    RX_WAKERS[(core - 1) as usize].wake();
   }
 
-  // no integral part
-  if e < 0 {
-      u &= 1 << 63;
-      rv2 = f64::from_bits(u);
-      return (x, rv2);
+  if cr1 & CR1_TXEIE > 0 && sr & SR_TXE > 0 {
+    match free(|cs| TX_BUFFERS.borrow(cs).borrow_mut()[(core - 1) as usize].dequeue()) {
+      Some(data) => write_dr(core, data),
+      None => {
+        modify_cr1(core, CR1_TXEIE, false);
+        if free(|cs| DIRECTION.borrow(cs).borrow()[(core - 1) as usize].is_some()) == true {modify_cr1(core, CR1_TCIE, true);}
+      }
+    };
+
+    #[cfg(feature = "async")]
+    TX_WAKERS[(core - 1) as usize].wake();
   }
 
-  mask = ((!0) >> 12) >> e;
-  if (u & mask) == 0 {
-      rv2 = x;
-      u &= 1 << 63;
-      return (f64::from_bits(u), rv2);
+  if cr1 & CR1_TCIE > 0 && sr & SR_TC > 0 {
+    modify_cr1(core, CR1_TCIE, false);
+    direction_end(core);
   }
-  u &= !mask;
-  rv2 = f64::from_bits(u);
-  return (x - rv2, rv2);
 }
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn USART1() {
+  uart_handler(1);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn USART2() {
+  uart_handler(2);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn USART3() {
+  uart_handler(3);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn UART4() {
+  uart_handler(4);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn UART5() {
+  uart_handler(5);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn USART6() {
+  uart_handler(6);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn TIM8_TRG_COM_TIM14() {
+  let peripheral_ptr = stm_peripherals();
+  let tim14 = &peripheral_ptr.TIM14;
+
+  tim14.cr1.modify(|_, w| w.cen().disabled());
+  tim14.sr.modify(|_, w| w.uif().clear_bit());
+  release_drivers();
+}
+
+#[cfg(feature = "uart-dma")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn DMA1_STREAM0() {
+  dma_rx_handler(5);
+}
+
+#[cfg(feature = "uart-dma")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn DMA1_STREAM1() {
+  dma_rx_handler(3);
+}
+
+#[cfg(feature = "uart-dma")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn DMA1_STREAM2() {
+  dma_rx_handler(4);
+}
+
+#[cfg(feature = "uart-dma")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn DMA1_STREAM3() {
+  dma_tx_handler(3);
+}
+
+#[cfg(feature = "uart-dma")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn DMA1_STREAM4() {
+  dma_tx_handler(4);
+}
+
+#[cfg(feature = "uart-dma")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn DMA1_STREAM5() {
+  dma_rx_handler(2);
+}
+
+#[cfg(feature = "uart-dma")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn DMA1_STREAM6() {
+  dma_tx_handler(2);
+}
+
+#[cfg(feature = "uart-dma")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn DMA1_STREAM7() {
+  dma_tx_handler(5);
+}
+
+#[cfg(feature = "uart-dma")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn DMA2_STREAM1() {
+  dma_rx_handler(6);
+}
+
+#[cfg(feature = "uart-dma")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn DMA2_STREAM2() {
+  dma_rx_handler(1);
+}
+
+#[cfg(feature = "uart-dma")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn DMA2_STREAM6() {
+  dma_tx_handler(6);
+}
+
+#[cfg(feature = "uart-dma")]
+#[allow(non_snake_case)]
+#[interrupt]
+fn DMA2_STREAM7() {
+  dma_tx_handler(1);
+}
+
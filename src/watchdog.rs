@@ -0,0 +1,222 @@
+//! This module contains everything that is related to the watchdog timers and the reset source.
+
+use crate::include::{stm_peripherals, ProgError};
+use crate::clocks::pclk1;
+use stm32f4::stm32f446::{NVIC, Interrupt, interrupt};
+use cortex_m::interrupt::{Mutex, free};
+use core::cell::RefCell;
+use rtt_target::rprintln;
+
+static EARLY_WAKEUP_CALLBACK: Mutex<RefCell<Option<fn()>>> = Mutex::new(RefCell::new(None));
+
+// Nominal LSI frequency, the real value can be between 17kHz and 47kHz.
+const LSI_FREQ: u32 = 32000;
+
+/// Represents the source of the last reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetCause {
+  LowPower,
+  WindowWatchdog,
+  IndependentWatchdog,
+  Software,
+  PowerOn,
+  Brownout,
+  Pin,
+  Unknown
+}
+
+/// Independent watchdog, clocked from the LSI. Once started it cannot be stopped until the next reset.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustuino::*;
+/// use rustuino::watchdog::IWDG;
+///
+/// let watchdog = IWDG::start(1000).unwrap();
+///
+/// loop {
+///   // Do something that takes less than one second
+///   watchdog.feed();
+/// }
+/// ```
+pub struct IWDG {
+  timeout: u32
+}
+
+/// Window watchdog, clocked from APB1. It resets the MC if it is fed too late or too early.
+pub struct WWDG {
+  counter: u8
+}
+
+impl IWDG {
+  /// Starts the watchdog with a timeout between 1ms and 32768ms.
+  pub fn start(timeout_ms: u32) -> Result<Self, ProgError> {
+    let peripheral_ptr = stm_peripherals();
+    let iwdg = &peripheral_ptr.IWDG;
+
+    // t = (4 * 2^PR) * (RLR + 1) / LSI_FREQ
+    let ticks = match timeout_ms.checked_mul(LSI_FREQ / 1000) {
+      Some(value) if value > 0 => value,
+      _ => {
+        rprintln!("Watchdog timeout outside of bounds! | IWDG::start()");
+        return Err(ProgError::InvalidConfiguration);
+      }
+    };
+
+    let mut prescaler: u32 = 0;
+    let mut reload = ticks / 4;
+    while reload > 4096 && prescaler < 6 {
+      prescaler += 1;
+      reload = ticks / (4 << prescaler);
+    }
+
+    if reload == 0 || reload > 4096 {
+      rprintln!("Watchdog timeout outside of bounds! | IWDG::start()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+
+    iwdg.kr.write(|w| unsafe {w.bits(0xCCCC)});
+    iwdg.kr.write(|w| unsafe {w.bits(0x5555)});
+    iwdg.pr.write(|w| unsafe {w.bits(prescaler)});
+    iwdg.rlr.write(|w| unsafe {w.bits(reload - 1)});
+    while iwdg.sr.read().bits() != 0 {}
+    iwdg.kr.write(|w| unsafe {w.bits(0xAAAA)});
+
+    return Ok(Self {
+      timeout: timeout_ms
+    });
+  }
+
+  pub fn feed(&self) {
+    let peripheral_ptr = stm_peripherals();
+    let iwdg = &peripheral_ptr.IWDG;
+
+    iwdg.kr.write(|w| unsafe {w.bits(0xAAAA)});
+  }
+
+  pub fn timeout(&self) -> u32 {
+    return self.timeout;
+  }
+}
+
+impl WWDG {
+  /// Starts the window watchdog. The MC is reset if it is not fed within `timeout_ms` or if it is
+  /// fed earlier than `window_ms` after the last feed. The timeout is at most 2^21 PCLK1 cycles,
+  /// e.g. 131ms with a PCLK1 of 16MHz. The optional callback runs in the early wakeup interrupt,
+  /// one watchdog tick before the reset.
+  pub fn start(timeout_ms: u32, window_ms: u32, early_wakeup: Option<fn()>) -> Result<Self, ProgError> {
+    let peripheral_ptr = stm_peripherals();
+    let rcc = &peripheral_ptr.RCC;
+    let wwdg = &peripheral_ptr.WWDG;
+
+    if rcc.apb1enr.read().wwdgen().is_enabled() == true {
+      rprintln!("Window watchdog is already running! | WWDG::start()");
+      return Err(ProgError::AlreadyConfigured);
+    }
+
+    // tick = 4096 * 2^WDGTB / PCLK1 in us, the longest timeout is 64 ticks with WDGTB = 3
+    let pclk = pclk1();
+    let max_ms = ((64 * (4096u64 << 3)) * 1000 / pclk as u64) as u32;
+    if timeout_ms == 0 || timeout_ms > max_ms {
+      rprintln!("Watchdog timeout outside of bounds! | WWDG::start()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+    if window_ms >= timeout_ms {
+      rprintln!("Watchdog window has to be shorter than the timeout! | WWDG::start()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+
+    let mut prescaler: u32 = 0;
+    let mut tick_us = (4096u64 * 1000000 / pclk as u64) as u32;
+    while (timeout_ms * 1000) / tick_us > 64 && prescaler < 3 {
+      prescaler += 1;
+      tick_us = ((4096u64 << prescaler) * 1000000 / pclk as u64) as u32;
+    }
+
+    let ticks = (timeout_ms * 1000) / tick_us;
+    let window_ticks = (window_ms * 1000 + tick_us - 1) / tick_us;
+
+    if ticks == 0 || ticks > 64 {
+      rprintln!("Watchdog timeout outside of bounds! | WWDG::start()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+    if window_ticks >= ticks {
+      rprintln!("Watchdog window has to be shorter than the timeout! | WWDG::start()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+
+    // The reset happens when the counter drops from 0x40 to 0x3F
+    let counter = (0x3F + ticks) as u8;
+    let window = counter as u32 - window_ticks;
+
+    rcc.apb1enr.modify(|_, w| w.wwdgen().enabled());
+    wwdg.cfr.write(|w| unsafe {w.bits((prescaler << 7) | window)});
+
+    if let Some(callback) = early_wakeup {
+      free(|cs| EARLY_WAKEUP_CALLBACK.borrow(cs).replace(Some(callback)));
+      wwdg.sr.write(|w| unsafe {w.bits(0)});
+      wwdg.cfr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 9))});
+      unsafe {NVIC::unmask(Interrupt::WWDG);}
+    }
+
+    wwdg.cr.write(|w| unsafe {w.bits((1 << 7) | counter as u32)});
+
+    return Ok(Self {
+      counter
+    });
+  }
+
+  pub fn feed(&self) {
+    let peripheral_ptr = stm_peripherals();
+    let wwdg = &peripheral_ptr.WWDG;
+
+    wwdg.cr.write(|w| unsafe {w.bits((1 << 7) | self.counter as u32)});
+  }
+}
+
+
+// Public Functions ===============================================================================
+/// Returns the source of the last reset and clears the reset flags, so it should only be called
+/// once after startup.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use rustuino::*;
+/// use rustuino::watchdog::{reset_cause, ResetCause};
+///
+/// if reset_cause() == ResetCause::IndependentWatchdog {
+///   rprintln!("Recovered from a hang!");
+/// }
+/// ```
+pub fn reset_cause() -> ResetCause {
+  let peripheral_ptr = stm_peripherals();
+  let rcc = &peripheral_ptr.RCC;
+
+  let csr = rcc.csr.read().bits();
+  rcc.csr.modify(|_, w| w.rmvf().set_bit());
+
+  // A power-on reset also sets the pin and brownout flags, so the order of the checks matters.
+  if csr & (1 << 31) > 0 {return ResetCause::LowPower;}
+  else if csr & (1 << 30) > 0 {return ResetCause::WindowWatchdog;}
+  else if csr & (1 << 29) > 0 {return ResetCause::IndependentWatchdog;}
+  else if csr & (1 << 28) > 0 {return ResetCause::Software;}
+  else if csr & (1 << 27) > 0 {return ResetCause::PowerOn;}
+  else if csr & (1 << 25) > 0 {return ResetCause::Brownout;}
+  else if csr & (1 << 26) > 0 {return ResetCause::Pin;}
+  else {return ResetCause::Unknown;}
+}
+
+
+// Interrupts =====================================================================================
+#[allow(non_snake_case)]
+#[interrupt]
+fn WWDG() {
+  let peripheral_ptr = stm_peripherals();
+  let wwdg = &peripheral_ptr.WWDG;
+
+  wwdg.sr.write(|w| unsafe {w.bits(0)});
+
+  if let Some(callback) = free(|cs| *EARLY_WAKEUP_CALLBACK.borrow(cs).borrow()) {callback();}
+}